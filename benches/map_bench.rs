@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use eq_maps::{
+    map_draw::{map_render_png_bytes, map_svg, RenderOptions},
+    map_fixture::synthetic_map,
+    map_items::MapItems,
+};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in SIZES {
+        let map = synthetic_map(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &map, |b, map| {
+            b.iter(|| map.parse::<MapItems>().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn rendering(c: &mut Criterion) {
+    let options = RenderOptions {
+        scale: 0.25,
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("svg");
+    for size in SIZES {
+        let map_items: MapItems = synthetic_map(size).parse().unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &map_items,
+            |b, map_items| b.iter(|| map_svg(map_items, &options)),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("png");
+    group.sample_size(10);
+    for size in SIZES {
+        let map_items: MapItems = synthetic_map(size).parse().unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &map_items,
+            |b, map_items| b.iter(|| map_render_png_bytes(map_items, &options).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing, rendering);
+criterion_main!(benches);
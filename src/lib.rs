@@ -1,6 +1,19 @@
-#![feature(assert_matches)]
-#![feature(let_else)]
-#![feature(once_cell)]
+//! Parsing and rendering for EverQuest map files.
+//!
+//! Parsing and analysis have no optional dependencies. Rendering
+//! (`map_draw`) lives behind the `render` feature and the async file loaders
+//! behind `async-io`; both are enabled by the default `cli` feature.
 
+pub mod map_bounds;
+pub mod map_check;
+pub mod map_cluster;
+pub mod map_colors;
+pub mod map_convert;
+pub mod map_diff;
+pub mod map_directives;
+#[cfg(feature = "render")]
+pub mod map_draw;
+pub mod map_filter;
+pub mod map_fixture;
 pub mod map_items;
-pub mod map_draw;
\ No newline at end of file
+pub mod map_simplify;
@@ -1,25 +1,2390 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::OsString,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::{Instant, SystemTime},
+};
 
+use anyhow::{anyhow, Context};
 use clap::*;
-use eq_maps::{map_items::MapItems, map_draw::map_draw};
+use eq_maps::{
+    map_bounds::{BoundingBox, Circle},
+    map_check::{check_file_by_entry, MapExtent, MapStats},
+    map_colors::{color_path, Colormap, SeededColors},
+    map_convert::{check_output_path, CoordinateOrder, MapFormat},
+    map_draw::{
+        chosen_scale, map_draw_by_color, map_draw_png, map_draw_scales, map_draw_tile,
+        map_draw_webp, map_draw_z_animation, map_html, map_meta, map_render_to_writer,
+        map_render_webp_to_writer, map_svg, map_tiles, map_view_box_with, millimetres_to_pixels,
+        scaled_path, Corner, DrawOrder, Fit, FocusRegion, FrameSize, Inset, LabelHalo, Orientation,
+        PageSize, Plane, RenderOptions, Stamp, SvgFormatting, SvgMetadata, SvgSize, TileId,
+        ZBandMode, MAX_TILE_ZOOM, TILE_SIZE,
+    },
+    map_items::{
+        check_stdin_once, Color, InputEncoding, InputFormat, LoadOptions, MapItems,
+        ZONE_LINK_PREFIX,
+    },
+    map_simplify::RoundMode,
+};
+use log::LevelFilter;
+use regex::Regex;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
-struct Args {
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+
+    /// Suppress all output except errors
+    #[clap(short, long, global = true)]
+    quiet: bool,
+
+    /// Also log details such as each input file's extent
+    #[clap(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+}
+
+// Only one Command is ever built, so the size of RenderArgs doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Draw map files to a PNG, WebP or HTML file, or an animated GIF. Runs
+    /// when no subcommand is given
+    Render(RenderArgs),
+    /// Convert map files to .map, .csv, .geojson, .svg or a waypoint list,
+    /// picked by the output file's extension or --format, without rendering.
+    /// Inputs may be .map, .txt, .zip or .csv files, and map files may be
+    /// gzip-compressed
+    Convert(ConvertArgs),
+    /// Print the items in, the extent of, or the entries of map files
+    Info(InfoArgs),
+    /// Check that map files parse cleanly and report each bad line. Each
+    /// entry of a zip archive is checked on its own
+    Validate(ValidateArgs),
+}
+
+/// The subcommand assumed when the arguments don't start with one, so that
+/// `eq-maps out.png a.map` keeps working.
+const DEFAULT_SUBCOMMAND: &str = "render";
+
+/// How the input files are read.
+#[derive(Args, Debug)]
+struct LoadArgs {
+    /// Tint each input file's items with a distinct palette color
+    #[clap(long)]
+    colorize_by_file: bool,
+
+    /// The line format of the input files: eq, legacy (bare commas, optional
+    /// point size), or auto to accept either
+    #[clap(long, value_parser, default_value = "eq")]
+    input_format: InputFormat,
+
+    /// The text encoding of the input files, such as utf-8 or windows-1252
+    #[clap(long, value_parser, default_value = "utf-8")]
+    input_encoding: InputEncoding,
+
+    /// Fail instead of loading more than this many items in total. Unlimited
+    /// by default
+    #[clap(long)]
+    max_items: Option<usize>,
+
+    /// Fail on the first line that isn't a map item, naming its line number,
+    /// instead of skipping it. Lines with more fields than are read fail too.
+    /// Blank lines and # comments are still skipped
+    #[clap(long)]
+    strict: bool,
+
+    /// Log and skip input files that fail to load instead of stopping at the
+    /// first one. The run still fails at the end if any file was skipped
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Parse up to this many input files at once, on separate threads. The
+    /// output is the same for any number of jobs: files are always combined
+    /// in the order they were given, only the loading is faster
+    #[clap(long, short = 'j', default_value = "1")]
+    jobs: usize,
+}
+
+/// The changes made to the loaded items before they are used, in the order
+/// they are applied.
+#[derive(Args, Debug)]
+#[clap(group(ArgGroup::new("region").args(&["crop", "center"])))]
+struct ProcessArgs {
+    /// Convert the coordinates between the map file convention and the one
+    /// /loc uses in game, before any other processing. /loc prints Y, X, Z
+    /// with X and Y negated, so "a, b, c" becomes the map file point -b, -a,
+    /// c. The conversion works in both directions
+    #[clap(long)]
+    loc_convention: bool,
+
+    /// Only keep items at or above this Z, to draw a single floor. Lines are
+    /// kept only when both ends are in range, so lines that straddle the
+    /// edge are dropped
+    #[clap(long, value_name = "Z", allow_hyphen_values = true)]
+    z_min: Option<f32>,
+
+    /// Only keep items at or below this Z. Lines follow the same rule as
+    /// --z-min
+    #[clap(long, value_name = "Z", allow_hyphen_values = true)]
+    z_max: Option<f32>,
+
+    /// Set every Z to 0 as soon as the files are loaded and --z-min and
+    /// --z-max have picked their floor, before any other processing, so
+    /// later steps can't tell items apart by height
+    #[clap(long)]
+    flatten_z: bool,
+
+    /// Drop the points and lines that repeat an earlier one, as when several
+    /// files for one zone are loaded together: the same color and label, or
+    /// color and width, at coordinates within 0.001 of each other, with a
+    /// line from A to B repeating one from B to A. Applied after --flatten-z
+    #[clap(long)]
+    dedup: bool,
+
+    /// Move every X and Y onto a grid of this many map units, to line the
+    /// map up with a tile grid
+    #[clap(long, value_name = "CELL_SIZE")]
+    snap: Option<f32>,
+
+    /// How --snap rounds onto the grid: nearest, floor to the cell's corner,
+    /// or ceil
+    #[clap(long, value_parser, requires = "snap")]
+    snap_mode: Option<RoundMode>,
+
+    /// Merge consecutive lines of one color that continue each other in a
+    /// straight line, which draws the same map with fewer items
+    #[clap(long)]
+    merge_colinear: bool,
+
+    /// Warn about points that share the exact same coordinates
+    #[clap(long)]
+    warn_stacked_points: bool,
+
+    /// Check for lines whose ends are more than this far apart in Z, which
+    /// are usually walls joined across floors by mistake
+    #[clap(long, value_name = "Z")]
+    max_line_z_delta: Option<f32>,
+
+    /// What to do with the lines --max-line-z-delta finds: warn about each,
+    /// or drop them
+    #[clap(
+        long,
+        value_parser,
+        default_value = "warn",
+        requires = "max-line-z-delta"
+    )]
+    steep_lines: SteepLines,
+
+    /// Warn about line ends no more than this far apart that don't quite
+    /// meet, with where they are, for finding gaps in walls meant to join
+    #[clap(long, value_name = "TOLERANCE")]
+    check_coincident_endpoints: Option<f32>,
+
+    /// Move line ends no more than this far apart onto the one that comes
+    /// first in the files, closing the gaps --check-coincident-endpoints
+    /// finds. Points don't move
+    #[clap(long, value_name = "TOLERANCE")]
+    snap_endpoints: Option<f32>,
+
+    /// Give colors within this RGB distance of one another the first of
+    /// them, in file order, so slightly-off duplicates such as 254,0,0 and
+    /// 255,0,0 group together. The distance is Euclidean over the red, green
+    /// and blue values; the default 0 only groups exact matches
+    #[clap(long, default_value_t = 0.0)]
+    color_tolerance: f32,
+
+    /// Recolor with distinct colors derived from each point's label with
+    /// --seeded-colors label, or from each item's position with
+    /// --seeded-colors item. The same --seed always gives the same colors
+    #[clap(long, value_parser)]
+    seeded_colors: Option<SeededColors>,
+
+    /// The seed for --seeded-colors
+    #[clap(long, default_value_t = 0, requires = "seeded-colors")]
+    seed: u64,
+
+    /// Only keep points whose label matches this regex
+    #[clap(long, value_parser)]
+    labels: Option<Regex>,
+
+    /// Only keep points whose label is one of the lines of this file. Blank
+    /// lines are ignored and surrounding whitespace is trimmed. Points must
+    /// also match --labels if both are given, and --exclude-labels still
+    /// applies. Lines are always kept
+    #[clap(long)]
+    include_file: Option<String>,
+
+    /// Drop points whose label matches this regex, after --labels is applied
+    #[clap(long, value_parser)]
+    exclude_labels: Option<Regex>,
+
+    /// Draw lines joining points that share a label, in the order the points
+    /// appear in the files
+    #[clap(long)]
+    connect_by_label: bool,
+
+    /// Keep only every Nth point and every Nth line, for a quick but lossy
+    /// preview of a huge map
+    #[clap(long)]
+    sample_every: Option<usize>,
+
+    /// Keep only the first N items, in file order, before any other
+    /// processing, for an instant preview while working on styles. Unlike
+    /// --sample-every this drops whole parts of the map, and the drawn
+    /// extent shrinks to fit what is left
+    #[clap(long)]
+    limit: Option<usize>,
+
+    /// Only keep items within MIN_X,MIN_Y,MAX_X,MAX_Y. Lines crossing the
+    /// edge are kept whole unless --clip-lines is given
+    #[clap(long, value_parser)]
+    crop: Option<BoundingBox>,
+
+    /// Only keep items within --radius of X,Y, and in the xy plane draw the
+    /// square around that circle. Lines passing through the circle are kept
+    /// whole unless --clip-lines is given
+    #[clap(long, value_parser = parse_center, requires = "radius")]
+    center: Option<(f32, f32)>,
+
+    /// The radius of the --center circle, in map units
+    #[clap(long, requires = "center")]
+    radius: Option<f32>,
+
+    /// With --crop or --center, trim lines at the edge of the region
+    #[clap(long, requires = "region")]
+    clip_lines: bool,
+}
+
+#[derive(Args, Debug)]
+struct RenderArgs {
+    /// The file to write, or - to write to standard output for piping into
+    /// other tools
     #[clap()]
     out: String,
-    
+
+    /// The map files to read, or - to read one from standard input
     #[clap()]
     files: Vec<String>,
+
+    /// What to do when an output file already exists: always replace it,
+    /// never replace it, or prompt to ask first. Prompting fails when not
+    /// run from a terminal
+    #[clap(long, value_parser, default_value = "always")]
+    overwrite: Overwrite,
+
+    /// What to write: png, webp with --lossless, or html for a page that
+    /// shows the SVG with pan and zoom. AVIF isn't supported by this build
+    #[clap(long, value_parser, default_value = "png")]
+    format: OutputFormat,
+
+    /// Encode the image without losing any detail, so thin lines stay
+    /// crisp. This build writes WebP only this way, and PNG always is
+    #[clap(long)]
+    lossless: bool,
+
+    /// Also write a JSON file here with the map's view box, as min_x, min_y,
+    /// width and height in map units, and its item, point and line counts,
+    /// for pages that overlay the image. The view box is the map's own,
+    /// before --size or --page letterboxes it
+    #[clap(long, value_name = "PATH")]
+    meta: Option<PathBuf>,
+
+    #[clap(flatten)]
+    load: LoadArgs,
+
+    #[clap(flatten)]
+    process: ProcessArgs,
+
+    /// Read rendering settings from this TOML file, with RenderOptions field
+    /// names as keys, such as font_size = 8 or background = "#202020".
+    /// Settings come from the defaults, then this file, then --preset, then
+    /// the other flags, each overriding the ones before
+    #[clap(long)]
+    config: Option<String>,
+
+    /// Draw the difference from this baseline map file instead: items both
+    /// share in gray, items only in the baseline in red and items only in
+    /// the input files in green. Both sides are processed the same way
+    #[clap(long, value_name = "FILE")]
+    relative_to: Option<String>,
+
+    /// Start from a bundle of rendering settings: web, print, thumbnail, or
+    /// editor. Flags given alongside it take precedence
+    #[clap(long, value_parser)]
+    preset: Option<Preset>,
+
+    /// Font family used for labels. Defaults to the bundled DejaVu Sans
+    #[clap(long)]
+    font: Option<String>,
+
+    /// Font size used for labels, in map units. Defaults to 10
+    #[clap(long)]
+    font_size: Option<f32>,
+
+    /// Leave out point labels when drawing at less than this many pixels per
+    /// map unit, as set by --scale, --dpi, --preset or --scales. Labels are always
+    /// drawn by default
+    #[clap(long)]
+    label_min_scale: Option<f32>,
+
+    /// Draw the underscores in point labels as they are. By default they are
+    /// drawn as spaces, as the game shows them
+    #[clap(long)]
+    keep_label_underscores: bool,
+
+    /// Draw at most this many point labels: zone links first, by
+    /// --style-zone-links or the to_ prefix, then the rest in file order.
+    /// Every marker is still drawn
+    #[clap(long)]
+    max_label_count: Option<usize>,
+
+    /// Scale each point's circle by the number at the end of its label, the
+    /// text after its last underscore or space, so the circle's area is
+    /// proportional to it: spawn_rare_4 is drawn twice as wide as usual.
+    /// Points without a positive number there keep the usual size
+    #[clap(long)]
+    size_by_label_number: bool,
+
+    /// Size each point's circle by its point type, the number before its
+    /// label: 1 small, 2 as usual and 3 large, as the game draws them. Other
+    /// types keep the usual size
+    #[clap(long)]
+    size_by_point_type: bool,
+
+    /// Draw labels in black or white, whichever contrasts with the marker
+    #[clap(long)]
+    contrast_labels: bool,
+
+    /// Draw a thick stroke in this color under each label, so labels stay
+    /// readable over dense lines: #rrggbb, r,g,b, or contrast for black or
+    /// white, whichever contrasts with the label. --label-halo alone uses
+    /// contrast
+    #[clap(
+        long,
+        value_name = "COLOR",
+        value_parser,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "contrast"
+    )]
+    label_halo: Option<LabelHalo>,
+
+    /// Fill the background with this color, given as #rrggbb, r,g,b or
+    /// rgb(r,g,b), or none or transparent to leave it clear even under a
+    /// preset's background. Transparent by default
+    #[clap(long, value_parser)]
+    background: Option<Background>,
+
+    /// Draw the map over this PNG, such as a zone screenshot, stretched to
+    /// the size of the output. --background fills in beneath it, showing
+    /// through any transparent parts. HTML output doesn't include the image
+    #[clap(long, value_name = "PNG")]
+    background_image: Option<PathBuf>,
+
+    /// Crop the PNG to the pixels that differ from --background, or that
+    /// aren't transparent without it, keeping MARGIN pixels around them.
+    /// --autocrop alone keeps no margin. The map is drawn at the size --scale,
+    /// --dpi, --preset or --scales give and then cropped, so the output is smaller
+    /// than that size. HTML output and --animate-z frames aren't cropped
+    #[clap(
+        long,
+        value_name = "MARGIN",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "0"
+    )]
+    autocrop: Option<u32>,
+
+    /// Draw the PNG at exactly WIDTHxHEIGHT pixels, such as 800x600, with the
+    /// map scaled to fit and centered in place of the --dpi or --preset
+    /// scale. Leftover space is filled with --letterbox-color
+    #[clap(long, value_name = "WIDTHxHEIGHT", value_parser, group = "frame", conflicts_with_all = &["scales", "animate-z", "autocrop", "tiles"])]
+    size: Option<FrameSize>,
+
+    /// Draw the PNG the size of a portrait page at --dpi, or the preset or
+    /// config dpi, with the map scaled to fit within --margin and centered:
+    /// a4 (210x297mm) or letter (8.5x11in)
+    #[clap(long, value_parser, group = "frame", conflicts_with_all = &["scales", "animate-z", "autocrop", "print-width", "tiles"])]
+    page: Option<PageSize>,
+
+    /// With --page, the margin kept clear on each side, in millimetres.
+    /// Defaults to 10
+    #[clap(long, value_name = "MM", requires = "page")]
+    margin: Option<f32>,
+
+    /// Fill the space --size or --page leaves beside the map with this color,
+    /// given as #rrggbb or r,g,b. Defaults to --background, or transparent
+    #[clap(long, value_parser, requires = "frame")]
+    letterbox_color: Option<Color>,
+
+    /// Outline points in this color, given as #rrggbb or r,g,b
+    #[clap(long, value_parser)]
+    point_outline: Option<Color>,
+
+    /// Draw zone connections, the points whose label starts with this prefix,
+    /// as larger diamonds. --style-zone-links alone uses the game's to_
+    /// prefix
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = ZONE_LINK_PREFIX
+    )]
+    style_zone_links: Option<String>,
+
+    /// Which items are drawn on top: points-on-top, lines-on-top, or
+    /// file-order. Defaults to points-on-top
+    #[clap(long, value_parser)]
+    draw_order: Option<DrawOrder>,
+
+    /// Scale line widths by length, between --min-line-width and
+    /// --max-line-width
+    #[clap(long)]
+    scale_line_width: bool,
+
+    /// Draw chains of connected lines of one color as smooth curves through
+    /// their ends instead of straight segments, for roads and rivers drawn
+    /// one segment at a time
+    #[clap(long)]
+    smooth: bool,
+
+    /// Defaults to 0.5
+    #[clap(long)]
+    min_line_width: Option<f32>,
+
+    /// Defaults to 4
+    #[clap(long)]
+    max_line_width: Option<f32>,
+
+    /// The stroke width of lines without a width of their own, in map units,
+    /// unless --scale-line-width is given. Defaults to 1
+    #[clap(long, value_parser = parse_line_width)]
+    line_width: Option<f32>,
+
+    /// The radius of each point's circle, in map units. --size-by-point-type,
+    /// --size-by-label-number, zone link diamonds and clusters scale from it.
+    /// Defaults to 3
+    #[clap(long, value_parser = parse_point_radius)]
+    point_radius: Option<f32>,
+
+    /// Which coordinates to draw: xy (top-down), xz, or yz (cross-sections).
+    /// Defaults to xy
+    #[clap(long, value_parser)]
+    plane: Option<Plane>,
+
+    /// Draw the first coordinate increasing to the left. By default it
+    /// increases to the right and the second one downwards, which puts north
+    /// at the top in the xy plane, as in game
+    #[clap(long)]
+    flip_x: bool,
+
+    /// Draw the second coordinate increasing upwards
+    #[clap(long)]
+    flip_y: bool,
+
+    /// Set both flips at once: default, flip-x, flip-y or flip-both
+    #[clap(long, value_parser, conflicts_with_all = &["flip-x", "flip-y"])]
+    orientation: Option<Orientation>,
+
+    /// Opacity of every line from 0 to 1, so dense areas show up darker.
+    /// Defaults to 1
+    #[clap(long, value_parser = parse_opacity)]
+    line_opacity: Option<f32>,
+
+    /// Merge points within about this many output pixels of each other into
+    /// one marker showing how many points it stands for
+    #[clap(long)]
+    cluster: Option<f32>,
+
+    /// Label lines at least this long, in map units, with their length.
+    /// --label-lengths alone labels lines of 10 units or more
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "10"
+    )]
+    label_lengths: Option<f32>,
+
+    /// Draw a heatmap of how many points fall in each square of this size,
+    /// in map units, instead of the points. The heatmap ignores the points'
+    /// colors. --heatmap alone uses 20 unit squares
+    #[clap(
+        long,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "20"
+    )]
+    heatmap: Option<f32>,
+
+    /// The gradient to color the heatmap with: magma, viridis, grayscale or
+    /// hot-cold
+    #[clap(long)]
+    colormap: Option<Colormap>,
+
+    /// Fade lines by their height so lower floors appear fainter
+    #[clap(long)]
+    depth_fade: bool,
+
+    /// Render for printing at this many dots per inch, and record it in the
+    /// PNG
+    #[clap(long, value_parser = parse_dpi)]
+    dpi: Option<f32>,
+
+    /// Draw at this many pixels per map unit, even if --preset or --config
+    /// sets a dpi. Defaults to 1, or the scale --preset draws at
+    #[clap(long, value_parser = parse_scale, conflicts_with = "dpi")]
+    scale: Option<f32>,
+
+    /// Draw one PNG per factor in this comma-separated list, named after the
+    /// output with @{factor}x before the extension, such as out@2x.png. Each
+    /// factor multiplies the scale that --scale, --dpi or --preset would
+    /// otherwise draw at
+    #[clap(long, value_parser = parse_scale_factor, use_value_delimiter = true)]
+    scales: Vec<f32>,
+
+    /// Draw an animated GIF instead, with one frame for each of this many
+    /// equal slices of the map's Z range, from the lowest up. Lines only
+    /// show in the frames that hold both of their ends
+    #[clap(long, conflicts_with_all = &["scales", "format"])]
+    animate_z: Option<usize>,
+
+    /// Draw one PNG per color instead, holding only that color's items and
+    /// named after the output with the color before the extension, such as
+    /// out-ff0000.png. Every file covers the whole map, so they stack as
+    /// layers
+    #[clap(long, conflicts_with_all = &["scales", "animate-z", "autocrop"])]
+    split_by_color: bool,
+
+    /// Draw 256 pixel web map tiles instead, from zoom 0 up to this zoom, to
+    /// OUT/{z}/{x}/{y}.png with OUT as a directory. Zoom 0 is one tile of
+    /// the whole map and each zoom splits every tile into four, up to zoom
+    /// 10. Tiles wholly past the map's short side are left out. Finished
+    /// tiles are recorded in OUT/manifest.json, and a rerun skips the ones
+    /// that are newer than the input files, so an interrupted or repeated
+    /// run only draws what is missing or out of date. --overwrite doesn't
+    /// apply
+    #[clap(
+        long,
+        value_name = "MAX_ZOOM",
+        value_parser = value_parser!(u32).range(..=MAX_TILE_ZOOM as i64),
+        conflicts_with_all = &["scales", "animate-z", "split-by-color", "autocrop"]
+    )]
+    tiles: Option<u32>,
+
+    /// With --tiles, draw every tile again, whatever the manifest says. Tiles
+    /// record only their inputs' modification times, so use this after
+    /// changing the other options
+    #[clap(long, requires = "tiles")]
+    force: bool,
+
+    /// With --animate-z, whether each frame shows only its own slice
+    /// (exclusive) or every slice up to it (cumulative)
+    #[clap(
+        long,
+        value_parser,
+        default_value = "exclusive",
+        requires = "animate-z"
+    )]
+    animate_z_mode: ZBandMode,
+
+    /// With --dpi, the printed width of the map in inches. Without it, one
+    /// map unit prints as 1/96 of an inch
+    #[clap(long, requires = "dpi")]
+    print_width: Option<f32>,
+
+    /// Draw the items of each kind sorted by color, coordinates and label
+    /// instead of in file order, so that the same items always give the same
+    /// output. --draw-order still decides whether points or lines come first
+    #[clap(long)]
+    sort_output: bool,
+
+    /// Tag SVG elements with data-type, data-label and data-z attributes
+    #[clap(long)]
+    svg_data_attrs: bool,
+
+    /// Group each item's SVG elements under an id made from a hash of the
+    /// item, so the same item always gets the same id
+    #[clap(long)]
+    svg_ids: bool,
+
+    /// Define each point marker shared by several points once and reference
+    /// it from each point, for smaller SVGs of maps with many points
+    #[clap(long)]
+    point_symbols: bool,
+
+    /// The SVG's width and height: px for the view box size, or percent to
+    /// fill the containing element. Defaults to px
+    #[clap(long, value_parser)]
+    svg_size: Option<SvgSize>,
+
+    /// With --format html, show the map coordinates under the mouse as it
+    /// moves, following --plane and the orientation flips, and in plan views
+    /// the /loc of that spot, for picking /locs off the map
+    #[clap(long)]
+    show_grid_coords_under_cursor: bool,
+
+    /// What to fit the view to: all items, or points for the labelled places
+    /// alone, leaving out walls that sprawl beyond them. Without --padding,
+    /// points on the edge are drawn half cut off. --crop and --center
+    /// override it. Defaults to all
+    #[clap(long, value_parser)]
+    fit: Option<Fit>,
+
+    /// Widen the view to take in the labels of the fitted items, so that
+    /// labels on the edge aren't cut off. Each label's size is estimated
+    /// from its length, so the margin they get is only roughly even
+    #[clap(long)]
+    fit_labels: bool,
+
+    /// Widen the view by this many map units on every side. --padding alone
+    /// pads by the point radius, so that points on the edge are drawn whole.
+    /// --crop and --center aren't padded
+    #[clap(
+        long,
+        value_name = "UNITS",
+        value_parser = parse_padding,
+        min_values = 0,
+        require_equals = true
+    )]
+    padding: Option<Option<f32>>,
+
+    /// Load the host's installed fonts in addition to the bundled font
+    #[clap(long)]
+    system_fonts: bool,
+
+    /// Print how long loading, processing and drawing took to stderr
+    #[clap(long)]
+    profile: bool,
+
+    /// Load and process the files, then print how many items would be drawn
+    /// and which files would be written at what size, without drawing or
+    /// writing anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// With --crop or --center, keep the items outside the region and dim
+    /// them under a translucent overlay instead
+    #[clap(long, requires = "region")]
+    focus: bool,
+
+    /// With --crop or --center, draw the lines of the whole map small in a
+    /// corner, with the cropped view marked in red: top-left, top-right,
+    /// bottom-left or bottom-right. --inset alone uses bottom-right
+    #[clap(
+        long,
+        value_name = "CORNER",
+        value_parser,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "bottom-right",
+        requires = "region"
+    )]
+    inset: Option<Corner>,
+
+    /// Write the names of the input files, without their directories or
+    /// extensions, in a corner: top-left, top-right, bottom-left or
+    /// bottom-right. --stamp-filename alone uses bottom-left
+    #[clap(
+        long,
+        value_name = "CORNER",
+        value_parser,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "bottom-left"
+    )]
+    stamp_filename: Option<Corner>,
+
+    /// With --stamp-filename, the font size of the names. Defaults to
+    /// --font-size
+    #[clap(long, requires = "stamp-filename")]
+    stamp_font_size: Option<f32>,
+}
+
+#[derive(Args, Debug)]
+struct ConvertArgs {
+    /// The map files to read, or - to read one from standard input
+    #[clap(required = true)]
+    files: Vec<String>,
+
+    /// The file to write, in the format its extension names
+    #[clap()]
+    out: String,
+
+    /// Write this format whatever the output's extension: map, csv,
+    /// geojson, svg, or waypoints for one "label x y z" line per labelled
+    /// point
+    #[clap(long, value_parser)]
+    format: Option<MapFormat>,
+
+    /// The coordinate order of waypoints output: map, or loc for the order
+    /// /loc prints in game. Defaults to map
+    #[clap(long, value_parser)]
+    waypoint_order: Option<CoordinateOrder>,
+
+    /// In CSV and waypoints output, add _2, _3 and so on to repeated point
+    /// labels, for tools that key on the label. The loaded map is unchanged
+    #[clap(long)]
+    unique_labels: bool,
+
+    /// What to do when the output file already exists: always replace it,
+    /// never replace it, or prompt to ask first. Prompting fails when not
+    /// run from a terminal
+    #[clap(long, value_parser, default_value = "always")]
+    overwrite: Overwrite,
+
+    #[clap(flatten)]
+    load: LoadArgs,
+
+    #[clap(flatten)]
+    process: ProcessArgs,
+
+    /// Start a .svg output with an XML declaration
+    #[clap(long)]
+    xml_prolog: bool,
+
+    /// Indent the elements of a .svg output by how deeply they are nested,
+    /// for reading
+    #[clap(long)]
+    pretty: bool,
+
+    /// Write a .svg output as small as it can be: on one line, without
+    /// default attributes or classes, and with shorter numbers
+    #[clap(long, conflicts_with = "pretty")]
+    minify: bool,
+
+    /// Write one file per color, holding only that color's items and named
+    /// after the output with the color before the extension, such as
+    /// out-ff0000.map
+    #[clap(long)]
+    split_by_color: bool,
+
+    /// Describe the map at the top of a .svg output: the input files, how
+    /// many lines and points it has, its extent and when it was made
+    #[clap(long)]
+    embed_metadata: bool,
+
+    /// With --embed-metadata, leave out when the file was made, so the same
+    /// inputs always give the same file
+    #[clap(long, requires = "embed-metadata")]
+    no_timestamp: bool,
+
+    /// Group each item's elements in a .svg output under an id made from a
+    /// hash of the item, so the same item always gets the same id and two
+    /// outputs can be compared by id
+    #[clap(long)]
+    svg_ids: bool,
+}
+
+#[derive(Args, Debug)]
+struct InfoArgs {
+    /// The map files to read, or - to read one from standard input
+    #[clap(required = true)]
+    files: Vec<String>,
+
+    #[clap(flatten)]
+    load: LoadArgs,
+
+    #[clap(flatten)]
+    process: ProcessArgs,
+
+    /// Print the extent of all the items together, after processing, as
+    /// "min_x min_y max_x max_y width height", instead of how many items and
+    /// points each file or zip archive entry holds
+    #[clap(long, conflicts_with = "list")]
+    bbox: bool,
+
+    /// Print JSON instead. With --bbox this is an object with those keys;
+    /// otherwise it is an array with an object for each file or zip archive
+    /// entry, holding its name, points, lines, raw, colors and labels
+    /// counts, its bounding_box and its z_range as [min, max]
+    #[clap(long, conflicts_with = "list")]
+    json: bool,
+
+    /// Only print the name of each file and zip archive entry that would be
+    /// read, sorted
+    #[clap(long)]
+    list: bool,
+}
+
+#[derive(Args, Debug)]
+struct ValidateArgs {
+    /// The map files to check, or - to read one from standard input
+    #[clap(required = true)]
+    files: Vec<String>,
+
+    /// The text encoding of the input files, such as utf-8 or windows-1252
+    #[clap(long, value_parser, default_value = "utf-8")]
+    input_encoding: InputEncoding,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
-    let paths = args.files.iter().map(|file| Path::new(file));
-    let map_items = MapItems::load_from_files(paths).await?;
+async fn main() -> ExitCode {
+    let cli = Cli::parse_from(args_with_default_subcommand(std::env::args_os()));
+    init_logger(&cli);
+
+    let result = match cli.command {
+        Command::Render(args) => render(args).await,
+        Command::Convert(args) => convert(args).await,
+        Command::Info(args) => info(args).await,
+        Command::Validate(args) => validate(&args.files, args.input_encoding),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {:?}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Inserts `DEFAULT_SUBCOMMAND` after any leading global flags, unless the
+/// arguments already name a subcommand or ask for help or the version.
+fn args_with_default_subcommand(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    const GLOBAL_FLAGS: [&str; 4] = ["-q", "--quiet", "-v", "--verbose"];
+    const PASSTHROUGH: [&str; 9] = [
+        "render",
+        "convert",
+        "info",
+        "validate",
+        "help",
+        "-h",
+        "--help",
+        "-V",
+        "--version",
+    ];
+
+    let mut args: Vec<OsString> = args.collect();
+    let first = args
+        .iter()
+        .skip(1)
+        .position(|arg| !GLOBAL_FLAGS.iter().any(|flag| arg == flag))
+        .map(|index| index + 1);
+
+    if let Some(index) = first {
+        if !PASSTHROUGH.iter().any(|name| args[index] == *name) {
+            args.insert(index, DEFAULT_SUBCOMMAND.into());
+        }
+    }
+
+    args
+}
+
+impl LoadArgs {
+    fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            colorize_by_file: self.colorize_by_file,
+            max_items: self.max_items,
+            strict: self.strict,
+            input_format: self.input_format,
+            input_encoding: self.input_encoding,
+            jobs: self.jobs,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the files, along with how many of them were skipped by
+    /// --keep-going.
+    async fn load(&self, files: &[String]) -> Result<(MapItems, Skipped), anyhow::Error> {
+        let paths = files.iter().map(Path::new);
+        let (map_items, skipped) = if self.keep_going {
+            let (map_items, failures) =
+                MapItems::from_files_skipping_failures(paths, &self.load_options())
+                    .context("Failed to load the map files")?;
+            for (path, err) in failures.iter() {
+                log::error!("Skipping {}: {:#}", path.display(), err);
+            }
+            (map_items, Skipped::new(failures.len(), files.len()))
+        } else {
+            let map_items = MapItems::load_from_files_with(paths, &self.load_options())
+                .await
+                .context("Failed to load the map files")?;
+            (map_items, Skipped::new(0, files.len()))
+        };
+
+        log::info!(
+            "Loaded {} items from {} files",
+            map_items.len(),
+            files.len() - skipped.count
+        );
+
+        Ok((map_items, skipped))
+    }
+}
+
+/// How many of a batch of input files --keep-going skipped.
+#[derive(Debug, Clone, Copy)]
+struct Skipped {
+    count: usize,
+    total: usize,
+}
+
+impl Skipped {
+    fn new(count: usize, total: usize) -> Self {
+        Skipped { count, total }
+    }
+
+    /// Fails the run once all the other files are done, if any were skipped.
+    fn into_result(self) -> Result<(), anyhow::Error> {
+        if self.count > 0 {
+            return Err(anyhow!(
+                "{} of {} files failed to load and were skipped",
+                self.count,
+                self.total
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl ProcessArgs {
+    /// Removes the --crop or --center region, so that it is only drawn
+    /// with --focus instead of cropping the items.
+    fn take_focus_region(&mut self) -> Option<FocusRegion> {
+        let circle = self.circle();
+        self.center = None;
+        self.radius = None;
+
+        match self.crop.take() {
+            Some(bounds) => Some(FocusRegion::Rect(bounds)),
+            None => circle.map(FocusRegion::Circle),
+        }
+    }
+
+    fn circle(&self) -> Option<Circle> {
+        let (x, y) = self.center?;
+        Some(Circle::new(x, y, self.radius?))
+    }
+
+    fn apply(&self, map_items: MapItems) -> Result<MapItems, anyhow::Error> {
+        Ok(self.crop_to_region(self.apply_uncropped(map_items)?))
+    }
+
+    /// Applies every step but --crop and --center.
+    fn apply_uncropped(&self, mut map_items: MapItems) -> Result<MapItems, anyhow::Error> {
+        if let Some(limit) = self.limit {
+            map_items.truncate(limit);
+        }
+        if self.loc_convention {
+            map_items.swap_loc_convention();
+        }
+        if self.z_min.is_some() || self.z_max.is_some() {
+            let min_z = self.z_min.unwrap_or(f32::NEG_INFINITY);
+            let max_z = self.z_max.unwrap_or(f32::INFINITY);
+            if min_z.is_nan() || max_z.is_nan() || min_z > max_z {
+                return Err(anyhow!(
+                    "--z-min and --z-max need numbers, with --z-min not above --z-max"
+                ));
+            }
+            map_items = map_items.filter_z(min_z, max_z);
+        }
+        if self.flatten_z {
+            map_items.flatten_z();
+        }
+        if self.dedup {
+            let removed = map_items.dedup();
+            log::info!("Removed {} repeated items", removed);
+        }
+        if let Some(cell_size) = self.snap {
+            if !(cell_size > 0.0 && cell_size.is_finite()) {
+                return Err(anyhow!("--snap needs a grid cell size above 0"));
+            }
+            map_items.snap_to_grid(cell_size, self.snap_mode.unwrap_or_default());
+        }
+
+        if let Some(tolerance) = self.snap_endpoints {
+            if !(tolerance > 0.0 && tolerance.is_finite()) {
+                return Err(anyhow!("--snap-endpoints needs a tolerance above 0"));
+            }
+            let moved = map_items.snap_endpoints(tolerance);
+            log::info!("Snapped {} line ends onto others", moved);
+        }
+
+        if self.color_tolerance > 0.0 {
+            map_items.merge_similar_colors(self.color_tolerance);
+        }
+
+        if self.merge_colinear {
+            let before = map_items.len();
+            map_items.merge_colinear();
+            log::debug!("Merged {} colinear lines", before - map_items.len());
+        }
+
+        if self.warn_stacked_points {
+            for group in map_items.stacked_points() {
+                let labels: Vec<&str> = group.iter().map(|point| point.label.as_str()).collect();
+                log::warn!(
+                    "{} points share {}, {}: {}",
+                    group.len(),
+                    group[0].point.x,
+                    group[0].point.y,
+                    labels.join(", ")
+                );
+            }
+        }
+
+        if let Some(max_z_delta) = self.max_line_z_delta {
+            match self.steep_lines {
+                SteepLines::Warn => {
+                    let steep = map_items.steep_lines(max_z_delta);
+                    for line in steep.iter() {
+                        log::warn!(
+                            "The line from {}, {}, {} to {}, {}, {} spans {} in Z",
+                            line.from.x,
+                            line.from.y,
+                            line.from.z,
+                            line.to.x,
+                            line.to.y,
+                            line.to.z,
+                            line.z_delta()
+                        );
+                    }
+                    if !steep.is_empty() {
+                        log::warn!("{} lines span more than {} in Z", steep.len(), max_z_delta);
+                    }
+                }
+                SteepLines::Drop => {
+                    let dropped = map_items.drop_steep_lines(max_z_delta);
+                    if dropped > 0 {
+                        log::warn!(
+                            "Dropped {} lines that span more than {} in Z",
+                            dropped,
+                            max_z_delta
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(tolerance) = self.check_coincident_endpoints {
+            if !(tolerance > 0.0 && tolerance.is_finite()) {
+                return Err(anyhow!(
+                    "--check-coincident-endpoints needs a tolerance above 0"
+                ));
+            }
+            let gaps = map_items.endpoint_gaps(tolerance);
+            for gap in gaps.iter() {
+                log::warn!(
+                    "The line ends at {}, {}, {} and {}, {}, {} are {} apart",
+                    gap.a.x,
+                    gap.a.y,
+                    gap.a.z,
+                    gap.b.x,
+                    gap.b.y,
+                    gap.b.z,
+                    gap.distance
+                );
+            }
+            if !gaps.is_empty() {
+                log::warn!(
+                    "{} pairs of line ends are within {} of each other without meeting",
+                    gaps.len(),
+                    tolerance
+                );
+            }
+        }
+
+        if let Some(by) = self.seeded_colors {
+            map_items = map_items.seeded_colors(self.seed, by);
+        }
+
+        if let Some(re) = &self.labels {
+            map_items = map_items.include_labels(re);
+        }
+        if let Some(path) = &self.include_file {
+            let labels = read_label_set(Path::new(path))
+                .with_context(|| format!("Failed to read the labels in {}", path))?;
+            map_items = map_items.include_label_set(&labels);
+        }
+        if let Some(re) = &self.exclude_labels {
+            map_items = map_items.exclude_labels(re);
+        }
+        if self.connect_by_label {
+            map_items = map_items.connect_by_label();
+        }
+        if let Some(n) = self.sample_every {
+            map_items = map_items.sample(n);
+        }
+
+        Ok(map_items)
+    }
+
+    /// Applies --crop and --center.
+    fn crop_to_region(&self, mut map_items: MapItems) -> MapItems {
+        if let Some(bounds) = &self.crop {
+            map_items = if self.clip_lines {
+                map_items.clip(bounds)
+            } else {
+                map_items.crop(bounds)
+            };
+        }
+        if let Some(circle) = self.circle() {
+            map_items = if self.clip_lines {
+                map_items.clip_circle(&circle)
+            } else {
+                map_items.crop_circle(&circle)
+            };
+        }
+
+        map_items
+    }
+}
+
+async fn render(mut args: RenderArgs) -> Result<(), anyhow::Error> {
+    let focus = if args.focus {
+        args.process.take_focus_region()
+    } else {
+        None
+    };
+
+    let config = match &args.config {
+        Some(path) => read_config(Path::new(path))
+            .with_context(|| format!("Failed to read the config file {}", path))?,
+        None => RenderOptions::default(),
+    };
 
-    map_draw(&map_items, Path::new(&args.out))?;
+    check_lossless(&args)?;
+    let inputs: Vec<&Path> = args
+        .files
+        .iter()
+        .chain(args.relative_to.iter())
+        .map(Path::new)
+        .collect();
+    check_stdin_once(&inputs)?;
+    let mut outputs = output_paths(&args)?;
+    if !args.dry_run && !args.split_by_color && args.tiles.is_none() {
+        for (path, _) in outputs.iter() {
+            args.overwrite.check(path)?;
+        }
+    }
+
+    if let Some(meta) = args.meta.as_deref().filter(|_| !args.dry_run) {
+        args.overwrite.check(meta)?;
+    }
+
+    let started = Instant::now();
+    let (map_items, skipped) = args.load.load(&args.files).await?;
+    let loaded = Instant::now();
+    let map_items = args.process.apply_uncropped(map_items)?;
+    let inset = args.inset.map(|corner| Inset {
+        map_items: map_items.clone(),
+        corner,
+    });
+    let map_items = args.process.crop_to_region(map_items);
+    let map_items = match &args.relative_to {
+        Some(path) => {
+            let (baseline, baseline_skipped) = args.load.load(std::slice::from_ref(path)).await?;
+            baseline_skipped.into_result()?;
+            let diff = args.process.apply(baseline)?.diff(&map_items);
+            log::info!(
+                "{} items are unchanged, {} were removed and {} added",
+                diff.shared.len(),
+                diff.removed.len(),
+                diff.added.len()
+            );
+            diff.colorized()
+        }
+        None => map_items,
+    };
+    if args.split_by_color {
+        // The files are only known once the map's colors are.
+        outputs = map_items
+            .colors()
+            .into_iter()
+            .map(|color| (color_path(Path::new(&args.out), color), 1.0))
+            .collect();
+        if !args.dry_run {
+            for (path, _) in outputs.iter() {
+                args.overwrite.check(path)?;
+            }
+        }
+    }
+    let processed = Instant::now();
+
+    let base = match args.preset {
+        Some(preset) => preset.render_options(config),
+        None => config,
+    };
+    let plane = args.plane.unwrap_or(base.plane);
+
+    let dpi = args.dpi.or(base.dpi);
+    let point_radius = args.point_radius.unwrap_or(base.point_radius);
+    let scale = chosen_scale(&map_items, args.dpi, args.scale, &base, args.print_width);
+
+    let (frame_size, frame_margin) = match (args.page, dpi) {
+        (Some(page), Some(dpi)) => {
+            let frame = page.frame(dpi);
+            let margin = args.margin.unwrap_or(DEFAULT_PAGE_MARGIN);
+            let margin_pixels = millimetres_to_pixels(margin, dpi);
+            if !margin.is_finite()
+                || margin < 0.0
+                || margin_pixels * 2 >= frame.width.min(frame.height)
+            {
+                return Err(anyhow!("A {}mm margin leaves no room on the page", margin));
+            }
+            (Some(frame), margin_pixels)
+        }
+        (Some(_), None) => {
+            return Err(anyhow!(
+                "--page needs a dpi from --dpi, --preset or the config file"
+            ))
+        }
+        (None, _) => (args.size.or(base.frame_size), base.frame_margin),
+    };
+
+    let font_size = args.font_size.unwrap_or(base.font_size);
+    let stamp = args.stamp_filename.map(|corner| Stamp {
+        text: args
+            .files
+            .iter()
+            .map(|file| {
+                Path::new(file)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        corner,
+        font_size: args.stamp_font_size.unwrap_or(font_size),
+    });
+
+    let render_options = RenderOptions {
+        font_family: args.font.clone().unwrap_or(base.font_family),
+        font_size,
+        system_fonts: args.system_fonts || base.system_fonts,
+        draw_order: args.draw_order.unwrap_or(base.draw_order),
+        contrast_labels: args.contrast_labels || base.contrast_labels,
+        point_outline: args.point_outline.or(base.point_outline),
+        label_halo: args.label_halo.or(base.label_halo),
+        dpi,
+        depth_fade: args.depth_fade || base.depth_fade,
+        line_opacity: args.line_opacity.unwrap_or(base.line_opacity),
+        plane,
+        orientation: args.orientation.unwrap_or(Orientation {
+            flip_x: args.flip_x || base.orientation.flip_x,
+            flip_y: args.flip_y || base.orientation.flip_y,
+        }),
+        svg_data_attrs: args.svg_data_attrs || base.svg_data_attrs,
+        svg_ids: args.svg_ids || base.svg_ids,
+        cluster_radius: args.cluster.or(base.cluster_radius),
+        heatmap_cell_size: args.heatmap.or(base.heatmap_cell_size),
+        colormap: args.colormap.unwrap_or(base.colormap),
+        svg_size: args.svg_size.unwrap_or(base.svg_size),
+        html_cursor_coordinates: args.show_grid_coords_under_cursor || base.html_cursor_coordinates,
+        background: args
+            .background
+            .map_or(base.background, |background| background.0),
+        background_image: args.background_image.clone().or(base.background_image),
+        label_lengths: args.label_lengths.or(base.label_lengths),
+        fit: args.fit.unwrap_or(base.fit),
+        fit_labels: args.fit_labels || base.fit_labels,
+        padding: match args.padding {
+            Some(Some(padding)) => padding,
+            Some(None) => point_radius,
+            None => base.padding,
+        },
+        view_bounds: args
+            .process
+            .circle()
+            .filter(|_| plane == Plane::Xy)
+            .map(|circle| circle.bounding_box()),
+        focus,
+        inset,
+        stamp,
+        on_item: None,
+        sort_output: args.sort_output || base.sort_output,
+        zone_link_prefix: args.style_zone_links.clone().or(base.zone_link_prefix),
+        xml_prolog: base.xml_prolog,
+        svg_formatting: base.svg_formatting,
+        metadata: None,
+        label_min_scale: args.label_min_scale.or(base.label_min_scale),
+        keep_label_underscores: args.keep_label_underscores || base.keep_label_underscores,
+        max_label_count: args.max_label_count.or(base.max_label_count),
+        size_by_label_number: args.size_by_label_number || base.size_by_label_number,
+        size_by_point_type: args.size_by_point_type || base.size_by_point_type,
+        point_symbols: args.point_symbols || base.point_symbols,
+        autocrop: args.autocrop.or(base.autocrop),
+        frame_size,
+        frame_margin,
+        letterbox_color: args.letterbox_color.or(base.letterbox_color),
+        scale,
+        scale_line_width: args.scale_line_width || base.scale_line_width,
+        smooth_lines: args.smooth || base.smooth_lines,
+        min_line_width: args.min_line_width.unwrap_or(base.min_line_width),
+        max_line_width: args.max_line_width.unwrap_or(base.max_line_width),
+        line_width: args.line_width.or(base.line_width),
+        point_radius,
+    };
+
+    if args.dry_run {
+        print_plan(&args, &outputs, &map_items, &render_options)?;
+        return skipped.into_result();
+    }
+
+    draw(&args, &map_items, &render_options)?;
+
+    if let Some(meta) = &args.meta {
+        let json = serde_json::to_string_pretty(&map_meta(&map_items, &render_options))?;
+        std::fs::write(meta, json)
+            .with_context(|| format!("Failed to write the map's metadata to {}", meta.display()))?;
+        log::info!("Wrote {}", meta.display());
+    }
+
+    if args.profile {
+        eprintln!(
+            "profile: load {:?}, process {:?}, draw {:?}, total {:?}",
+            loaded - started,
+            processed - loaded,
+            processed.elapsed(),
+            started.elapsed()
+        );
+    }
+
+    skipped.into_result()
+}
+
+/// The --margin around a --page, in millimetres.
+const DEFAULT_PAGE_MARGIN: f32 = 10.0;
+
+/// The files render writes, along with the --scales factor each is drawn
+/// at: the output itself at 1, or one file per factor. None when writing to
+/// standard output.
+fn output_paths(args: &RenderArgs) -> Result<Vec<(PathBuf, f32)>, anyhow::Error> {
+    if args.out == STDOUT {
+        if !args.scales.is_empty() {
+            return Err(anyhow!("--scales can't write to standard output"));
+        }
+        if args.animate_z.is_some() {
+            return Err(anyhow!("--animate-z can't write to standard output"));
+        }
+        if args.split_by_color {
+            return Err(anyhow!("--split-by-color can't write to standard output"));
+        }
+        if args.tiles.is_some() {
+            return Err(anyhow!("--tiles can't write to standard output"));
+        }
+        return Ok(Vec::new());
+    }
+
+    // The tiles are listed by print_plan and draw_tiles, once the manifest
+    // has been read.
+    if args.tiles.is_some() {
+        return Ok(Vec::new());
+    }
+
+    let out = Path::new(&args.out);
+    check_output_extension(out, args)?;
+    if args.scales.is_empty() || args.animate_z.is_some() {
+        return Ok(vec![(out.to_path_buf(), 1.0)]);
+    }
+
+    Ok(args
+        .scales
+        .iter()
+        .map(|&factor| (scaled_path(out, factor), factor))
+        .collect())
+}
+
+/// Fails when the output format can't be written the way --lossless asks:
+/// WebP is only written losslessly, and GIF and HTML have no lossless mode.
+fn check_lossless(args: &RenderArgs) -> Result<(), anyhow::Error> {
+    if args.lossless && args.animate_z.is_some() {
+        return Err(anyhow!(
+            "--lossless doesn't apply to --animate-z, as GIF frames are limited to 256 colors"
+        ));
+    }
+    match (args.format, args.lossless) {
+        (OutputFormat::Webp, false) => Err(anyhow!(
+            "This build only writes WebP losslessly; pass --lossless"
+        )),
+        (OutputFormat::Html, true) => Err(anyhow!("--lossless only applies to images")),
+        _ => Ok(()),
+    }
+}
+
+/// Prints what --dry-run found that render would do.
+fn print_plan(
+    args: &RenderArgs,
+    outputs: &[(PathBuf, f32)],
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    let stats = map_items.stats();
+    println!(
+        "Would draw {} items: {} lines, {} points and {} texts",
+        map_items.len(),
+        stats.lines,
+        stats.points,
+        stats.texts
+    );
+
+    if let Some(max_zoom) = args.tiles {
+        let plan = TilePlan::new(args, map_tiles(map_items, render_options, max_zoom))?;
+        println!(
+            "Would write {} of {} {}x{} PNG tiles to {}, skipping {} that are up to date",
+            plan.pending.len(),
+            plan.pending.len() + plan.done.len(),
+            TILE_SIZE,
+            TILE_SIZE,
+            args.out,
+            plan.done.len()
+        );
+        return Ok(());
+    }
+
+    let (_, _, width, height) = map_view_box_with(map_items, render_options);
+    let size = |factor: f32| match render_options.frame_size {
+        Some(frame) => (frame.width, frame.height),
+        None => {
+            let scale = render_options.scale * factor;
+            (
+                (width * scale).ceil() as u32,
+                (height * scale).ceil() as u32,
+            )
+        }
+    };
+    let what = |factor: f32| match (args.animate_z, args.format) {
+        (Some(bands), _) => {
+            let (width, height) = size(factor);
+            format!("a {}x{} GIF of {} frames", width, height, bands)
+        }
+        (None, OutputFormat::Html) => String::from("an HTML page"),
+        (None, OutputFormat::Webp) => {
+            let (width, height) = size(factor);
+            format!("a lossless {}x{} WebP", width, height)
+        }
+        (None, OutputFormat::Png) => {
+            let (width, height) = size(factor);
+            format!("a {}x{} PNG", width, height)
+        }
+    };
+    let cropped = if render_options.autocrop.is_some() && args.animate_z.is_none() {
+        " (before --autocrop)"
+    } else {
+        ""
+    };
+
+    if outputs.is_empty() {
+        println!("Would write {} to standard output{}", what(1.0), cropped);
+    }
+    for (path, factor) in outputs {
+        let exists = if path.exists() {
+            ", replacing the file there"
+        } else {
+            ""
+        };
+        println!(
+            "Would write {} to {}{}{}",
+            what(*factor),
+            path.display(),
+            cropped,
+            exists
+        );
+    }
+    if let Some(meta) = &args.meta {
+        println!("Would write the map's metadata to {}", meta.display());
+    }
 
     Ok(())
 }
+
+/// The output name that writes to standard output instead of a file. Logs
+/// and progress go to standard error, so nothing else ends up in the image.
+const STDOUT: &str = "-";
+
+/// Writes the drawn map in the output format, once per --scales factor or
+/// --split-by-color color, or as --tiles.
+fn draw(
+    args: &RenderArgs,
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    if let Some(bands) = args.animate_z {
+        map_draw_z_animation(
+            map_items,
+            Path::new(&args.out),
+            render_options,
+            bands,
+            args.animate_z_mode,
+        )
+        .with_context(|| format!("Failed to draw the Z animation to {}", args.out))?;
+        log::info!("Wrote {}", args.out);
+
+        return Ok(());
+    }
+
+    if !args.scales.is_empty() {
+        if !matches!(args.format, OutputFormat::Png) {
+            return Err(anyhow!("--scales can only be used with --format png"));
+        }
+
+        let paths = map_draw_scales(
+            map_items,
+            Path::new(&args.out),
+            render_options,
+            &args.scales,
+        )
+        .with_context(|| format!("Failed to draw the map at several scales to {}", args.out))?;
+        for path in paths {
+            log::info!("Wrote {}", path.display());
+        }
+
+        return Ok(());
+    }
+
+    if let Some(max_zoom) = args.tiles {
+        if !matches!(args.format, OutputFormat::Png) {
+            return Err(anyhow!("--tiles can only be used with --format png"));
+        }
+
+        return draw_tiles(args, max_zoom, map_items, render_options)
+            .with_context(|| format!("Failed to draw the map's tiles to {}", args.out));
+    }
+
+    if args.split_by_color {
+        if !matches!(args.format, OutputFormat::Png) {
+            return Err(anyhow!(
+                "--split-by-color can only be used with --format png"
+            ));
+        }
+
+        let paths = map_draw_by_color(map_items, Path::new(&args.out), render_options)
+            .with_context(|| format!("Failed to draw the map by color to {}", args.out))?;
+        for path in paths {
+            log::info!("Wrote {}", path.display());
+        }
+
+        return Ok(());
+    }
+
+    if args.out == STDOUT {
+        let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+        match args.format {
+            OutputFormat::Png => map_render_to_writer(map_items, render_options, &mut out),
+            OutputFormat::Webp => map_render_webp_to_writer(map_items, render_options, &mut out),
+            OutputFormat::Html => out
+                .write_all(map_html(map_items, render_options).as_bytes())
+                .map_err(anyhow::Error::from),
+        }
+        .and_then(|()| out.flush().map_err(anyhow::Error::from))
+        .context("Failed to draw the map to standard output")?;
+        log::info!("Wrote the map to standard output");
+
+        return Ok(());
+    }
+
+    match args.format {
+        OutputFormat::Png => map_draw_png(map_items, Path::new(&args.out), render_options),
+        OutputFormat::Webp => map_draw_webp(map_items, Path::new(&args.out), render_options),
+        OutputFormat::Html => std::fs::write(&args.out, map_html(map_items, render_options))
+            .map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("Failed to draw the map to {}", args.out))?;
+
+    log::info!("Wrote {}", args.out);
+
+    Ok(())
+}
+
+/// The file in a --tiles directory that records which tiles are done.
+const TILE_MANIFEST: &str = "manifest.json";
+
+/// How many tiles to draw between saving the manifest, so an interrupted
+/// run loses little work without rewriting the manifest for every tile.
+const TILE_MANIFEST_SAVE_INTERVAL: usize = 32;
+
+/// The --tiles manifest, saved as JSON like
+///
+/// ```json
+/// { "version": 1, "tiles": ["0/0/0", "1/0/0", "1/0/1"] }
+/// ```
+///
+/// `tiles` lists the z/x/y of every tile drawn so far, sorted by zoom. A tile
+/// is drawn after it's taken off the list and only put back once its file is
+/// complete, so a listed tile's file is never half written.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TileManifest {
+    version: u32,
+    tiles: Vec<String>,
+}
+
+/// The version of `TileManifest` this build reads and writes.
+const TILE_MANIFEST_VERSION: u32 = 1;
+
+/// The tiles that --tiles draws that are done and those still to draw.
+struct TilePlan {
+    manifest_path: PathBuf,
+    done: BTreeSet<TileId>,
+    pending: Vec<TileId>,
+}
+
+impl TilePlan {
+    /// Reads the manifest in the --tiles directory, unless --force is given,
+    /// and counts a listed tile as done while its file is newer than every
+    /// input file, --config and --relative-to. Every tile is drawn again when
+    /// any of their modification times can't be read.
+    fn new(
+        args: &RenderArgs,
+        tiles: impl Iterator<Item = TileId>,
+    ) -> Result<TilePlan, anyhow::Error> {
+        let dir = Path::new(&args.out);
+        let manifest_path = dir.join(TILE_MANIFEST);
+        let listed = if args.force || !manifest_path.exists() {
+            BTreeSet::new()
+        } else {
+            read_tile_manifest(&manifest_path)?
+        };
+
+        let inputs = args
+            .files
+            .iter()
+            .chain(args.config.iter())
+            .chain(args.relative_to.iter());
+        let mut newest_input = Some(SystemTime::UNIX_EPOCH);
+        for input in inputs {
+            let modified = std::fs::metadata(input).and_then(|metadata| metadata.modified());
+            newest_input = match (newest_input, modified) {
+                (Some(newest), Ok(modified)) => Some(newest.max(modified)),
+                _ => None,
+            };
+        }
+
+        let up_to_date = |tile: &TileId| {
+            let modified = std::fs::metadata(tile_path(dir, *tile))
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            matches!((modified, newest_input), (Some(modified), Some(newest)) if modified >= newest)
+        };
+        let (done, pending): (Vec<_>, Vec<_>) =
+            tiles.partition(|tile| listed.contains(tile) && up_to_date(tile));
+
+        Ok(TilePlan {
+            manifest_path,
+            done: done.into_iter().collect(),
+            pending,
+        })
+    }
+}
+
+/// The file --tiles draws a tile to, under `dir`.
+fn tile_path(dir: &Path, tile: TileId) -> PathBuf {
+    dir.join(tile.z.to_string())
+        .join(tile.x.to_string())
+        .join(format!("{}.png", tile.y))
+}
+
+fn read_tile_manifest(path: &Path) -> Result<BTreeSet<TileId>, anyhow::Error> {
+    let context = || {
+        format!(
+            "Failed to read the tile manifest {}; pass --force to draw every tile again",
+            path.display()
+        )
+    };
+    let manifest: TileManifest =
+        serde_json::from_str(&std::fs::read_to_string(path).with_context(context)?)
+            .with_context(context)?;
+    if manifest.version != TILE_MANIFEST_VERSION {
+        return Err(anyhow!(
+            "The tile manifest {} is version {}, but only version {} can be read",
+            path.display(),
+            manifest.version,
+            TILE_MANIFEST_VERSION
+        ));
+    }
+
+    manifest
+        .tiles
+        .iter()
+        .map(|tile| tile.parse())
+        .collect::<Result<_, _>>()
+        .with_context(context)
+}
+
+/// Saves the manifest through a temporary file, so an interruption can't
+/// leave a half-written one.
+fn write_tile_manifest(path: &Path, tiles: &BTreeSet<TileId>) -> Result<(), anyhow::Error> {
+    let manifest = TileManifest {
+        version: TILE_MANIFEST_VERSION,
+        tiles: tiles.iter().map(TileId::to_string).collect(),
+    };
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_string(&manifest)?)?;
+    std::fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+/// Draws the --tiles that aren't up to date, saving the manifest as it goes
+/// and once more when done or failed.
+fn draw_tiles(
+    args: &RenderArgs,
+    max_zoom: u32,
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    let TilePlan {
+        manifest_path,
+        mut done,
+        pending,
+    } = TilePlan::new(args, map_tiles(map_items, render_options, max_zoom))?;
+    let dir = Path::new(&args.out);
+    std::fs::create_dir_all(dir)?;
+    // Stale tiles stay listed until they're drawn again otherwise.
+    write_tile_manifest(&manifest_path, &done)?;
+
+    let mut draw = || -> Result<(), anyhow::Error> {
+        for (count, &tile) in pending.iter().enumerate() {
+            let path = tile_path(dir, tile);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            map_draw_tile(map_items, &path, render_options, tile)
+                .with_context(|| format!("Failed to draw the tile {}", tile))?;
+            done.insert(tile);
+
+            if (count + 1) % TILE_MANIFEST_SAVE_INTERVAL == 0 {
+                write_tile_manifest(&manifest_path, &done)?;
+            }
+        }
+
+        Ok(())
+    };
+    let drawn = draw();
+    write_tile_manifest(&manifest_path, &done)?;
+    drawn?;
+
+    log::info!(
+        "Wrote {} tiles to {}, skipping {} that were up to date",
+        pending.len(),
+        args.out,
+        done.len() - pending.len()
+    );
+
+    Ok(())
+}
+
+async fn convert(args: ConvertArgs) -> Result<(), anyhow::Error> {
+    let out = Path::new(&args.out);
+    let format = args.format.or_else(|| MapFormat::from_path(out)).ok_or_else(|| {
+        anyhow!(
+            "Can't tell the format to write {} in; use a .map, .txt, .csv, .geojson or .svg extension, or --format",
+            args.out
+        )
+    })?;
+
+    check_output_path(out, format)?;
+    if args.xml_prolog && format != MapFormat::Svg {
+        return Err(anyhow!("--xml-prolog only applies to .svg output"));
+    }
+    if args.pretty && format != MapFormat::Svg {
+        return Err(anyhow!("--pretty only applies to .svg output"));
+    }
+    if args.minify && format != MapFormat::Svg {
+        return Err(anyhow!("--minify only applies to .svg output"));
+    }
+    if args.embed_metadata && format != MapFormat::Svg {
+        return Err(anyhow!("--embed-metadata only applies to .svg output"));
+    }
+    if args.svg_ids && format != MapFormat::Svg {
+        return Err(anyhow!("--svg-ids only applies to .svg output"));
+    }
+    if args.unique_labels && !matches!(format, MapFormat::Csv | MapFormat::Waypoints) {
+        return Err(anyhow!(
+            "--unique-labels only applies to CSV and waypoints output"
+        ));
+    }
+    if args.waypoint_order.is_some() && format != MapFormat::Waypoints {
+        return Err(anyhow!("--waypoint-order only applies to waypoints output"));
+    }
+    if !args.split_by_color {
+        args.overwrite.check(out)?;
+    }
+
+    let (map_items, skipped) = args.load.load(&args.files).await?;
+    let map_items = args.process.apply(map_items)?;
+    let map_items = if args.unique_labels {
+        map_items.with_unique_labels()
+    } else {
+        map_items
+    };
+
+    if args.split_by_color {
+        let groups = map_items.partition_by_color();
+        let paths: Vec<_> = groups
+            .iter()
+            .map(|(color, _)| color_path(out, *color))
+            .collect();
+        for path in paths.iter() {
+            args.overwrite.check(path)?;
+        }
+        for ((_, group), path) in groups.iter().zip(paths) {
+            write_converted(&args, format, &path, group)
+                .with_context(|| format!("Failed to write the map to {}", path.display()))?;
+            log::info!("Wrote {} items to {}", group.len(), path.display());
+        }
+
+        return skipped.into_result();
+    }
+
+    write_converted(&args, format, out, &map_items)
+        .with_context(|| format!("Failed to write the map to {}", args.out))?;
+
+    log::info!("Wrote {} items to {}", map_items.len(), args.out);
+
+    skipped.into_result()
+}
+
+/// Writes the map items to `out` in the format convert settled on.
+fn write_converted(
+    args: &ConvertArgs,
+    format: MapFormat,
+    out: &Path,
+    map_items: &MapItems,
+) -> Result<(), anyhow::Error> {
+    match format {
+        MapFormat::Svg
+            if args.xml_prolog
+                || args.embed_metadata
+                || args.pretty
+                || args.minify
+                || args.svg_ids =>
+        {
+            let metadata = args.embed_metadata.then(|| SvgMetadata {
+                sources: args.files.clone(),
+                generated: (!args.no_timestamp).then(|| utc_timestamp(SystemTime::now())),
+            });
+            let render_options = RenderOptions {
+                xml_prolog: args.xml_prolog,
+                svg_formatting: if args.pretty {
+                    SvgFormatting::Pretty
+                } else if args.minify {
+                    SvgFormatting::Minify
+                } else {
+                    SvgFormatting::Compact
+                },
+                metadata,
+                svg_ids: args.svg_ids,
+                ..Default::default()
+            };
+            std::fs::write(out, map_svg(map_items, &render_options)).map_err(anyhow::Error::from)
+        }
+        MapFormat::Waypoints => std::fs::write(
+            out,
+            map_items.to_waypoints(args.waypoint_order.unwrap_or_default()),
+        )
+        .map_err(anyhow::Error::from),
+        _ => map_items.write_to_file(out, format),
+    }
+}
+
+async fn info(args: InfoArgs) -> Result<(), anyhow::Error> {
+    if args.bbox {
+        let (map_items, skipped) = args.load.load(&args.files).await?;
+        let map_items = args.process.apply(map_items)?;
+        print_bounding_box(&map_items, args.json)?;
+        return skipped.into_result();
+    }
+
+    print_stats(&args.files, &args.load, args.list, args.json)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OutputFormat {
+    Png,
+    Webp,
+    Html,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::Webp),
+            "html" => Ok(OutputFormat::Html),
+            "avif" => Err(anyhow!("AVIF output isn't supported by this build")),
+            _ => Err(anyhow!("Unknown output format {}", s)),
+        }
+    }
+}
+
+/// Extensions of image and page formats. Asking render for one it can't
+/// write fails before anything is loaded, rather than writing another
+/// format under that name.
+const KNOWN_OUTPUT_EXTENSIONS: &[&str] = &[
+    "png", "gif", "html", "htm", "svg", "webp", "avif", "jpg", "jpeg", "bmp", "tif", "tiff",
+];
+
+/// Fails when `out` has the extension of a format other than the one these
+/// arguments write. Other extensions, or none, are left to the user.
+fn check_output_extension(out: &Path, args: &RenderArgs) -> Result<(), anyhow::Error> {
+    let Some(extension) = out
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+    else {
+        return Ok(());
+    };
+    if !KNOWN_OUTPUT_EXTENSIONS.contains(&extension.as_str()) {
+        return Ok(());
+    }
+
+    let written: &[&str] = match (args.animate_z, args.format) {
+        (Some(_), _) => &["gif"],
+        (None, OutputFormat::Png) => &["png"],
+        (None, OutputFormat::Webp) => &["webp"],
+        (None, OutputFormat::Html) => &["html", "htm"],
+    };
+    if written.contains(&extension.as_str()) {
+        return Ok(());
+    }
+
+    let hint = if extension == "svg" {
+        "; use convert to write SVG"
+    } else {
+        ""
+    };
+    Err(anyhow!(
+        "Can't write a .{} file to {}: render writes .png, .webp with --format webp, .html with --format html, or .gif with --animate-z{}",
+        extension,
+        out.display(),
+        hint
+    ))
+}
+
+/// What --max-line-z-delta does with the lines it finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SteepLines {
+    Warn,
+    Drop,
+}
+
+impl FromStr for SteepLines {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(SteepLines::Warn),
+            "drop" => Ok(SteepLines::Drop),
+            _ => Err(anyhow!("Unknown steep line handling {}", s)),
+        }
+    }
+}
+
+/// A --background color, or `None` for none or transparent.
+#[derive(Debug, Clone, Copy)]
+struct Background(Option<Color>);
+
+impl FromStr for Background {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" | "transparent" => Ok(Background(None)),
+            _ => Ok(Background(Some(s.parse()?))),
+        }
+    }
+}
+
+/// Whether an output file that already exists may be replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overwrite {
+    Always,
+    Never,
+    Prompt,
+}
+
+impl Overwrite {
+    /// Fails if `path` exists and this policy, or the answer to the prompt,
+    /// doesn't allow replacing it.
+    fn check(self, path: &Path) -> Result<(), anyhow::Error> {
+        if self == Overwrite::Always || !path.exists() {
+            return Ok(());
+        }
+
+        if self == Overwrite::Prompt && std::io::stdin().is_terminal() {
+            eprint!("{} already exists. Overwrite it? [y/N] ", path.display());
+            std::io::stderr().flush()?;
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            if matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+                return Ok(());
+            }
+        }
+
+        Err(anyhow!(
+            "{} already exists; pass --overwrite=always to replace it",
+            path.display()
+        ))
+    }
+}
+
+impl FromStr for Overwrite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Overwrite::Always),
+            "never" => Ok(Overwrite::Never),
+            "prompt" => Ok(Overwrite::Prompt),
+            _ => Err(anyhow!("Unknown overwrite policy {}", s)),
+        }
+    }
+}
+
+/// Named bundles of rendering settings, applied underneath any explicit flags.
+#[derive(Debug, Clone, Copy)]
+enum Preset {
+    /// Contrasting labels, `data-*` attributes and a percent SVG size, at one
+    /// pixel per map unit.
+    Web,
+    /// 300 DPI with contrasting 8 unit labels and lines scaled by length.
+    Print,
+    /// A quarter of the size, with lines scaled between 2 and 6 units wide so
+    /// they survive the shrink, and points within 12 pixels clustered.
+    Thumbnail,
+    /// Items drawn in file order with `data-*` attributes and contrasting
+    /// labels, for checking a file against its source.
+    Editor,
+}
+
+impl Preset {
+    /// The preset's settings over `base`.
+    fn render_options(self, base: RenderOptions) -> RenderOptions {
+        match self {
+            Preset::Web => RenderOptions {
+                contrast_labels: true,
+                svg_data_attrs: true,
+                svg_size: SvgSize::Percent,
+                ..base
+            },
+            Preset::Print => RenderOptions {
+                dpi: Some(300.0),
+                font_size: 8.0,
+                contrast_labels: true,
+                scale_line_width: true,
+                ..base
+            },
+            Preset::Thumbnail => RenderOptions {
+                scale: 0.25,
+                scale_line_width: true,
+                min_line_width: 2.0,
+                max_line_width: 6.0,
+                cluster_radius: Some(12.0),
+                ..base
+            },
+            Preset::Editor => RenderOptions {
+                draw_order: DrawOrder::FileOrder,
+                contrast_labels: true,
+                svg_data_attrs: true,
+                ..base
+            },
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "web" => Ok(Preset::Web),
+            "print" => Ok(Preset::Print),
+            "thumbnail" => Ok(Preset::Thumbnail),
+            "editor" => Ok(Preset::Editor),
+            _ => Err(anyhow!("Unknown preset {}", s)),
+        }
+    }
+}
+
+fn parse_opacity(s: &str) -> Result<f32, anyhow::Error> {
+    let opacity: f32 = s.parse()?;
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(anyhow!("Opacity must be between 0 and 1"));
+    }
+
+    Ok(opacity)
+}
+
+fn parse_scale_factor(s: &str) -> Result<f32, anyhow::Error> {
+    let factor: f32 = s.parse()?;
+    if !(factor > 0.0 && factor.is_finite()) {
+        return Err(anyhow!("Scale factors must be positive"));
+    }
+
+    Ok(factor)
+}
+
+fn parse_scale(s: &str) -> Result<f32, anyhow::Error> {
+    let scale: f32 = s.parse()?;
+    if !(scale > 0.0 && scale.is_finite()) {
+        return Err(anyhow!("The scale must be positive"));
+    }
+
+    Ok(scale)
+}
+
+fn parse_dpi(s: &str) -> Result<f32, anyhow::Error> {
+    check_dpi(s.parse()?)
+}
+
+fn check_dpi(dpi: f32) -> Result<f32, anyhow::Error> {
+    if !(dpi > 0.0 && dpi.is_finite()) {
+        return Err(anyhow!("The dpi must be positive"));
+    }
+
+    Ok(dpi)
+}
+
+fn parse_line_width(s: &str) -> Result<f32, anyhow::Error> {
+    let width: f32 = s.parse()?;
+    if !(width > 0.0 && width.is_finite()) {
+        return Err(anyhow!("The line width must be positive"));
+    }
+
+    Ok(width)
+}
+
+fn parse_point_radius(s: &str) -> Result<f32, anyhow::Error> {
+    let radius: f32 = s.parse()?;
+    if !(radius > 0.0 && radius.is_finite()) {
+        return Err(anyhow!("The point radius must be positive"));
+    }
+
+    Ok(radius)
+}
+
+fn parse_padding(s: &str) -> Result<f32, anyhow::Error> {
+    let padding: f32 = s.parse()?;
+    if !(padding >= 0.0 && padding.is_finite()) {
+        return Err(anyhow!("The padding can't be negative"));
+    }
+
+    Ok(padding)
+}
+
+fn parse_center(s: &str) -> Result<(f32, f32), anyhow::Error> {
+    let Some((x, y)) = s.split_once(',') else {
+        return Err(anyhow!("Expected X,Y but got {}", s));
+    };
+
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+/// `time` in UTC as `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, second_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Howard Hinnant's civil_from_days, which counts years from March so
+    // that leap days fall at the end.
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3_600,
+        second_of_day % 3_600 / 60,
+        second_of_day % 60
+    )
+}
+
+fn read_config(path: &Path) -> Result<RenderOptions, anyhow::Error> {
+    let config: RenderOptions = toml::from_str(&std::fs::read_to_string(path)?)?;
+    if let Some(dpi) = config.dpi {
+        check_dpi(dpi)?;
+    }
+
+    Ok(config)
+}
+
+fn read_label_set(path: &Path) -> Result<HashSet<String>, anyhow::Error> {
+    let text = std::fs::read_to_string(path)?;
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn print_bounding_box(map_items: &MapItems, json: bool) -> Result<(), anyhow::Error> {
+    let bounds = map_items
+        .bounding_box()
+        .ok_or_else(|| anyhow!("The map files have no points or lines"))?;
+
+    if json {
+        println!(
+            "{{\"min_x\": {}, \"min_y\": {}, \"max_x\": {}, \"max_y\": {}, \"width\": {}, \"height\": {}}}",
+            bounds.min_x,
+            bounds.min_y,
+            bounds.max_x,
+            bounds.max_y,
+            bounds.width(),
+            bounds.height()
+        );
+    } else {
+        println!(
+            "{} {} {} {} {} {}",
+            bounds.min_x,
+            bounds.min_y,
+            bounds.max_x,
+            bounds.max_y,
+            bounds.width(),
+            bounds.height()
+        );
+    }
+
+    Ok(())
+}
+
+fn validate(inputs: &[String], encoding: InputEncoding) -> Result<(), anyhow::Error> {
+    check_stdin_once(&inputs.iter().map(Path::new).collect::<Vec<_>>())?;
+    let mut files = Vec::new();
+    for input in inputs {
+        let entries = check_file_by_entry(Path::new(input), encoding)
+            .with_context(|| format!("Failed to read {}", input))?;
+        files.extend(entries);
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut failed_files = 0;
+    for (name, errors) in files.iter() {
+        for error in errors.iter() {
+            println!("{}: {}", name, error);
+        }
+
+        if !errors.is_empty() {
+            failed_files += 1;
+        }
+    }
+
+    if failed_files > 0 {
+        return Err(anyhow!(
+            "{} of {} files failed validation",
+            failed_files,
+            files.len()
+        ));
+    }
+
+    log::info!("All {} files are valid", files.len());
+
+    Ok(())
+}
+
+fn print_stats(
+    inputs: &[String],
+    load: &LoadArgs,
+    names_only: bool,
+    json: bool,
+) -> Result<(), anyhow::Error> {
+    let load_options = load.load_options();
+    let mut files = Vec::new();
+    let mut skipped = Skipped::new(0, inputs.len());
+    let paths: Vec<&Path> = inputs.iter().map(Path::new).collect();
+    check_stdin_once(&paths)?;
+    let loaded = load_options.map_files(
+        &paths,
+        |path| MapItems::from_file_by_entry(path, &load_options),
+        load.keep_going,
+    );
+    for (input, loaded) in inputs.iter().zip(loaded) {
+        match loaded {
+            Ok(entries) => files.extend(entries),
+            Err(err) if load.keep_going => {
+                log::error!("Skipping {}: {:#}", input, err);
+                skipped.count += 1;
+            }
+            Err(err) => return Err(err.context(format!("Failed to read {}", input))),
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if json {
+        #[derive(serde::Serialize)]
+        struct FileStats<'a> {
+            name: &'a str,
+            #[serde(flatten)]
+            stats: MapStats,
+            #[serde(flatten)]
+            extent: MapExtent,
+        }
+
+        let all: Vec<FileStats> = files
+            .iter()
+            .map(|(name, map_items)| FileStats {
+                name,
+                stats: map_items.stats(),
+                extent: map_items.extent(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&all)?);
+
+        return skipped.into_result();
+    }
+
+    for (name, map_items) in files.iter() {
+        if names_only {
+            println!("{}", name);
+            continue;
+        }
+
+        let stats = map_items.stats();
+        println!(
+            "{}: {} items, {} points, {} lines",
+            name,
+            map_items.len(),
+            stats.points,
+            stats.lines
+        );
+    }
+
+    skipped.into_result()
+}
+
+fn init_logger(args: &Cli) {
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else if args.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "{}: {}",
+                record.level().as_str().to_lowercase(),
+                record.args()
+            )
+        })
+        .init();
+}
@@ -0,0 +1,459 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::map_items::{MapItem, MapItems, Point};
+
+/// An axis-aligned extent in map units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BoundingBox {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+impl BoundingBox {
+    pub fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max_y - self.min_y
+    }
+
+    /// Converts a `(min_x, min_y, width, height)` view box tuple, as returned
+    /// by `map_view_box`.
+    pub fn from_view_box(view_box: (f32, f32, f32, f32)) -> Self {
+        let (min_x, min_y, width, height) = view_box;
+
+        BoundingBox {
+            min_x,
+            min_y,
+            max_x: min_x + width,
+            max_y: min_y + height,
+        }
+    }
+
+    /// The smallest box covering both boxes.
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    /// Whether `(x, y)` lies inside the box or on its edge.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+
+    /// Finds the part of the segment from `from` to `to` that lies inside the
+    /// box in the XY plane, as a `(start, end)` pair of fractions along the
+    /// segment. Returns `None` if the segment misses the box. Uses the
+    /// Liang–Barsky algorithm.
+    pub fn clip_segment(&self, from: &Point, to: &Point) -> Option<(f32, f32)> {
+        let dx = to.x - from.x;
+        let dy = to.y - from.y;
+        let edges = [
+            (-dx, from.x - self.min_x),
+            (dx, self.max_x - from.x),
+            (-dy, from.y - self.min_y),
+            (dy, self.max_y - from.y),
+        ];
+
+        let (mut start, mut end) = (0.0f32, 1.0f32);
+        for (p, q) in edges {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                start = start.max(q / p);
+            } else {
+                end = end.min(q / p);
+            }
+        }
+
+        (start <= end).then_some((start, end))
+    }
+}
+
+/// A circle in the XY plane.
+#[derive(Debug, Clone, Copy)]
+pub struct Circle {
+    /// The center. Its Z is ignored.
+    pub center: Point,
+    pub radius: f32,
+}
+
+impl Circle {
+    pub fn new(x: f32, y: f32, radius: f32) -> Self {
+        Circle {
+            center: Point::new(x, y, 0.0),
+            radius,
+        }
+    }
+
+    /// Whether `point` lies inside the circle or on its edge, in the XY plane.
+    pub fn contains(&self, point: &Point) -> bool {
+        self.center.distance_2d(point) <= self.radius
+    }
+
+    /// The square the circle just fits in.
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            min_x: self.center.x - self.radius,
+            min_y: self.center.y - self.radius,
+            max_x: self.center.x + self.radius,
+            max_y: self.center.y + self.radius,
+        }
+    }
+
+    /// Like `BoundingBox::clip_segment`, the part of the segment from `from`
+    /// to `to` inside the circle as a `(start, end)` pair of fractions along
+    /// it, or `None` if the segment misses the circle.
+    pub fn clip_segment(&self, from: &Point, to: &Point) -> Option<(f32, f32)> {
+        let (dx, dy) = (to.x - from.x, to.y - from.y);
+        let (fx, fy) = (from.x - self.center.x, from.y - self.center.y);
+
+        let a = dx * dx + dy * dy;
+        let c = fx * fx + fy * fy - self.radius * self.radius;
+        if a == 0.0 {
+            return (c <= 0.0).then_some((0.0, 1.0));
+        }
+
+        let b = 2.0 * (fx * dx + fy * dy);
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let start = ((-b - root) / (2.0 * a)).max(0.0);
+        let end = ((-b + root) / (2.0 * a)).min(1.0);
+
+        (start <= end).then_some((start, end))
+    }
+}
+
+impl MapItem {
+    /// The XY extent of the item: a zero-size box at a point, or the box
+    /// spanning a line's two ends. Raw lines have no extent.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let corners = |a: &Point, b: &Point| BoundingBox {
+            min_x: a.x.min(b.x),
+            min_y: a.y.min(b.y),
+            max_x: a.x.max(b.x),
+            max_y: a.y.max(b.y),
+        };
+
+        match self {
+            MapItem::PointItem(point) => Some(corners(&point.point, &point.point)),
+            MapItem::LineItem(line) => Some(corners(&line.from, &line.to)),
+            MapItem::TextItem(text) => Some(corners(&text.point, &text.point)),
+            MapItem::Raw(_) => None,
+        }
+    }
+}
+
+impl MapItems {
+    /// The XY extent of every point and line endpoint, or `None` for a map
+    /// with nothing to draw. Unlike `map_view_box`, this is not widened when
+    /// the items are colinear.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.items
+            .iter()
+            .filter_map(MapItem::bounding_box)
+            .reduce(|bounds, item_bounds| bounds.union(&item_bounds))
+    }
+
+    /// Rescales X and Y so the map's bounding box fits the unit square,
+    /// starting at 0, 0, and returns the box it had. Both axes are scaled by
+    /// the same factor, one over the longer side, so shapes keep their
+    /// proportions and only the longer side spans 0 to 1. Z, line widths and
+    /// text sizes are unchanged. A point `(x, y)` goes back with
+    /// `min + (x, y) * max(width, height)`. A map without extent is only
+    /// moved to the origin, and an empty one returns a zero box.
+    pub fn normalize(&mut self) -> BoundingBox {
+        let Some(bounds) = self.bounding_box() else {
+            return BoundingBox::from_view_box((0.0, 0.0, 0.0, 0.0));
+        };
+
+        let extent = bounds.width().max(bounds.height());
+        let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+        self.map_points(|point| {
+            Point::new(
+                (point.x - bounds.min_x) * scale,
+                (point.y - bounds.min_y) * scale,
+                point.z,
+            )
+        });
+
+        bounds
+    }
+
+    /// Like `bounding_box`, over the point items alone, or `None` when there
+    /// are none.
+    pub fn bounds_of_points_only(&self) -> Option<BoundingBox> {
+        self.items
+            .iter()
+            .filter(|item| matches!(item, MapItem::PointItem(_)))
+            .filter_map(MapItem::bounding_box)
+            .reduce(|bounds, item_bounds| bounds.union(&item_bounds))
+    }
+
+    /// The average position of the point items, or `None` when there are
+    /// none. Lines, texts and raw lines don't count: a road drawn in many
+    /// short segments would pull the centre towards itself.
+    pub fn centroid(&self) -> Option<Point> {
+        let (count, [x, y, z]) = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.point),
+                _ => None,
+            })
+            .fold((0usize, [0f64; 3]), |(count, [x, y, z]), point| {
+                (
+                    count + 1,
+                    [x + point.x as f64, y + point.y as f64, z + point.z as f64],
+                )
+            });
+        if count == 0 {
+            return None;
+        }
+
+        let count = count as f64;
+        Some(Point::new(
+            (x / count) as f32,
+            (y / count) as f32,
+            (z / count) as f32,
+        ))
+    }
+
+    /// Splits the map into a grid of `cols` by `rows` equal cells over its
+    /// bounding box, returned row by row from the lowest Y, each row from the
+    /// lowest X. Cells include their lower edges, and the last row and column
+    /// their upper ones too, so a point or text lands in exactly one cell. A
+    /// line is copied whole, not clipped, into every cell its bounding box
+    /// overlaps, so a diagonal line can also land in cells it only passes
+    /// near. Raw lines have no extent and are left out. Every cell of an
+    /// empty map is empty, and a grid without cells gives none.
+    pub fn split_at_bbox_grid(&self, cols: usize, rows: usize) -> Vec<MapItems> {
+        let mut cells = vec![MapItems { items: vec![] }; cols * rows];
+        let Some(bounds) = self.bounding_box().filter(|_| !cells.is_empty()) else {
+            return cells;
+        };
+
+        let cell = |value: f32, min: f32, extent: f32, count: usize| {
+            if extent <= 0.0 {
+                return 0;
+            }
+            let index = ((value - min) / extent * count as f32).floor();
+            (index.max(0.0) as usize).min(count - 1)
+        };
+        for item in &self.items {
+            let Some(item_bounds) = item.bounding_box() else {
+                continue;
+            };
+
+            let columns = cell(item_bounds.min_x, bounds.min_x, bounds.width(), cols)
+                ..=cell(item_bounds.max_x, bounds.min_x, bounds.width(), cols);
+            for row in cell(item_bounds.min_y, bounds.min_y, bounds.height(), rows)
+                ..=cell(item_bounds.max_y, bounds.min_y, bounds.height(), rows)
+            {
+                for col in columns.clone() {
+                    cells[row * cols + col].items.push(item.clone());
+                }
+            }
+        }
+
+        cells
+    }
+
+    /// The lowest and highest Z of every point and line endpoint, or `None`
+    /// for a map with nothing to draw.
+    pub fn z_range(&self) -> Option<(f32, f32)> {
+        self.items
+            .iter()
+            .flat_map(|item| match item {
+                MapItem::PointItem(point) => vec![point.point.z],
+                MapItem::LineItem(line) => vec![line.from.z, line.to.z],
+                MapItem::TextItem(text) => vec![text.point.z],
+                MapItem::Raw(_) => vec![],
+            })
+            .fold(None, |range, z| {
+                let (min, max) = range.unwrap_or((z, z));
+                Some((min.min(z), max.max(z)))
+            })
+    }
+}
+
+impl FromStr for BoundingBox {
+    type Err = anyhow::Error;
+
+    /// Parses `min_x,min_y,max_x,max_y`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()?;
+
+        let [min_x, min_y, max_x, max_y] = values[..] else {
+            return Err(anyhow!("Expected min_x,min_y,max_x,max_y but got {}", s));
+        };
+        if min_x > max_x || min_y > max_y {
+            return Err(anyhow!("The minimum corner of {} is past the maximum", s));
+        }
+
+        Ok(BoundingBox {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_bounds::{BoundingBox, Circle},
+        map_items::{MapItem, MapItems, Point},
+    };
+
+    #[test]
+    fn bounding_box_covers_points_and_lines() {
+        let map_items: MapItems = "P 5.0, -2.0, 0.0, 0, 0, 0, 2, Bank
+L -1.0, 3.0, 0.0, 4.0, 8.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items.bounding_box(),
+            Some(BoundingBox {
+                min_x: -1.0,
+                min_y: -2.0,
+                max_x: 5.0,
+                max_y: 8.0,
+            })
+        );
+        assert_eq!(MapItems { items: vec![] }.bounding_box(), None);
+    }
+
+    #[test]
+    fn normalizing_into_the_unit_square() {
+        let mut map_items: MapItems = "P 10.0, 20.0, 5.0, 0, 0, 0, 2, Bank
+L 10.0, 70.0, 0.0, 210.0, 20.0, 3.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let bounds = map_items.normalize();
+        assert_eq!(bounds, "10,20,210,70".parse().unwrap());
+        assert_eq!(
+            map_items.bounding_box(),
+            Some("0,0,1,0.25".parse().unwrap())
+        );
+        let mut written = Vec::new();
+        map_items.write_to(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "P 0, 0, 5, 0, 0, 0, 2, Bank\nL 0, 0.25, 0, 1, 0, 3, 0, 0, 0\n"
+        );
+
+        let mut empty = MapItems { items: vec![] };
+        assert_eq!(empty.normalize(), "0,0,0,0".parse().unwrap());
+    }
+
+    #[test]
+    fn centroid_of_the_points() {
+        let map_items: MapItems = "P 4.0, -2.0, 10.0, 0, 0, 0, 2, Bank
+P -2.0, 6.0, 20.0, 0, 0, 0, 2, Inn
+P 7.0, 2.0, 0.0, 0, 0, 0, 2, Gate
+L 100.0, 100.0, 0.0, 200.0, 200.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(map_items.centroid(), Some(Point::new(3.0, 2.0, 10.0)));
+
+        let lines_only: MapItems = "L 0.0, 0.0, 0.0, 2.0, 2.0, 0.0, 0, 0, 0".parse().unwrap();
+        assert_eq!(lines_only.centroid(), None);
+    }
+
+    #[test]
+    fn bounding_box_of_each_item() {
+        let map_items: MapItems = "P 5.0, -2.0, 0.0, 0, 0, 0, 2, Bank
+L 4.0, 3.0, 0.0, -1.0, 8.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items[0].bounding_box(),
+            Some("5,-2,5,-2".parse().unwrap())
+        );
+        assert_eq!(
+            map_items[1].bounding_box(),
+            Some("-1,3,4,8".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn splitting_into_a_grid() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 0, 0, 0, 2, A
+P 10.0, 0.0, 0.0, 0, 0, 0, 2, B
+P 0.0, 10.0, 0.0, 0, 0, 0, 2, C
+P 10.0, 10.0, 0.0, 0, 0, 0, 2, D
+L 1.0, 1.0, 0.0, 9.0, 2.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let cells = map_items.split_at_bbox_grid(2, 2);
+        let labels: Vec<Vec<_>> = cells
+            .iter()
+            .map(|cell| {
+                cell.items
+                    .iter()
+                    .filter_map(|item| match item {
+                        MapItem::PointItem(point) => Some(point.label.as_str()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(labels, [["A"], ["B"], ["C"], ["D"]]);
+        // The line spans both bottom cells and is in each.
+        let sizes: Vec<_> = cells.iter().map(MapItems::len).collect();
+        assert_eq!(sizes, [2, 2, 1, 1]);
+
+        assert!(map_items.split_at_bbox_grid(0, 3).is_empty());
+        assert_eq!(map_items.split_at_bbox_grid(1, 1)[0].len(), map_items.len());
+    }
+
+    #[test]
+    fn clipping_segments_to_a_circle() {
+        let circle = Circle::new(0.0, 0.0, 5.0);
+        let point = |x, y| Point::new(x, y, 0.0);
+
+        assert_eq!(
+            circle.clip_segment(&point(-10.0, 0.0), &point(10.0, 0.0)),
+            Some((0.25, 0.75))
+        );
+        assert_eq!(
+            circle.clip_segment(&point(0.0, 0.0), &point(0.0, 10.0)),
+            Some((0.0, 0.5))
+        );
+        assert_eq!(
+            circle.clip_segment(&point(-10.0, 6.0), &point(10.0, 6.0)),
+            None
+        );
+        assert_eq!(
+            circle.clip_segment(&point(6.0, 0.0), &point(10.0, 0.0)),
+            None
+        );
+        assert_eq!(circle.bounding_box(), "-5,-5,5,5".parse().unwrap());
+    }
+}
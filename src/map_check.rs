@@ -0,0 +1,520 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::BufRead,
+    path::Path,
+};
+
+use crate::{
+    map_bounds::BoundingBox,
+    map_directives::Directives,
+    map_items::{
+        is_zip_path, open_file, open_map_file, read_zip_entries, InputEncoding, LineItem, MapItem,
+        MapItems, Point, PointItem,
+    },
+};
+
+/// A line that failed to parse.
+#[derive(Debug, Clone)]
+pub struct LineError {
+    /// The 1-based line number.
+    pub line_number: usize,
+    pub line: String,
+    pub message: String,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}: {:?}",
+            self.line_number, self.message, self.line
+        )
+    }
+}
+
+/// Parses every line of a map, decoded from `encoding`, and collects the
+/// lines that fail, rather than stopping at the first one. Blank lines are
+/// not errors, and `#` lines are read as comments and directives.
+pub fn check_reader(
+    reader: impl BufRead,
+    encoding: InputEncoding,
+) -> Result<Vec<LineError>, anyhow::Error> {
+    let mut errors = Vec::new();
+    let mut directives = Directives::default();
+
+    for (index, bytes) in reader.split(b'\n').enumerate() {
+        let bytes = bytes?;
+        let Some(line) = encoding.decode_line(&bytes) else {
+            errors.push(LineError {
+                line_number: index + 1,
+                line: String::from_utf8_lossy(&bytes).into_owned(),
+                message: String::from("Not valid text in the input encoding"),
+            });
+            continue;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            directives.read(comment);
+            continue;
+        }
+
+        if let Err(err) = directives.parse(&line, |line| MapItem::parse_item(line, false)) {
+            errors.push(LineError {
+                line_number: index + 1,
+                line,
+                message: err.to_string(),
+            });
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Checks a single map file with `check_reader`.
+pub fn check_file(path: &Path, encoding: InputEncoding) -> Result<Vec<LineError>, anyhow::Error> {
+    check_reader(open_map_file(path)?, encoding)
+}
+
+/// Checks a map file like `check_file`, or each entry of a zip archive
+/// separately, with entries named as by `MapItems::from_file_by_entry`.
+pub fn check_file_by_entry(
+    path: &Path,
+    encoding: InputEncoding,
+) -> Result<Vec<(String, Vec<LineError>)>, anyhow::Error> {
+    if !is_zip_path(path) {
+        return Ok(vec![(
+            path.display().to_string(),
+            check_file(path, encoding)?,
+        )]);
+    }
+
+    let mut entries = Vec::new();
+    read_zip_entries(open_file(path)?, |name, entry| {
+        entries.push((
+            format!("{}/{}", path.display(), name),
+            check_reader(entry, encoding)?,
+        ));
+        Ok(())
+    })?;
+
+    Ok(entries)
+}
+
+/// Counts of each kind of item in a map, and of its colors and labels.
+///
+/// With the `serde` feature this serializes with the field names as keys.
+/// New fields may be added, but these won't change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MapStats {
+    pub points: usize,
+    pub lines: usize,
+    /// Text annotations, which have no marker.
+    pub texts: usize,
+    /// Unparsed lines kept by `LoadOptions::keep_raw_lines`.
+    pub raw: usize,
+    /// How many distinct colors the points and lines use.
+    pub colors: usize,
+    /// How many distinct non-empty point labels there are.
+    pub labels: usize,
+}
+
+/// The extent of a map, kept apart from `MapStats` so that the counts can
+/// be compared exactly.
+///
+/// With the `serde` feature this serializes with `bounding_box` as an object
+/// of `min_x`, `min_y`, `max_x` and `max_y`, and `z_range` as a `[min, max]`
+/// pair. Both are null for a map without points or lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MapExtent {
+    /// The XY extent, as `MapItems::bounding_box` finds it.
+    pub bounding_box: Option<BoundingBox>,
+    /// The lowest and highest Z, as `MapItems::z_range` finds them.
+    pub z_range: Option<(f32, f32)>,
+}
+
+/// Two line ends that nearly meet: `distance` apart, but not at the same
+/// spot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EndpointGap {
+    pub a: Point,
+    pub b: Point,
+    pub distance: f32,
+}
+
+impl MapItems {
+    pub fn stats(&self) -> MapStats {
+        let mut stats = MapStats::default();
+        let mut labels = HashSet::new();
+        for item in self.items.iter() {
+            match item {
+                MapItem::PointItem(point) => {
+                    stats.points += 1;
+                    if !point.label.is_empty() {
+                        labels.insert(point.label.as_str());
+                    }
+                }
+                MapItem::LineItem(_) => stats.lines += 1,
+                MapItem::TextItem(_) => stats.texts += 1,
+                MapItem::Raw(_) => stats.raw += 1,
+            }
+        }
+
+        stats.colors = self.colors().len();
+        stats.labels = labels.len();
+        stats
+    }
+
+    /// The XY extent and Z range of the points and lines.
+    pub fn extent(&self) -> MapExtent {
+        MapExtent {
+            bounding_box: self.bounding_box(),
+            z_range: self.z_range(),
+        }
+    }
+
+    /// Finds groups of points that share the exact same X and Y coordinates,
+    /// which draw as a single marker with overlapping labels. Groups are
+    /// returned in the order their first point appears.
+    pub fn stacked_points(&self) -> Vec<Vec<&PointItem>> {
+        let mut groups: Vec<Vec<&PointItem>> = Vec::new();
+        let mut group_index: HashMap<(u32, u32), usize> = HashMap::new();
+
+        for item in self.items.iter() {
+            let MapItem::PointItem(point) = item else {
+                continue;
+            };
+
+            let key = (point.point.x.to_bits(), point.point.y.to_bits());
+            let index = *group_index.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[index].push(point);
+        }
+
+        groups.retain(|group| group.len() > 1);
+        groups
+    }
+
+    /// The lines whose ends are more than `max_z_delta` apart in Z, which in
+    /// a zone of several floors is usually a wall joined across them by
+    /// mistake.
+    pub fn steep_lines(&self, max_z_delta: f32) -> Vec<&LineItem> {
+        self.items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::LineItem(line) if line.z_delta() > max_z_delta => Some(line),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Removes the lines `steep_lines` finds, returning how many there were.
+    pub fn drop_steep_lines(&mut self, max_z_delta: f32) -> usize {
+        let before = self.len();
+        self.retain(
+            |item| !matches!(item, MapItem::LineItem(line) if line.z_delta() > max_z_delta),
+        );
+        before - self.len()
+    }
+
+    /// Finds the pairs of line ends that are no more than `tolerance` apart
+    /// in X, Y and Z without being exactly equal, which in walls meant to
+    /// join leave gaps that rendering and pathfinding see through. Each pair
+    /// is reported once, in the order their ends first appear. Point items
+    /// don't count.
+    pub fn endpoint_gaps(&self, tolerance: f32) -> Vec<EndpointGap> {
+        let ends = self.distinct_line_ends();
+        near_pairs(&ends, tolerance)
+            .into_iter()
+            .map(|(a, b)| EndpointGap {
+                a: ends[a],
+                b: ends[b],
+                distance: ends[a].distance_3d(&ends[b]),
+            })
+            .collect()
+    }
+
+    /// Closes the gaps `endpoint_gaps` finds by moving each line end onto the
+    /// first of the ends it is near. Nearness chains, so a run of ends each
+    /// within `tolerance` of the next all move onto the first of them.
+    /// Returns how many line ends moved.
+    pub fn snap_endpoints(&mut self, tolerance: f32) -> usize {
+        let ends = self.distinct_line_ends();
+
+        // Union-find over the ends, keeping the earliest end as each group's
+        // root.
+        let mut roots: Vec<usize> = (0..ends.len()).collect();
+        fn root(roots: &mut [usize], mut index: usize) -> usize {
+            while roots[index] != index {
+                roots[index] = roots[roots[index]];
+                index = roots[index];
+            }
+            index
+        }
+        for (a, b) in near_pairs(&ends, tolerance) {
+            let (a, b) = (root(&mut roots, a), root(&mut roots, b));
+            roots[a.max(b)] = a.min(b);
+        }
+
+        let targets: HashMap<Point, Point> = (0..ends.len())
+            .filter_map(|index| {
+                let target = root(&mut roots, index);
+                (target != index).then(|| (ends[index], ends[target]))
+            })
+            .collect();
+
+        let mut moved = 0;
+        let mut snap = |end: &mut Point| {
+            if let Some(target) = targets.get(end) {
+                *end = *target;
+                moved += 1;
+            }
+        };
+        for item in self.items.iter_mut() {
+            if let MapItem::LineItem(line) = item {
+                snap(&mut line.from);
+                snap(&mut line.to);
+            }
+        }
+
+        moved
+    }
+
+    /// Every position a line starts or ends at, once each, in the order they
+    /// first appear.
+    fn distinct_line_ends(&self) -> Vec<Point> {
+        let mut seen = HashSet::new();
+        self.items
+            .iter()
+            .flat_map(|item| match item {
+                MapItem::LineItem(line) => vec![line.from, line.to],
+                _ => vec![],
+            })
+            .filter(|end| seen.insert(*end))
+            .collect()
+    }
+}
+
+/// The index pairs of the `points` no more than `tolerance` apart, each pair
+/// lowest index first and sorted. Points are bucketed into cubes of the
+/// tolerance, so only neighbouring cubes are compared.
+fn near_pairs(points: &[Point], tolerance: f32) -> Vec<(usize, usize)> {
+    if tolerance.is_nan() || tolerance <= 0.0 {
+        return Vec::new();
+    }
+
+    let cube =
+        |point: &Point| [point.x, point.y, point.z].map(|value| (value / tolerance).floor() as i64);
+    let mut cubes: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    for (index, point) in points.iter().enumerate() {
+        cubes.entry(cube(point)).or_default().push(index);
+    }
+
+    let mut pairs = Vec::new();
+    for (a, point) in points.iter().enumerate() {
+        let [x, y, z] = cube(point);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(near) = cubes.get(&[x + dx, y + dy, z + dz]) else {
+                        continue;
+                    };
+                    pairs.extend(
+                        near.iter()
+                            .filter(|&&b| b > a && point.distance_3d(&points[b]) <= tolerance)
+                            .map(|&b| (a, b)),
+                    );
+                }
+            }
+        }
+    }
+
+    pairs.sort_unstable();
+    pairs
+}
+
+/// How many times larger one file's extent must be than another's before
+/// `mixed_scales` reports them.
+pub(crate) const MIXED_SCALE_RATIO: f32 = 100.0;
+
+/// Compares the extents of several files that are being merged, where each
+/// extent is the larger of a file's width and height. Returns the indexes of
+/// the largest and the smallest file if the largest is at least
+/// `MIXED_SCALE_RATIO` times the size of the smallest, which usually means the
+/// files were authored in different units. Files with no extent are ignored.
+pub(crate) fn mixed_scales(extents: &[Option<f32>]) -> Option<(usize, usize)> {
+    let sized = || {
+        extents
+            .iter()
+            .enumerate()
+            .filter_map(|(index, extent)| extent.filter(|extent| *extent > 0.0).map(|e| (index, e)))
+    };
+
+    let (largest, max) = sized().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let (smallest, min) = sized().min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    (max >= min * MIXED_SCALE_RATIO).then_some((largest, smallest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_check::{check_reader, mixed_scales},
+        map_items::{InputEncoding, MapItem, MapItems, Point},
+    };
+
+    #[test]
+    fn finding_and_snapping_endpoint_gaps() {
+        let mut map_items: MapItems = "L 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0, 0, 0
+L 10.05, 0.0, 0.0, 10.0, 10.0, 0.0, 0, 0, 0
+L 10.0, 10.0, 0.0, 0.0, 10.0, 0.0, 0, 0, 0
+L 0.0, 10.0, 0.0, 0.0, 0.5, 0.0, 0, 0, 0
+P 0.0, 0.05, 0.0, 0, 0, 0, 2, Bank"
+            .parse()
+            .unwrap();
+
+        // The corner at 10, 10 meets exactly, and 0, 0.5 is too far.
+        let gaps = map_items.endpoint_gaps(0.1);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].a, Point::new(10.0, 0.0, 0.0));
+        assert_eq!(gaps[0].b, Point::new(10.05, 0.0, 0.0));
+        assert!((gaps[0].distance - 0.05).abs() < 1e-5);
+        assert_eq!(map_items.endpoint_gaps(1.0).len(), 2);
+
+        assert_eq!(map_items.snap_endpoints(0.1), 1);
+        assert!(map_items.endpoint_gaps(0.1).is_empty());
+        let MapItem::LineItem(line) = &map_items.items[1] else {
+            panic!("Expected a line");
+        };
+        assert_eq!(line.from, Point::new(10.0, 0.0, 0.0));
+        assert!(matches!(&map_items.items[4], MapItem::PointItem(point) if point.point.y == 0.05));
+    }
+
+    #[test]
+    fn check_collects_every_bad_line() {
+        let map = "P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank
+
+L 1.0, 2.0
+L 1.0, 2.0, 0.0, 5.0, 5.0, 0.0, 0, 0, 0
+X nonsense
+";
+
+        let errors = check_reader(map.as_bytes(), InputEncoding::default()).unwrap();
+
+        let line_numbers: Vec<usize> = errors.iter().map(|err| err.line_number).collect();
+        assert_eq!(line_numbers, [3, 5]);
+        assert_eq!(errors[1].line, "X nonsense");
+    }
+
+    #[test]
+    fn check_decodes_the_input_encoding() {
+        let map = encoding_rs::WINDOWS_1252
+            .encode("P 1.0, 2.0, 0.0, 0, 0, 0, 2, Caf\u{e9}\n")
+            .0;
+
+        let errors = check_reader(&map[..], InputEncoding::default()).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Not valid text in the input encoding");
+
+        let latin = InputEncoding(encoding_rs::WINDOWS_1252);
+        assert!(check_reader(&map[..], latin).unwrap().is_empty());
+    }
+
+    #[test]
+    fn summarizing_a_map() {
+        let map_items: MapItems = "P 1.0, 2.0, 5.0, 255, 0, 0, 2, Bank
+P 3.0, 2.0, 0.0, 255, 0, 0, 2, Bank
+P 3.0, 4.0, 0.0, 0, 0, 255, 2, Inn
+L 0.0, 0.0, -5.0, 3.0, 8.0, 0.0, 255, 0, 0"
+            .parse()
+            .unwrap();
+
+        let stats = map_items.stats();
+        assert_eq!((stats.points, stats.lines, stats.raw), (3, 1, 0));
+        assert_eq!((stats.colors, stats.labels), (2, 2));
+        let extent = map_items.extent();
+        assert_eq!(extent.bounding_box, Some("0,0,3,8".parse().unwrap()));
+        assert_eq!(extent.z_range, Some((-5.0, 5.0)));
+    }
+
+    #[test]
+    fn finds_stacked_points() {
+        let map_items: MapItems = "P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank
+P 5.0, 5.0, 0.0, 0, 0, 0, 2, Alone
+P 1.0, 2.0, 10.0, 0, 0, 0, 2, Banker
+L 1.0, 2.0, 0.0, 5.0, 5.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let stacked = map_items.stacked_points();
+
+        assert_eq!(stacked.len(), 1);
+        let labels: Vec<&str> = stacked[0]
+            .iter()
+            .map(|point| point.label.as_str())
+            .collect();
+        assert_eq!(labels, ["Bank", "Banker"]);
+    }
+
+    #[test]
+    fn detects_mixed_scales() {
+        assert_eq!(mixed_scales(&[Some(10.0), Some(500.0), None]), None);
+        assert_eq!(
+            mixed_scales(&[Some(10.0), Some(5000.0), Some(20.0), Some(0.0)]),
+            Some((1, 0))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn checks_zip_entries_separately() {
+        use std::io::Write;
+
+        use crate::map_check::check_file_by_entry;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("maps.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let file_options = zip::write::SimpleFileOptions::default();
+        writer.start_file("b.txt", file_options).unwrap();
+        writer.write_all(b"L 1.0, 2.0\n").unwrap();
+        writer.start_file("a.txt", file_options).unwrap();
+        writer
+            .write_all(b"P 1.0, 0.0, 0.0, 0, 0, 0, 2, First\n")
+            .unwrap();
+        writer.finish().unwrap();
+
+        let entries = check_file_by_entry(&path, InputEncoding::default()).unwrap();
+
+        let summary: Vec<(bool, usize)> = entries
+            .iter()
+            .map(|(name, errors)| (name.ends_with(".zip/a.txt"), errors.len()))
+            .collect();
+        assert_eq!(summary, [(true, 0), (false, 1)]);
+    }
+
+    #[test]
+    fn finding_steep_lines() {
+        let mut map_items: MapItems = "L 0.0, 0.0, 0.0, 10.0, 0.0, 5.0, 0, 0, 0
+L 10.0, 0.0, 5.0, 10.0, 10.0, 205.0, 255, 0, 0
+L 10.0, 10.0, 205.0, 0.0, 10.0, 210.0, 0, 0, 0
+P 0.0, 0.0, 500.0, 0, 0, 0, 2, Bank"
+            .parse()
+            .unwrap();
+
+        let steep = map_items.steep_lines(50.0);
+        assert_eq!(steep.len(), 1);
+        assert_eq!(steep[0].to.z, 205.0);
+        assert!(map_items.steep_lines(200.0).is_empty());
+
+        assert_eq!(map_items.drop_steep_lines(50.0), 1);
+        assert_eq!(map_items.len(), 3);
+        assert!(map_items.steep_lines(50.0).is_empty());
+    }
+}
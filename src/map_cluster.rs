@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use crate::map_items::{LineItem, MapItem, MapItems, Point, PointItem};
+
+/// A group of nearby points, found by `MapItems::point_clusters`.
+#[derive(Debug, Clone)]
+pub struct PointCluster {
+    /// The mean position of the members.
+    pub center: Point,
+    /// Indexes into `MapItems::items` of the member points, in map order.
+    pub members: Vec<usize>,
+}
+
+/// One cell of `MapItems::point_density`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DensityCell {
+    /// The cell's corner with the lowest coordinates. Cells are aligned to
+    /// multiples of the cell size.
+    pub min_x: f32,
+    pub min_y: f32,
+    /// How many points fall in the cell.
+    pub count: usize,
+}
+
+impl MapItems {
+    /// Joins each run of points sharing a label with lines, from each point to
+    /// the next one with that label in the order they appear, in the color of
+    /// the first. The lines are added after the existing items. Points with an
+    /// empty label are never joined.
+    pub fn connect_by_label(&self) -> MapItems {
+        let mut routes: Vec<Vec<&PointItem>> = Vec::new();
+        let mut route_index: HashMap<&str, usize> = HashMap::new();
+
+        for item in self.items.iter() {
+            let MapItem::PointItem(point) = item else {
+                continue;
+            };
+            if point.label.is_empty() {
+                continue;
+            }
+
+            let route = *route_index.entry(&point.label).or_insert_with(|| {
+                routes.push(Vec::new());
+                routes.len() - 1
+            });
+            routes[route].push(point);
+        }
+
+        let lines = routes.iter().flat_map(|route| {
+            route.windows(2).map(|pair| {
+                MapItem::LineItem(LineItem::new(pair[0].point, pair[1].point, route[0].color))
+            })
+        });
+
+        self.items.iter().cloned().chain(lines).collect()
+    }
+
+    /// Counts the point items in each `cell_size` square of the XY plane.
+    /// Only cells with at least one point are returned, ordered by row and
+    /// then column.
+    pub fn point_density(&self, cell_size: f32) -> Vec<DensityCell> {
+        self.point_density_by(cell_size, |point| (point.x, point.y))
+    }
+
+    /// Like `point_density`, with the grid laid over the `(x, y)` returned by
+    /// `position` instead.
+    pub(crate) fn point_density_by(
+        &self,
+        cell_size: f32,
+        position: impl Fn(&Point) -> (f32, f32),
+    ) -> Vec<DensityCell> {
+        let mut counts: HashMap<(i64, i64), usize> = HashMap::new();
+
+        for item in self.items.iter() {
+            if let MapItem::PointItem(point) = item {
+                *counts
+                    .entry(grid_cell(position(&point.point), cell_size))
+                    .or_default() += 1;
+            }
+        }
+
+        let mut cells: Vec<((i64, i64), usize)> = counts.into_iter().collect();
+        cells.sort_by_key(|((column, row), _)| (*row, *column));
+
+        cells
+            .into_iter()
+            .map(|((column, row), count)| DensityCell {
+                min_x: column as f32 * cell_size,
+                min_y: row as f32 * cell_size,
+                count,
+            })
+            .collect()
+    }
+
+    /// Groups the point items into clusters by snapping them to a grid of
+    /// `radius` sized cells in the XY plane, so points closer than `radius`
+    /// usually, but not always, share a cluster. Every point belongs to
+    /// exactly one cluster, which may hold only that point. Clusters are
+    /// returned in the order their first member appears.
+    pub fn point_clusters(&self, radius: f32) -> Vec<PointCluster> {
+        self.point_clusters_by(radius, |point| (point.x, point.y))
+    }
+
+    /// Like `point_clusters`, with the grid laid over the `(x, y)` returned by
+    /// `position` instead.
+    pub(crate) fn point_clusters_by(
+        &self,
+        radius: f32,
+        position: impl Fn(&Point) -> (f32, f32),
+    ) -> Vec<PointCluster> {
+        let mut clusters: Vec<PointCluster> = Vec::new();
+        let mut cell_index: HashMap<(i64, i64), usize> = HashMap::new();
+
+        for (index, item) in self.items.iter().enumerate() {
+            let MapItem::PointItem(point) = item else {
+                continue;
+            };
+
+            let cell = grid_cell(position(&point.point), radius);
+            let cluster = *cell_index.entry(cell).or_insert_with(|| {
+                clusters.push(PointCluster {
+                    center: Point::new(0.0, 0.0, 0.0),
+                    members: Vec::new(),
+                });
+                clusters.len() - 1
+            });
+            clusters[cluster].members.push(index);
+        }
+
+        for cluster in clusters.iter_mut() {
+            let points = cluster
+                .members
+                .iter()
+                .filter_map(|index| match &self.items[*index] {
+                    MapItem::PointItem(point) => Some(point.point),
+                    _ => None,
+                });
+            let (sum, count) = points.fold((Point::new(0.0, 0.0, 0.0), 0.0), |(sum, count), p| {
+                (sum + p, count + 1.0)
+            });
+            cluster.center = Point::new(sum.x / count, sum.y / count, sum.z / count);
+        }
+
+        clusters
+    }
+}
+
+/// The `(column, row)` of the `cell_size` grid cell holding `(x, y)`.
+fn grid_cell((x, y): (f32, f32), cell_size: f32) -> (i64, i64) {
+    (
+        (x / cell_size).floor() as i64,
+        (y / cell_size).floor() as i64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_cluster::DensityCell,
+        map_items::{MapItem, MapItems},
+    };
+
+    #[test]
+    fn nearby_points_share_a_cluster() {
+        let map_items: MapItems = "P 1.0, 1.0, 0.0, 0, 0, 0, 2, A
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0
+P 50.0, 50.0, 0.0, 0, 0, 0, 2, Far
+P 3.0, 5.0, 4.0, 0, 0, 0, 2, B"
+            .parse()
+            .unwrap();
+
+        let clusters = map_items.point_clusters(10.0);
+        let members: Vec<&[usize]> = clusters.iter().map(|c| c.members.as_slice()).collect();
+        assert_eq!(members, [&[0, 3][..], &[2][..]]);
+
+        let center = clusters[0].center;
+        assert_eq!((center.x, center.y, center.z), (2.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn connects_points_sharing_a_label() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 255, 0, 0, 2, Route
+P 5.0, 5.0, 0.0, 0, 0, 0, 2, Alone
+P 1.0, 0.0, 0.0, 0, 255, 0, 2, Route
+P 1.0, 1.0, 2.0, 0, 0, 255, 2, Route"
+            .parse()
+            .unwrap();
+
+        let connected = map_items.connect_by_label();
+        let lines: Vec<_> = connected
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::LineItem(line) => Some((line.from.x, line.to.x, line.to.z, line.color.r)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(connected.items.len(), 6);
+        assert_eq!(lines, [(0.0, 1.0, 0.0, 255), (1.0, 1.0, 2.0, 255)]);
+    }
+
+    #[test]
+    fn point_density_counts_per_cell() {
+        let map_items: MapItems = "P 1.0, 1.0, 0.0, 0, 0, 0, 2, A
+P 9.0, 2.0, 0.0, 0, 0, 0, 2, B
+P -1.0, 15.0, 0.0, 0, 0, 0, 2, C
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items.point_density(10.0),
+            [
+                DensityCell {
+                    min_x: 0.0,
+                    min_y: 0.0,
+                    count: 2
+                },
+                DensityCell {
+                    min_x: -10.0,
+                    min_y: 10.0,
+                    count: 1
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+
+#[cfg(feature = "serde")]
+use crate::map_items::deserialize_from_str;
+use crate::map_items::{Color, MapItem, MapItems};
+
+/// What `MapItems::seeded_colors` derives each item's color from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SeededColors {
+    /// Points with the same label get the same color. Lines are unchanged.
+    Label,
+    /// Every item gets its own color, from its position in the map.
+    Item,
+}
+
+impl FromStr for SeededColors {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "label" => Ok(SeededColors::Label),
+            "item" => Ok(SeededColors::Item),
+            _ => Err(anyhow!("Unknown color source {}", s)),
+        }
+    }
+}
+
+/// A gradient for coloring values, such as heatmap counts, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Colormap {
+    /// Dark purple through red and orange to pale yellow.
+    #[default]
+    Magma,
+    /// Dark blue through teal to yellow.
+    Viridis,
+    /// Black to white.
+    Grayscale,
+    /// Blue through white to red.
+    HotCold,
+}
+
+impl Colormap {
+    /// The colors the map blends between, from 0 to 1, evenly spaced.
+    fn stops(&self) -> &'static [(u8, u8, u8)] {
+        match self {
+            Colormap::Magma => &[
+                (40, 11, 84),
+                (120, 28, 109),
+                (187, 55, 84),
+                (237, 105, 37),
+                (251, 180, 26),
+                (252, 255, 164),
+            ],
+            Colormap::Viridis => &[
+                (68, 1, 84),
+                (59, 82, 139),
+                (33, 145, 140),
+                (94, 201, 98),
+                (253, 231, 37),
+            ],
+            Colormap::Grayscale => &[(0, 0, 0), (255, 255, 255)],
+            Colormap::HotCold => &[(0, 0, 255), (255, 255, 255), (255, 0, 0)],
+        }
+    }
+
+    /// The color at `t`, clamped to 0 to 1, blending the neighbouring stops.
+    pub fn sample(&self, t: f32) -> Color {
+        let stops = self.stops();
+        let position = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) } * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let blend = position - index as f32;
+
+        let (from, to) = (stops[index], stops[index + 1]);
+        let channel =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * blend).round() as u8;
+
+        Color::new(
+            channel(from.0, to.0),
+            channel(from.1, to.1),
+            channel(from.2, to.2),
+        )
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "magma" => Ok(Colormap::Magma),
+            "viridis" => Ok(Colormap::Viridis),
+            "grayscale" => Ok(Colormap::Grayscale),
+            "hot-cold" => Ok(Colormap::HotCold),
+            _ => Err(anyhow!("Unknown colormap {}", s)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Colormap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl MapItems {
+    /// Recolors items with distinct colors that only depend on `seed` and the
+    /// item's label or position, so that data without meaningful colors is
+    /// still easy to tell apart and renders the same way every time.
+    pub fn seeded_colors(&self, seed: u64, by: SeededColors) -> MapItems {
+        let mut map_items = self.clone();
+
+        for (index, item) in map_items.items.iter_mut().enumerate() {
+            let hash = match (by, &*item) {
+                (SeededColors::Label, MapItem::PointItem(point)) => {
+                    fnv1a(seed, point.label.as_bytes())
+                }
+                (SeededColors::Label, _) => continue,
+                (SeededColors::Item, _) => fnv1a(seed, &(index as u64).to_le_bytes()),
+            };
+
+            item.set_color(hashed_color(hash));
+        }
+
+        map_items
+    }
+
+    /// Recolors each item with the first color, in file order, within
+    /// `tolerance` of its own, measured by `Color::distance`. This folds
+    /// slightly-off duplicates such as 254, 0, 0 and 255, 0, 0 into one
+    /// color, so that grouping by color sees them as the same. A `tolerance`
+    /// of 0 only matches exactly equal colors and changes nothing.
+    pub fn merge_similar_colors(&mut self, tolerance: f32) {
+        let mut canonical: Vec<Color> = Vec::new();
+
+        for item in self.items.iter_mut() {
+            let Some(color) = item.color() else {
+                continue;
+            };
+
+            match canonical
+                .iter()
+                .find(|seen| seen.distance(&color) <= tolerance)
+            {
+                Some(seen) => item.set_color(*seen),
+                None => canonical.push(color),
+            }
+        }
+    }
+
+    /// The distinct colors of all the points and lines, sorted by red, then
+    /// green, then blue, for building legends and palettes.
+    pub fn colors(&self) -> Vec<Color> {
+        self.items
+            .iter()
+            .filter_map(MapItem::color)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Splits the map into one map per color, sorted by color as `colors`
+    /// sorts them, each holding that color's items in file order. Raw items
+    /// have no color and are left out.
+    pub fn partition_by_color(&self) -> Vec<(Color, MapItems)> {
+        let mut groups: BTreeMap<Color, Vec<MapItem>> = BTreeMap::new();
+        for item in &self.items {
+            if let Some(color) = item.color() {
+                groups.entry(color).or_default().push(item.clone());
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(color, items)| (color, MapItems { items }))
+            .collect()
+    }
+}
+
+/// `out_file` with the color's hex code added before its extension, so
+/// `map.png` for red becomes `map-ff0000.png`.
+pub fn color_path(out_file: &Path, color: Color) -> PathBuf {
+    let stem = out_file.file_stem().unwrap_or_default().to_string_lossy();
+    let hex = format!("{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+    let name = match out_file.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, hex, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, hex),
+    };
+
+    out_file.with_file_name(name)
+}
+
+/// Picks a saturated, fairly bright color from a hash.
+fn hashed_color(hash: u64) -> Color {
+    let hue = (hash % 360) as f32;
+    let saturation = 0.65 + ((hash >> 16) % 26) as f32 / 100.0;
+    let value = 0.75 + ((hash >> 32) % 21) as f32 / 100.0;
+
+    Color::from_hsv(hue, saturation, value)
+}
+
+/// 64-bit FNV-1a of `seed` followed by `bytes`. Unlike std's hashers, its
+/// output is fixed, so colors stay the same across Rust versions.
+pub(crate) fn fnv1a(seed: u64, bytes: &[u8]) -> u64 {
+    seed.to_le_bytes()
+        .iter()
+        .chain(bytes)
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        map_colors::{color_path, Colormap, SeededColors},
+        map_items::{Color, MapItem, MapItems},
+    };
+
+    fn colors(map_items: &MapItems) -> Vec<(u8, u8, u8)> {
+        map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.color),
+                MapItem::LineItem(line) => Some(line.color),
+                _ => None,
+            })
+            .map(|color| (color.r, color.g, color.b))
+            .collect()
+    }
+
+    #[test]
+    fn seeded_colors_are_stable_per_label() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 0, 0, 0, 2, Bank
+P 1.0, 0.0, 0.0, 0, 0, 0, 2, Inn
+P 2.0, 0.0, 0.0, 0, 0, 0, 2, Bank
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let by_label = colors(&map_items.seeded_colors(7, SeededColors::Label));
+        assert_eq!(by_label[0], by_label[2]);
+        assert_ne!(by_label[0], by_label[1]);
+        assert_eq!(by_label[3], (0, 0, 0));
+        assert_eq!(
+            by_label,
+            colors(&map_items.seeded_colors(7, SeededColors::Label))
+        );
+        assert_ne!(
+            by_label,
+            colors(&map_items.seeded_colors(8, SeededColors::Label))
+        );
+
+        let by_item = colors(&map_items.seeded_colors(7, SeededColors::Item));
+        assert_ne!(by_item[0], by_item[2]);
+        assert_ne!(by_item[3], (0, 0, 0));
+    }
+
+    #[test]
+    fn distinct_colors_are_sorted() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 255, 0, 0, 2, Bank
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 255
+P 1.0, 0.0, 0.0, 255, 0, 0, 2, Inn
+L 1.0, 0.0, 0.0, 2.0, 1.0, 0.0, 255, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items.colors(),
+            [Color::new(0, 0, 255), Color::new(255, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn partitioning_by_color() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 255, 0, 0, 2, Bank
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 255
+P 1.0, 0.0, 0.0, 255, 0, 0, 2, Inn
+L 1.0, 0.0, 0.0, 2.0, 1.0, 0.0, 255, 0, 0
+L 2.0, 0.0, 0.0, 3.0, 1.0, 0.0, 0, 0, 255"
+            .parse()
+            .unwrap();
+
+        let groups = map_items.partition_by_color();
+        let sizes: Vec<_> = groups
+            .iter()
+            .map(|(color, group)| (*color, group.len()))
+            .collect();
+        assert_eq!(
+            sizes,
+            [(Color::new(0, 0, 255), 2), (Color::new(255, 0, 0), 3)]
+        );
+        assert_eq!(
+            groups.iter().map(|(_, group)| group.len()).sum::<usize>(),
+            map_items.len()
+        );
+        assert!(groups
+            .iter()
+            .all(|(color, group)| group.colors() == [*color]));
+        assert_eq!(
+            color_path(Path::new("out/map.png"), Color::new(255, 0, 16)),
+            Path::new("out/map-ff0010.png")
+        );
+    }
+
+    #[test]
+    fn merging_similar_colors() {
+        let mut map_items: MapItems = "P 0.0, 0.0, 0.0, 254, 0, 0, 2, Bank
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 255, 1, 0
+P 1.0, 0.0, 0.0, 0, 0, 255, 2, Inn
+L 1.0, 0.0, 0.0, 2.0, 1.0, 0.0, 250, 0, 0"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            Color::new(254, 0, 0).distance(&Color::new(255, 1, 0)),
+            2f32.sqrt()
+        );
+
+        map_items.merge_similar_colors(0.0);
+        assert_eq!(map_items.colors().len(), 4);
+
+        map_items.merge_similar_colors(2.0);
+        assert_eq!(
+            colors(&map_items),
+            [(254, 0, 0), (254, 0, 0), (0, 0, 255), (250, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn sampling_colormaps() {
+        assert_eq!(Colormap::Grayscale.sample(0.0), Color::new(0, 0, 0));
+        assert_eq!(Colormap::Grayscale.sample(0.5), Color::new(128, 128, 128));
+        assert_eq!(Colormap::Grayscale.sample(1.0), Color::new(255, 255, 255));
+
+        assert_eq!(Colormap::HotCold.sample(0.0), Color::new(0, 0, 255));
+        assert_eq!(Colormap::HotCold.sample(0.5), Color::new(255, 255, 255));
+        assert_eq!(Colormap::HotCold.sample(1.0), Color::new(255, 0, 0));
+
+        assert_eq!(Colormap::Viridis.sample(0.0), Color::new(68, 1, 84));
+        assert_eq!(Colormap::Viridis.sample(0.5), Color::new(33, 145, 140));
+        assert_eq!(Colormap::Viridis.sample(1.0), Color::new(253, 231, 37));
+
+        // Out of range values clamp to the ends.
+        assert_eq!(Colormap::Magma.sample(-1.0), Colormap::Magma.sample(0.0));
+        assert_eq!(Colormap::Magma.sample(2.0), Color::new(252, 255, 164));
+        assert_eq!("hot-cold".parse::<Colormap>().unwrap(), Colormap::HotCold);
+        assert!("rainbow".parse::<Colormap>().is_err());
+    }
+}
@@ -0,0 +1,701 @@
+//! Reading and writing maps in formats other than the game's own: CSV, and
+//! GeoJSON, SVG and waypoint lists for output only. `MapFormat` picks the
+//! format from a path's extension.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{BufRead, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+
+use crate::map_items::{
+    create_file, is_zip_path, Color, LineItem, LoadOptions, MapItem, MapItems, Point, PointItem,
+    TextItem, DEFAULT_POINT_TYPE,
+};
+
+/// The file formats a map can be converted between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MapFormat {
+    /// The game's own line format, in `.map` or `.txt` files or, for input
+    /// only, a `.zip` archive of them.
+    Map,
+    /// One item per row under a
+    /// `type,x,y,z,to_x,to_y,to_z,r,g,b,label,sub_label,point_type,width`
+    /// header. Points leave the `to_` columns and width empty, and lines the
+    /// label, sub-label and point type. Texts are `T` rows with the text in
+    /// the label column and the size in the point type column.
+    Csv,
+    /// A FeatureCollection of Point and LineString features, with the color
+    /// and label as properties. Write only.
+    GeoJson,
+    /// The map drawn with the default render options. Write only, and needs
+    /// the `render` feature.
+    Svg,
+    /// One `label x y z` line per labelled point, as navigation addons read
+    /// waypoints. Write only, and never picked by extension.
+    Waypoints,
+}
+
+impl MapFormat {
+    /// The format named by a path's extension, ignoring case, or `None` if
+    /// the extension isn't one of map, txt, zip, csv, geojson, json or svg.
+    pub fn from_path(path: &Path) -> Option<MapFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match extension.as_str() {
+            "map" | "txt" | "zip" => Some(MapFormat::Map),
+            "csv" => Some(MapFormat::Csv),
+            "geojson" | "json" => Some(MapFormat::GeoJson),
+            "svg" => Some(MapFormat::Svg),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MapFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MapFormat::Map => "map",
+            MapFormat::Csv => "CSV",
+            MapFormat::GeoJson => "GeoJSON",
+            MapFormat::Svg => "SVG",
+            MapFormat::Waypoints => "waypoint",
+        })
+    }
+}
+
+impl FromStr for MapFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "map" => Ok(MapFormat::Map),
+            "csv" => Ok(MapFormat::Csv),
+            "geojson" => Ok(MapFormat::GeoJson),
+            "svg" => Ok(MapFormat::Svg),
+            "waypoints" => Ok(MapFormat::Waypoints),
+            _ => Err(anyhow!("Unknown map format {}", s)),
+        }
+    }
+}
+
+/// The order `MapItems::to_waypoints` writes coordinates in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum CoordinateOrder {
+    /// X, Y and Z as the map file has them.
+    #[default]
+    Map,
+    /// As `/loc` prints them in game; see `Point::to_loc`.
+    Loc,
+}
+
+impl FromStr for CoordinateOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "map" => Ok(CoordinateOrder::Map),
+            "loc" => Ok(CoordinateOrder::Loc),
+            _ => Err(anyhow!("Unknown coordinate order {}", s)),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "type,x,y,z,to_x,to_y,to_z,r,g,b,label,sub_label,point_type,width";
+
+/// The number of columns under `CSV_HEADER`.
+const CSV_COLUMNS: usize = 14;
+/// The number of columns in files written before the sub-label, point type
+/// and width were added.
+const OLD_CSV_COLUMNS: usize = 11;
+
+impl MapItems {
+    /// Reads items written by `write_csv`, failing on the first row that
+    /// doesn't parse. The header row is optional and blank rows are skipped.
+    /// Rows without the last three columns, from older files, get no
+    /// sub-label or width and the default point type.
+    pub fn from_csv(reader: impl BufRead) -> Result<Self, anyhow::Error> {
+        let options = LoadOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        MapItems::from_csv_with(reader, &options)
+    }
+
+    /// Reads items written by `write_csv`, with rows that don't parse or
+    /// decode handled as `from_reader` handles such lines: dropped, kept raw
+    /// or failing the load under `LoadOptions::strict`. The item limit and
+    /// input encoding apply as well.
+    pub fn from_csv_with(
+        reader: impl BufRead,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        let mut items = Vec::new();
+
+        for (index, bytes) in reader.split(b'\n').enumerate() {
+            let row_number = index + 1;
+            let Some(line) = options.input_encoding.decode_line(&bytes?) else {
+                if options.strict {
+                    return Err(anyhow!(
+                        "CSV row {} isn't valid text in the input encoding",
+                        row_number
+                    ));
+                }
+                continue;
+            };
+            if line.trim().is_empty() || (index == 0 && line.starts_with("type,")) {
+                continue;
+            }
+
+            let item = match parse_csv_item(&line) {
+                Ok(item) => item,
+                Err(err) if options.strict => {
+                    return Err(err.context(format!("Invalid CSV row {}: {:?}", row_number, line)))
+                }
+                Err(_) if options.keep_raw_lines => MapItem::Raw(line),
+                Err(_) => continue,
+            };
+
+            items.push(item);
+            options.check_item_count(items.len())?;
+        }
+
+        Ok(MapItems { items })
+    }
+
+    /// Writes one row per point, line and text, after a header row. A text is
+    /// written as a `T` row with its size in the point type column. Raw items
+    /// are left out.
+    pub fn write_csv(&self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(out, "{}", CSV_HEADER)?;
+
+        for item in self.items.iter() {
+            match item {
+                MapItem::PointItem(PointItem {
+                    point,
+                    color,
+                    label,
+                    sub_label,
+                    point_type,
+                }) => writeln!(
+                    out,
+                    "P,{},{},{},,,,{},{},{},{},{},{},",
+                    point.x,
+                    point.y,
+                    point.z,
+                    color.r,
+                    color.g,
+                    color.b,
+                    csv_field(label),
+                    sub_label.as_deref().map(csv_field).unwrap_or_default(),
+                    point_type
+                )?,
+                MapItem::LineItem(LineItem {
+                    from,
+                    to,
+                    color,
+                    width,
+                }) => writeln!(
+                    out,
+                    "L,{},{},{},{},{},{},{},{},{},,,,{}",
+                    from.x,
+                    from.y,
+                    from.z,
+                    to.x,
+                    to.y,
+                    to.z,
+                    color.r,
+                    color.g,
+                    color.b,
+                    width.map(|width| width.to_string()).unwrap_or_default()
+                )?,
+                MapItem::TextItem(TextItem {
+                    point,
+                    color,
+                    size,
+                    text,
+                }) => writeln!(
+                    out,
+                    "T,{},{},{},,,,{},{},{},{},,{},",
+                    point.x,
+                    point.y,
+                    point.z,
+                    color.r,
+                    color.g,
+                    color.b,
+                    csv_field(text),
+                    size
+                )?,
+                MapItem::Raw(_) => {}
+            }
+        }
+
+        out.flush()
+    }
+
+    /// The items as a GeoJSON FeatureCollection, with 3D coordinates in map
+    /// units. Raw items are left out. Fails on an item with a NaN or infinite
+    /// coordinate or text size, which JSON has no numbers for.
+    pub fn to_geojson(&self) -> Result<String, anyhow::Error> {
+        if let Some(item) = self.items.iter().find(|item| !json_numbers_finite(item)) {
+            return Err(anyhow!(
+                "Can't write {:?} as GeoJSON: its numbers must be finite",
+                item.to_string()
+            ));
+        }
+
+        let features: Vec<String> = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(format!(
+                    "{{\"type\": \"Feature\", \"geometry\": {{\"type\": \"Point\", \"coordinates\": {}}}, \"properties\": {{\"color\": \"{}\", \"label\": {}}}}}",
+                    json_position(&point.point),
+                    hex_color(point.color),
+                    json_string(&point.label)
+                )),
+                MapItem::LineItem(line) => Some(format!(
+                    "{{\"type\": \"Feature\", \"geometry\": {{\"type\": \"LineString\", \"coordinates\": [{}, {}]}}, \"properties\": {{\"color\": \"{}\"}}}}",
+                    json_position(&line.from),
+                    json_position(&line.to),
+                    hex_color(line.color)
+                )),
+                MapItem::TextItem(text) => Some(format!(
+                    "{{\"type\": \"Feature\", \"geometry\": {{\"type\": \"Point\", \"coordinates\": {}}}, \"properties\": {{\"color\": \"{}\", \"text\": {}, \"size\": {}}}}}",
+                    json_position(&text.point),
+                    hex_color(text.color),
+                    json_string(&text.text),
+                    text.size
+                )),
+                MapItem::Raw(_) => None,
+            })
+            .collect();
+
+        Ok(format!(
+            "{{\"type\": \"FeatureCollection\", \"features\": [\n{}\n]}}\n",
+            features.join(",\n")
+        ))
+    }
+
+    /// One `label x y z` line per point, in `order`. Points without a label
+    /// are left out, as are lines and raw items.
+    pub fn to_waypoints(&self, order: CoordinateOrder) -> String {
+        let mut waypoints = String::new();
+
+        for item in self.items.iter() {
+            let MapItem::PointItem(point) = item else {
+                continue;
+            };
+            if point.label.is_empty() {
+                continue;
+            }
+
+            let (x, y, z) = match order {
+                CoordinateOrder::Map => (point.point.x, point.point.y, point.point.z),
+                CoordinateOrder::Loc => point.point.to_loc(),
+            };
+            waypoints.push_str(&format!("{} {} {} {}\n", point.label, x, y, z));
+        }
+
+        waypoints
+    }
+
+    /// A copy of the map with a `_2`, `_3` and so on added to each repeat of
+    /// a point label, for exports read by tools that key on the label. The
+    /// first point keeps its label, suffixes already taken by another point
+    /// are skipped, and unlabelled points stay unlabelled.
+    pub fn with_unique_labels(&self) -> MapItems {
+        let mut taken: HashSet<String> = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.label.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut seen: HashSet<&str> = HashSet::new();
+
+        let items = self
+            .items
+            .iter()
+            .map(|item| match item {
+                MapItem::PointItem(point)
+                    if !point.label.is_empty() && !seen.insert(&point.label) =>
+                {
+                    let label = (2..)
+                        .map(|n| format!("{}_{}", point.label, n))
+                        .find(|label| !taken.contains(label))
+                        .unwrap_or_default();
+                    taken.insert(label.clone());
+                    MapItem::PointItem(PointItem {
+                        label,
+                        ..point.clone()
+                    })
+                }
+                item => item.clone(),
+            })
+            .collect();
+
+        MapItems { items }
+    }
+
+    /// The map drawn as an SVG document with the default render options.
+    #[cfg(feature = "render")]
+    pub fn to_svg(&self) -> String {
+        crate::map_draw::map_svg(self, &Default::default())
+    }
+
+    /// Writes the items to `path` in `format`, failing before creating the
+    /// file if `check_output_path` does.
+    pub fn write_to_file(&self, path: &Path, format: MapFormat) -> Result<(), anyhow::Error> {
+        check_output_path(path, format)?;
+        let mut out = BufWriter::new(create_file(path)?);
+
+        match format {
+            MapFormat::Map => self.write_to(out)?,
+            MapFormat::Csv => self.write_csv(out)?,
+            MapFormat::GeoJson => {
+                out.write_all(self.to_geojson()?.as_bytes())?;
+                out.flush()?;
+            }
+            #[cfg(feature = "render")]
+            MapFormat::Svg => {
+                out.write_all(self.to_svg().as_bytes())?;
+                out.flush()?;
+            }
+            #[cfg(not(feature = "render"))]
+            MapFormat::Svg => return Err(anyhow!("Writing SVG needs the render feature")),
+            MapFormat::Waypoints => {
+                out.write_all(self.to_waypoints(CoordinateOrder::Map).as_bytes())?;
+                out.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fails if items can't be written to `path` in `format`. `.zip` archives
+/// are only read, so `MapFormat::Map` output can't go to a `.zip` path.
+pub fn check_output_path(path: &Path, format: MapFormat) -> Result<(), anyhow::Error> {
+    if format == MapFormat::Map && is_zip_path(path) {
+        return Err(anyhow!(
+            "Can't write {}: zip archives can only be read",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+fn parse_csv_item(line: &str) -> Result<MapItem, anyhow::Error> {
+    let mut fields = split_csv(line)?;
+    if fields.len() == OLD_CSV_COLUMNS {
+        fields.resize(CSV_COLUMNS, String::new());
+    }
+    let [kind, x, y, z, to_x, to_y, to_z, r, g, b, label, sub_label, point_type, width] =
+        &fields[..]
+    else {
+        return Err(anyhow!(
+            "Expected {} or {} columns but got {}",
+            CSV_COLUMNS,
+            OLD_CSV_COLUMNS,
+            fields.len()
+        ));
+    };
+
+    let number = |field: &str| -> Result<f32, anyhow::Error> { Ok(field.trim().parse()?) };
+    let channel = |field: &str| -> Result<u8, anyhow::Error> { Ok(field.trim().parse()?) };
+    let point = Point::new(number(x)?, number(y)?, number(z)?);
+    let color = Color::new(channel(r)?, channel(g)?, channel(b)?);
+
+    match kind.as_str() {
+        "P" => Ok(MapItem::PointItem(PointItem {
+            sub_label: (!sub_label.is_empty()).then(|| sub_label.clone()),
+            point_type: match point_type.trim() {
+                "" => DEFAULT_POINT_TYPE,
+                point_type => point_type.parse()?,
+            },
+            ..PointItem::new(point, color, label)
+        })),
+        "L" => {
+            let to = Point::new(number(to_x)?, number(to_y)?, number(to_z)?);
+            Ok(MapItem::LineItem(LineItem {
+                width: match width.trim() {
+                    "" => None,
+                    width => Some(number(width)?),
+                },
+                ..LineItem::new(point, to, color)
+            }))
+        }
+        "T" => {
+            let size = number(point_type)?;
+            if !(size.is_finite() && size > 0.0) {
+                return Err(anyhow!("Invalid text size {}", size));
+            }
+            Ok(MapItem::TextItem(TextItem::new(point, color, size, label)))
+        }
+        _ => Err(anyhow!("Unknown item type {}", kind)),
+    }
+}
+
+/// Splits a CSV row at commas outside of double quotes. `""` inside quotes
+/// stands for a quote.
+fn split_csv(line: &str) -> Result<Vec<String>, anyhow::Error> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(anyhow!("Unterminated quoted field"));
+    }
+    fields.push(field);
+
+    Ok(fields)
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Whether every number `to_geojson` writes for `item` is finite.
+fn json_numbers_finite(item: &MapItem) -> bool {
+    let finite = |point: &Point| point.x.is_finite() && point.y.is_finite() && point.z.is_finite();
+    match item {
+        MapItem::PointItem(point) => finite(&point.point),
+        MapItem::LineItem(line) => finite(&line.from) && finite(&line.to),
+        MapItem::TextItem(text) => finite(&text.point) && text.size.is_finite(),
+        MapItem::Raw(_) => true,
+    }
+}
+
+fn json_position(point: &Point) -> String {
+    format!("[{}, {}, {}]", point.x, point.y, point.z)
+}
+
+fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        map_convert::{check_output_path, CoordinateOrder, MapFormat},
+        map_items::{InputEncoding, LoadOptions, MapItem, MapItems, Point},
+    };
+
+    fn sample() -> MapItems {
+        "P 1.5, -2, 3, 255, 0, 0, 2, \"Inn, \"\"The Rest\"\"\"\n\
+            L 0, 0, 0, 10, 20.25, -1, 0, 0, 255\n"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn formats_from_extensions() {
+        let format = |path: &str| MapFormat::from_path(Path::new(path));
+        assert_eq!(format("zones/akanon.TXT"), Some(MapFormat::Map));
+        assert_eq!(format("pack.zip"), Some(MapFormat::Map));
+        assert_eq!(format("out.csv"), Some(MapFormat::Csv));
+        assert_eq!(format("out.geojson"), Some(MapFormat::GeoJson));
+        assert_eq!(format("out.svg"), Some(MapFormat::Svg));
+        assert_eq!(format("out.png"), None);
+        assert_eq!(format("akanon"), None);
+    }
+
+    #[test]
+    fn zip_paths_are_input_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("never-written.zip");
+        let err = sample().write_to_file(&path, MapFormat::Map).unwrap_err();
+        assert!(
+            err.to_string().contains("zip archives can only be read"),
+            "{}",
+            err
+        );
+        assert!(!path.exists());
+        assert!(check_output_path(&path, MapFormat::Csv).is_ok());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut csv = Vec::new();
+        sample().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv,
+            "type,x,y,z,to_x,to_y,to_z,r,g,b,label,sub_label,point_type,width\n\
+                P,1.5,-2,3,,,,255,0,0,\"Inn, \"\"The Rest\"\"\",,2,\n\
+                L,0,0,0,10,20.25,-1,0,0,255,,,,\n"
+        );
+
+        let map_items = MapItems::from_csv(csv.as_bytes()).unwrap();
+        let mut text = Vec::new();
+        map_items.write_to(&mut text).unwrap();
+        let mut expected = Vec::new();
+        sample().write_to(&mut expected).unwrap();
+        assert_eq!(text, expected);
+
+        let err = MapItems::from_csv("Q,1,2,3,,,,0,0,0,\n".as_bytes()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Invalid CSV row 1"));
+    }
+
+    #[test]
+    fn csv_round_trip_keeps_point_types_sub_labels_and_widths() {
+        let map_items: MapItems = "P 1, 2, 3, 0, 0, 0, 4, Bank | closes at night\n\
+            L 0, 0, 0, 10, 20, 0, 0, 0, 255, 2.5\n\
+            T 5, 6, 7, 0, 255, 0, 1.5, North, gate\n"
+            .parse()
+            .unwrap();
+
+        let mut csv = Vec::new();
+        map_items.write_csv(&mut csv).unwrap();
+        let read = MapItems::from_csv(&csv[..]).unwrap();
+        assert_eq!(read.to_map_string(), map_items.to_map_string());
+
+        let old = MapItems::from_csv("P,1,2,3,,,,0,0,0,Bank\n".as_bytes()).unwrap();
+        assert_eq!(old.to_map_string(), "P 1, 2, 3, 0, 0, 0, 2, Bank\n");
+    }
+
+    #[test]
+    fn reading_csv_with_load_options() {
+        let csv = "type,x,y,z,to_x,to_y,to_z,r,g,b,label\n\
+            P,1,2,3,,,,0,0,0,Bank\n\
+            P,1,2,oops,,,,0,0,0,Inn\n\
+            P,4,5,6,,,,0,0,0,Gu\u{e9}\n";
+        let csv = encoding_rs::WINDOWS_1252.encode(csv).0;
+        let options = |strict, max_items| LoadOptions {
+            strict,
+            max_items,
+            input_encoding: InputEncoding(encoding_rs::WINDOWS_1252),
+            ..Default::default()
+        };
+
+        let map_items = MapItems::from_csv_with(&csv[..], &options(false, None)).unwrap();
+        let labels: Vec<&str> = map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, ["Bank", "Gu\u{e9}"]);
+
+        let err = MapItems::from_csv_with(&csv[..], &options(true, None)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid CSV row 3: \"P,1,2,oops,,,,0,0,0,Inn\""
+        );
+
+        let err = MapItems::from_csv_with(&csv[..], &options(false, Some(1))).unwrap_err();
+        assert_eq!(err.to_string(), "Map has more than the maximum of 1 items");
+    }
+
+    #[test]
+    fn writing_geojson() {
+        let geojson = sample().to_geojson().unwrap();
+        assert!(geojson.starts_with("{\"type\": \"FeatureCollection\""));
+        assert!(geojson.contains(
+            "\"coordinates\": [1.5, -2, 3]}, \"properties\": {\"color\": \"#ff0000\", \"label\": \"Inn, \\\"The Rest\\\"\"}"
+        ));
+        assert!(geojson.contains("\"LineString\", \"coordinates\": [[0, 0, 0], [10, 20.25, -1]]"));
+        assert!(matches!(sample().items[1], MapItem::LineItem(_)));
+
+        let mut map_items = sample();
+        map_items.map_points(|point| Point {
+            x: f32::NAN,
+            ..point
+        });
+        let err = map_items.to_geojson().unwrap_err();
+        assert!(err.to_string().contains("must be finite"), "{}", err);
+    }
+
+    #[test]
+    fn writing_waypoints() {
+        let map_items: MapItems = "P 1.5, -2, 3, 255, 0, 0, 2, Bank
+P 4, 5, 6, 0, 0, 0, 2, 
+L 0, 0, 0, 10, 20, 0, 0, 0, 255
+P 10, 20, 30, 0, 0, 255, 2, Guild Hall"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items.to_waypoints(CoordinateOrder::Map),
+            "Bank 1.5 -2 3\nGuild Hall 10 20 30\n"
+        );
+        assert_eq!(
+            map_items.to_waypoints(CoordinateOrder::Loc),
+            "Bank 2 -1.5 3\nGuild Hall -20 -10 30\n"
+        );
+        assert_eq!(
+            "waypoints".parse::<MapFormat>().unwrap(),
+            MapFormat::Waypoints
+        );
+    }
+
+    #[test]
+    fn suffixing_repeated_labels() {
+        let map_items: MapItems = "P 1, 2, 3, 0, 0, 0, 2, Bank
+P 4, 5, 6, 0, 0, 0, 2, Bank
+P 0, 0, 0, 0, 0, 0, 2, 
+P 0, 0, 0, 0, 0, 0, 2, 
+P 7, 8, 9, 0, 0, 0, 2, Bank_2
+P 1, 1, 1, 0, 0, 0, 2, Bank"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            map_items
+                .with_unique_labels()
+                .to_waypoints(CoordinateOrder::Map),
+            "Bank 1 2 3\nBank_3 4 5 6\nBank_2 7 8 9\nBank_4 1 1 1\n"
+        );
+        assert_eq!(
+            map_items.to_waypoints(CoordinateOrder::Map),
+            "Bank 1 2 3\nBank 4 5 6\nBank_2 7 8 9\nBank 1 1 1\n"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::map_items::{Color, MapItem, MapItems};
+
+/// The color `MapDiff::colorized` draws unchanged items in.
+pub const SHARED_COLOR: Color = Color {
+    r: 160,
+    g: 160,
+    b: 160,
+};
+/// The color `MapDiff::colorized` draws items only in the baseline in.
+pub const REMOVED_COLOR: Color = Color {
+    r: 220,
+    g: 40,
+    b: 40,
+};
+/// The color `MapDiff::colorized` draws items only in the edited map in.
+pub const ADDED_COLOR: Color = Color {
+    r: 40,
+    g: 170,
+    b: 60,
+};
+
+/// The items two maps share and the items unique to each, compared by
+/// `MapItem` equality. An item listed twice in one map and once in the other
+/// is shared once and unique once. Raw lines are left out.
+#[derive(Debug, Clone)]
+pub struct MapDiff {
+    pub shared: MapItems,
+    /// Items only in the baseline, in its order.
+    pub removed: MapItems,
+    /// Items only in the edited map, in its order.
+    pub added: MapItems,
+}
+
+impl MapDiff {
+    /// All the items, recolored by which side they are on, with the shared
+    /// ones first so that the changes are drawn over them.
+    pub fn colorized(&self) -> MapItems {
+        let mut map_items = MapItems { items: Vec::new() };
+
+        for (side, color) in [
+            (&self.shared, SHARED_COLOR),
+            (&self.removed, REMOVED_COLOR),
+            (&self.added, ADDED_COLOR),
+        ] {
+            let mut side = side.clone();
+            side.map_colors(|_| color);
+            map_items.append(side);
+        }
+
+        map_items
+    }
+}
+
+impl MapItems {
+    /// Compares these items, the baseline, with an edited version of them.
+    pub fn diff(&self, edited: &MapItems) -> MapDiff {
+        let drawn = |map_items: &MapItems| -> Vec<MapItem> {
+            map_items
+                .items
+                .iter()
+                .filter(|item| !matches!(item, MapItem::Raw(_)))
+                .cloned()
+                .collect()
+        };
+        let (baseline, edited) = (drawn(self), drawn(edited));
+
+        let mut unmatched: HashMap<&MapItem, usize> = HashMap::new();
+        for item in baseline.iter() {
+            *unmatched.entry(item).or_insert(0) += 1;
+        }
+
+        let (mut shared, mut added) = (Vec::new(), Vec::new());
+        for item in edited.iter() {
+            match unmatched.get_mut(item) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    shared.push(item.clone());
+                }
+                _ => added.push(item.clone()),
+            }
+        }
+
+        // Whatever is left unmatched was removed, counting back from the end
+        // so that the earliest copies are the shared ones.
+        let mut removed = Vec::new();
+        for item in baseline.iter().rev() {
+            if let Some(count) = unmatched.get_mut(item).filter(|count| **count > 0) {
+                *count -= 1;
+                removed.push(item.clone());
+            }
+        }
+        removed.reverse();
+
+        MapDiff {
+            shared: MapItems { items: shared },
+            removed: MapItems { items: removed },
+            added: MapItems { items: added },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_diff::{ADDED_COLOR, REMOVED_COLOR, SHARED_COLOR},
+        map_items::MapItems,
+    };
+
+    #[test]
+    fn diffing_maps() {
+        let baseline: MapItems = "L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0
+P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank
+P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank
+P 5.0, 5.0, 0.0, 0, 0, 0, 2, Inn"
+            .parse()
+            .unwrap();
+        let edited: MapItems = "P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank
+L -0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0
+P 5.0, 5.0, 0.0, 0, 0, 0, 2, Tavern"
+            .parse()
+            .unwrap();
+
+        let diff = baseline.diff(&edited);
+        assert_eq!(diff.shared.len(), 2);
+        let items = |text: &str| text.parse::<MapItems>().unwrap().items;
+        assert_eq!(
+            diff.removed.items,
+            items("P 1.0, 2.0, 0.0, 0, 0, 0, 2, Bank\nP 5.0, 5.0, 0.0, 0, 0, 0, 2, Inn")
+        );
+        assert_eq!(
+            diff.added.items,
+            items("P 5.0, 5.0, 0.0, 0, 0, 0, 2, Tavern")
+        );
+
+        let colors: Vec<_> = diff
+            .colorized()
+            .items
+            .iter()
+            .filter_map(|item| item.color())
+            .collect();
+        assert_eq!(
+            colors,
+            [
+                SHARED_COLOR,
+                SHARED_COLOR,
+                REMOVED_COLOR,
+                REMOVED_COLOR,
+                ADDED_COLOR
+            ]
+        );
+        assert!(baseline.diff(&baseline).added.is_empty());
+    }
+}
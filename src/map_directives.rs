@@ -0,0 +1,154 @@
+//! `#` directives that set defaults for the rest of a map file.
+//!
+//! A line starting with `#` is a comment, unless its first word names one of
+//! these directives:
+//!
+//! - `# default-color r,g,b` lets the lines after it leave out their color.
+//!   Lines become `L fx, fy, fz, tx, ty, tz` and points
+//!   `P x, y, z, size, label`.
+//! - `# scale factor` multiplies the coordinates of the items after it.
+//!
+//! A comment that looks like a directive, a single lowercase word followed by
+//! a value, but isn't one is ignored with a warning.
+
+use crate::map_items::{Color, MapItem};
+
+/// The directives in effect at some line of a map file.
+#[derive(Debug, Clone)]
+pub(crate) struct Directives {
+    default_color: Option<Color>,
+    scale: f32,
+}
+
+impl Default for Directives {
+    fn default() -> Self {
+        Directives {
+            default_color: None,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Directives {
+    /// Applies `comment`, the text of a `#` line after the `#`.
+    pub(crate) fn read(&mut self, comment: &str) {
+        let comment = comment.trim();
+        let (name, value) = comment
+            .split_once(char::is_whitespace)
+            .unwrap_or((comment, ""));
+        let value = value.trim();
+
+        let result = match name {
+            "default-color" => value.parse().map(|color| self.default_color = Some(color)),
+            "scale" => value
+                .parse()
+                .map(|scale| self.scale = scale)
+                .map_err(anyhow::Error::from),
+            _ => {
+                let looks_like_directive = !name.is_empty()
+                    && name.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+                    && !value.is_empty()
+                    && !value.contains(char::is_whitespace);
+                if looks_like_directive {
+                    log::warn!("Ignoring unknown map directive {:?}", comment);
+                }
+                Ok(())
+            }
+        };
+
+        if let Err(err) = result {
+            log::warn!("Ignoring invalid map directive {:?}: {}", comment, err);
+        }
+    }
+
+    /// Parses a map line with `parse`, filling in the default color if the
+    /// line fails without one, and applying the scale.
+    pub(crate) fn parse(
+        &self,
+        line: &str,
+        parse: impl Fn(&str) -> Result<MapItem, anyhow::Error>,
+    ) -> Result<MapItem, anyhow::Error> {
+        let mut item = match parse(line) {
+            Ok(item) => item,
+            Err(err) => match self.with_default_color(line) {
+                Some(line) => parse(&line).map_err(|_| err)?,
+                None => return Err(err),
+            },
+        };
+
+        if self.scale != 1.0 {
+            item.scale(self.scale);
+        }
+
+        Ok(item)
+    }
+
+    /// Rewrites a point or line that leaves out its color with the default
+    /// color, if there is one.
+    fn with_default_color(&self, line: &str) -> Option<String> {
+        let color = self.default_color?;
+        let color = format!("{}, {}, {}", color.r, color.g, color.b);
+
+        let (identifier, content) = line.split_once(' ')?;
+        let fields: Vec<&str> = content.split(',').map(str::trim).collect();
+
+        let fields = match (identifier, &fields[..]) {
+            ("P", [x, y, z, size, label]) => [*x, *y, *z, &color, *size, *label].join(", "),
+            ("L", [fx, fy, fz, tx, ty, tz]) => [*fx, *fy, *fz, *tx, *ty, *tz, &color].join(", "),
+            _ => return None,
+        };
+
+        Some(format!("{} {}", identifier, fields))
+    }
+}
+
+impl MapItem {
+    /// Multiplies every coordinate of the item by `factor`.
+    fn scale(&mut self, factor: f32) {
+        let points = match self {
+            MapItem::PointItem(point) => vec![&mut point.point],
+            MapItem::LineItem(line) => vec![&mut line.from, &mut line.to],
+            MapItem::TextItem(text) => vec![&mut text.point],
+            MapItem::Raw(_) => vec![],
+        };
+
+        for point in points {
+            point.x *= factor;
+            point.y *= factor;
+            point.z *= factor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_check::check_reader,
+        map_items::{InputEncoding, MapItem, MapItems},
+    };
+
+    #[test]
+    fn directives_apply_to_the_lines_after_them() {
+        let map = "P 1.0, 1.0, 1.0, 2, No_color
+# Made by hand
+# default-color 128,128,128
+# scale 2
+P 1.0, 2.0, 3.0, 2, Gray
+L 0.0, 0.0, 0.0, 1.0, 1.0, 1.0
+L 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 255, 0, 0
+# future-directive on
+";
+
+        let map_items: MapItems = map.parse().unwrap();
+        assert_eq!(map_items.items.len(), 3);
+        assert!(matches!(&map_items.items[0], MapItem::PointItem(point)
+            if point.label == "Gray" && point.color.r == 128 && point.point.z == 6.0));
+        assert!(matches!(&map_items.items[1], MapItem::LineItem(line)
+            if line.color.g == 128 && line.to.x == 2.0));
+        assert!(matches!(&map_items.items[2], MapItem::LineItem(line) if line.color.r == 255));
+
+        let errors = check_reader(map.as_bytes(), InputEncoding::default()).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line_number, 1);
+    }
+}
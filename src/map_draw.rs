@@ -1,129 +1,4838 @@
-use anyhow::anyhow;
-use std::{cmp::Ordering, path::Path};
+use anyhow::{anyhow, Context};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
-use crate::map_items::{Color, LineItem, MapItem, MapItems, PointItem};
+#[cfg(feature = "serde")]
+use crate::map_items::deserialize_from_str;
+use crate::{
+    map_bounds::{BoundingBox, Circle},
+    map_cluster::{DensityCell, PointCluster},
+    map_colors::{color_path, fnv1a, Colormap},
+    map_items::{
+        create_file, Color, LineItem, MapItem, MapItems, Point, PointItem, TextItem,
+        ZONE_LINK_PREFIX,
+    },
+};
 
-trait SvgDraw {
-    fn svg(&self) -> String;
+/// DejaVu Sans, bundled so that labels render identically on every host.
+/// See `assets/fonts/LICENSE-DejaVu.txt`.
+static BUNDLED_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+/// The family name of `BUNDLED_FONT`.
+pub const BUNDLED_FONT_FAMILY: &str = "DejaVu Sans";
+
+/// Options controlling how map items are drawn.
+///
+/// With the `serde` feature, these deserialize from a map of field names to
+/// values, with missing fields left at their defaults. Colors and enums are
+/// strings in the same form the command line takes, such as
+/// `background = "#202020"` or `draw_order = "lines-on-top"`.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RenderOptions {
+    /// Font family used for all text elements.
+    pub font_family: String,
+    /// Font size, in map units, used for all text elements.
+    pub font_size: f32,
+    /// Also load the host's installed fonts. The bundled font is always
+    /// loaded.
+    pub system_fonts: bool,
+    /// Output pixels per map unit.
+    pub scale: f32,
+    /// Which kind of item is drawn over the other.
+    pub draw_order: DrawOrder,
+    /// Scale each line's stroke width by its length, from `min_line_width`
+    /// for the shortest lines up to `max_line_width` for the longest line in
+    /// the map.
+    pub scale_line_width: bool,
+    pub min_line_width: f32,
+    pub max_line_width: f32,
+    /// The stroke width of lines that have no width of their own, when
+    /// `scale_line_width` is off. Without one they keep the SVG default of 1.
+    pub line_width: Option<f32>,
+    /// The radius of each point's circle, which `size_by_point_type`,
+    /// `size_by_label_number`, zone link diamonds and cluster markers scale
+    /// from.
+    pub point_radius: f32,
+    /// Draw each chain of connected lines of one color and width, as
+    /// `MapItems::line_chains` finds them, as a smooth curve through their
+    /// ends instead of straight segments. A chain is split where
+    /// `scale_line_width` or `depth_fade` draw its lines differently. See
+    /// `smoothed_chain_svg`.
+    pub smooth_lines: bool,
+    /// Fill each label with black or white, whichever contrasts with its
+    /// marker color, instead of the marker color itself.
+    pub contrast_labels: bool,
+    /// Outline each point's circle in this color, so markers stand out
+    /// against lines of the same color.
+    pub point_outline: Option<Color>,
+    /// Draw a thick stroke under each label, so it stays readable over lines.
+    pub label_halo: Option<LabelHalo>,
+    /// Resolution recorded in the PNG metadata. This doesn't change the
+    /// scale; see `dpi_scale` to derive one.
+    pub dpi: Option<f32>,
+    /// Fade each line's opacity by its average Z within the map's Z range,
+    /// so lower floors appear fainter. Only the opacity changes, so this
+    /// composes with whatever colors the lines have.
+    pub depth_fade: bool,
+    /// Opacity of every line, from 0 to 1, so that overlapping lines in dense
+    /// maps build up into darker areas. Multiplied with `depth_fade`.
+    pub line_opacity: f32,
+    /// Which two coordinates map to the SVG x and y axes.
+    pub plane: Plane,
+    /// Which of those axes are mirrored.
+    pub orientation: Orientation,
+    /// Add `data-type`, `data-label` and `data-z` attributes to each element,
+    /// for scripts that select items in the SVG. Clusters get `data-count`
+    /// and `data-labels` in place of `data-label`.
+    pub svg_data_attrs: bool,
+    /// Wrap each item's elements in a group with an `id` of `item-` and a
+    /// hash of the item's map file line, so the same item always gets the
+    /// same id and two renders can be compared by id. Repeats of an item
+    /// add `-2`, `-3` and so on. A cluster, grouped under its first point's
+    /// id, lists every member's id in `data-members`.
+    pub svg_ids: bool,
+    /// Merge points closer than about this many output pixels into a single
+    /// marker, sized by and labelled with its number of points.
+    pub cluster_radius: Option<f32>,
+    /// Replace the points with a heatmap of how many fall in each square of
+    /// this size, in map units. Cells are colored by count alone, ignoring
+    /// the points' own colors, and drawn beneath the lines.
+    pub heatmap_cell_size: Option<f32>,
+    /// The gradient heatmap cells are colored with, from the least to the
+    /// most crowded.
+    pub colormap: Colormap,
+    /// How the SVG's `width` and `height` are written. Rasterizing always
+    /// uses pixels.
+    pub svg_size: SvgSize,
+    /// Show the map coordinates under the mouse in `map_html` pages, and the
+    /// `/loc` of that spot in plan views.
+    pub html_cursor_coordinates: bool,
+    /// Fill the whole view box with this color, beneath every item. Without
+    /// it the background is transparent.
+    pub background: Option<Color>,
+    /// Stretch this PNG over the whole image, beneath every item but over
+    /// `background`, when rasterizing, such as to draw the map over a zone
+    /// screenshot. SVG and HTML output leave it out.
+    pub background_image: Option<PathBuf>,
+    /// Label each line at least this long, in map units, with its rounded
+    /// length at its midpoint.
+    pub label_lengths: Option<f32>,
+    /// Draw this region, in the coordinates of `plane`, instead of the extent
+    /// of the items.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub view_bounds: Option<BoundingBox>,
+    /// Which items the extent is taken from when `view_bounds` isn't set.
+    pub fit: Fit,
+    /// Widen the extent to take in the point labels and texts of those
+    /// items, so that labels on the edge aren't cut off. Text isn't laid out
+    /// until it is rendered, so each label's size is estimated from its
+    /// character count; see `text_extent`.
+    pub fit_labels: bool,
+    /// Widen the extent by this many map units on every side, so that
+    /// markers on the edge are drawn whole. It isn't added to `view_bounds`.
+    pub padding: f32,
+    /// Dim everything outside this region, in the coordinates of `plane`,
+    /// under a translucent black overlay drawn over the map.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub focus: Option<FocusRegion>,
+    /// Draw a thumbnail of a larger map in a corner, with the drawn view
+    /// marked on it, such as the whole zone behind a crop.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub inset: Option<Inset>,
+    /// Write a line of text in a corner, such as the files the map came
+    /// from.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stamp: Option<Stamp>,
+    /// Called for each item as it is drawn, to replace its SVG. See
+    /// `ItemHook`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_item: Option<ItemHook>,
+    /// Draw items of each kind sorted by color, then coordinates, then label,
+    /// instead of in the order they were loaded, so that the same items
+    /// always give the same SVG whatever order the files listed them in.
+    /// `draw_order` still decides whether points or lines are drawn first.
+    pub sort_output: bool,
+    /// Draw points whose label starts with this prefix, usually
+    /// `ZONE_LINK_PREFIX`, as larger diamonds, so that zone connections stand
+    /// out from other markers.
+    pub zone_link_prefix: Option<String>,
+    /// Start `map_svg` output with an XML declaration, for consumers that
+    /// reject SVG files without one. Embedded and rasterized SVGs never have
+    /// it.
+    pub xml_prolog: bool,
+    /// How `map_svg` and `map_html` lay out the SVG's elements.
+    pub svg_formatting: SvgFormatting,
+    /// Only draw point labels when `scale` is at least this, so that zoomed
+    /// out overviews show bare markers. `None` always draws them.
+    pub label_min_scale: Option<f32>,
+    /// Draw the underscores in point labels and sub-labels, which the game's
+    /// maps use in place of spaces, as they are instead of as spaces.
+    pub keep_label_underscores: bool,
+    /// Draw at most this many point labels. Zone link labels, by
+    /// `zone_link_prefix` or else `ZONE_LINK_PREFIX`, come first, then the
+    /// other labels in the order the items were loaded. Every marker is
+    /// still drawn.
+    pub max_label_count: Option<usize>,
+    /// Scale each point's circle by `PointItem::label_number`, so that its
+    /// area is proportional to the number. Points without one keep the usual
+    /// size.
+    pub size_by_label_number: bool,
+    /// Size each point's circle by `PointItem::point_type`, as
+    /// `marker_radius` maps it and in proportion to `point_radius`, before
+    /// `size_by_label_number` scales it.
+    pub size_by_point_type: bool,
+    /// Crop rendered images to the pixels that differ from `background`, or
+    /// that aren't transparent without one, keeping this many pixels of
+    /// margin around them. SVG output and Z animations are never cropped.
+    pub autocrop: Option<u32>,
+    /// Render images at exactly this size, scaling the map to fit in place of
+    /// `scale` and centering it, with `letterbox_color` beside it. Only
+    /// `map_render`, and the PNGs written from it, use a frame, and
+    /// `autocrop` is ignored with one.
+    pub frame_size: Option<FrameSize>,
+    /// The pixels on each side of `frame_size` that the map is kept out of,
+    /// as a page margin.
+    pub frame_margin: u32,
+    /// The color of the bars `frame_size` leaves beside a map of another
+    /// aspect ratio. Defaults to `background`, or transparent without one.
+    pub letterbox_color: Option<Color>,
+    /// Describe the map in a `<desc>` element at the top of the SVG.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub metadata: Option<SvgMetadata>,
+    /// Define each point circle shared by two or more points, by color and
+    /// size, once in `<defs>` and draw the points as `<use>` references to
+    /// it, which shrinks point-heavy SVGs. Circles of a kind of their own
+    /// are still drawn inline.
+    pub point_symbols: bool,
 }
 
-impl SvgDraw for LineItem {
-    fn svg(&self) -> String {
-        String::from(format!(
-            "<path d=\"M {} {} L {} {}\" stroke=\"{}\" class=\"line-item\" />\n",
-            self.from.x,
-            self.from.y,
-            self.to.x,
-            self.to.y,
-            self.color.svg()
-        ))
+/// Provenance written into the SVG by `RenderOptions::metadata`, alongside
+/// the item counts and extent, which come from the items themselves.
+#[derive(Debug, Clone, Default)]
+pub struct SvgMetadata {
+    /// The files the items were loaded from.
+    pub sources: Vec<String>,
+    /// When the SVG was made, in any format. Leave it out for output that
+    /// only depends on the input files.
+    pub generated: Option<String>,
+}
+
+/// The region `RenderOptions::focus` leaves undimmed.
+#[derive(Debug, Clone, Copy)]
+pub enum FocusRegion {
+    Rect(BoundingBox),
+    Circle(Circle),
+}
+
+/// The minimap `RenderOptions::inset` draws, like a game's: the lines of
+/// `map_items` scaled down into `corner`, under a rectangle marking the view.
+#[derive(Debug, Clone)]
+pub struct Inset {
+    pub map_items: MapItems,
+    pub corner: Corner,
+}
+
+/// The text `RenderOptions::stamp` writes in `corner`, at `font_size` in the
+/// same units as `RenderOptions::font_size`. It is black, or the color that
+/// contrasts with the background, and gets the labels' halo.
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    pub text: String,
+    pub corner: Corner,
+    pub font_size: f32,
+}
+
+/// A function that can draw any item in place of the built-in SVG, for
+/// styling items, leaving them out or adding markup beside them.
+///
+/// `svg_document` calls it for every item, raw lines included, in draw order
+/// and before clustering, smoothing or the heatmap decide how the item is
+/// drawn. Returning `Some(svg)` writes `svg` in the item's place, and
+/// `Some(String::new())` leaves the item out. Returning `None` draws the item
+/// as usual, so an item that is clustered or chained into another stays
+/// hidden. The returned SVG is written as it is: it must be well formed,
+/// with labels and other text escaped by the hook, and positioned in drawn
+/// coordinates, which `RenderOptions::project` gives for a point.
+#[derive(Clone)]
+pub struct ItemHook(Arc<ItemHookFn>);
+
+type ItemHookFn = dyn Fn(&MapItem) -> Option<String> + Send + Sync;
+
+impl ItemHook {
+    pub fn new(hook: impl Fn(&MapItem) -> Option<String> + Send + Sync + 'static) -> Self {
+        ItemHook(Arc::new(hook))
     }
 }
 
-impl SvgDraw for PointItem {
-    fn svg(&self) -> String {
-        String::from(format!(
-            "<circle cx=\"{}\" cy=\"{}\" r=\"3\" fill=\"{}\" class=\"point-item-circle\" />\n",
-            self.point.x,
-            self.point.y,
-            self.color.svg()
-        ))
+impl std::fmt::Debug for ItemHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ItemHook(..)")
     }
 }
 
-impl SvgDraw for Color {
-    fn svg(&self) -> String {
-        String::from(format!("rgb({},{},{})", self.r, self.g, self.b))
+/// A corner of the drawn map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(anyhow!("Unknown corner {}", s)),
+        }
     }
 }
 
-impl SvgDraw for MapItems {
-    fn svg(&self) -> String {
-        let view_box = map_view_box(&self);
+/// The most of the view box's width and height an inset may take up.
+const INSET_FRACTION: f32 = 0.25;
 
-        let mut svg = String::new();
-        svg.push_str(&format!(
-            "<svg width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n",
-            view_box.2, view_box.3, view_box.0, view_box.1, view_box.2, view_box.3
-        ));
+/// The gap between an inset and the edges of the view box, as a fraction of
+/// the view box's shorter side.
+const INSET_MARGIN: f32 = 0.02;
 
-        for item in self.items.iter() {
-            let path = match item {
-                MapItem::LineItem(line) => line.svg(),
-                MapItem::PointItem(point) => point.svg(),
-            };
+/// The color of the rectangle marking the view on an inset.
+const INSET_VIEW_COLOR: Color = Color {
+    r: 220,
+    g: 40,
+    b: 40,
+};
+
+/// The opacity of the overlay outside a focus region.
+const FOCUS_MASK_OPACITY: f32 = 0.6;
+
+/// Which items `map_view_box_with` takes the extent from. Neither adds
+/// padding of its own, so without `RenderOptions::padding` items on the edge
+/// of the extent are drawn half cut off, and items outside it aren't drawn.
+/// `RenderOptions::fit_labels` makes room for the labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// Every item.
+    #[default]
+    All,
+    /// Only the point items, for an overview of the labelled places that
+    /// sprawling walls don't shrink. A map without points fits all of its
+    /// items instead.
+    Points,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Fit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for Fit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Fit::All),
+            "points" => Ok(Fit::Points),
+            _ => Err(anyhow!("Unknown fit {}", s)),
+        }
+    }
+}
+
+/// The color of the halo drawn under each label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelHalo {
+    /// Black or white, whichever contrasts with the label's own fill.
+    Contrasting,
+    Color(Color),
+}
+
+impl LabelHalo {
+    /// The halo's color under a label filled with `fill`.
+    fn color(&self, fill: Color) -> Color {
+        match self {
+            LabelHalo::Contrasting => fill.contrasting(),
+            LabelHalo::Color(color) => *color,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for LabelHalo {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for LabelHalo {
+    type Err = anyhow::Error;
+
+    /// `contrast`, or a color as `Color` parses it.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "contrast" => Ok(LabelHalo::Contrasting),
+            _ => Ok(LabelHalo::Color(s.parse()?)),
+        }
+    }
+}
+
+/// The layout of the elements in a written SVG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SvgFormatting {
+    /// One element per line, without indentation.
+    #[default]
+    Compact,
+    /// One element per line, indented by how deeply it is nested, for
+    /// reading while debugging styles.
+    Pretty,
+    /// Everything on one line, with attributes that only repeat the SVG
+    /// default dropped, numbers and paths written in fewer characters, and
+    /// `class` attributes dropped unless the SVG has a stylesheet, for the
+    /// smallest file to serve.
+    Minify,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SvgFormatting {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for SvgFormatting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact" => Ok(SvgFormatting::Compact),
+            "pretty" => Ok(SvgFormatting::Pretty),
+            "minify" => Ok(SvgFormatting::Minify),
+            _ => Err(anyhow!("Unknown SVG formatting {}", s)),
+        }
+    }
+}
+
+/// The `width` and `height` written on the root `<svg>` element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SvgSize {
+    /// The view box size in pixels, for standalone files.
+    #[default]
+    Pixels,
+    /// `100%` of the containing element, for embedding in a responsive page.
+    Percent,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SvgSize {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for SvgSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "px" => Ok(SvgSize::Pixels),
+            "percent" => Ok(SvgSize::Percent),
+            _ => Err(anyhow!("Unknown SVG size {}", s)),
+        }
+    }
+}
+
+/// The size of a fixed frame to render into, in pixels, written `WIDTHxHEIGHT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FrameSize {
+    /// The largest scale at which a view box of `width` by `height` map
+    /// units fits in the frame.
+    pub fn fit_scale(&self, width: f32, height: f32) -> f32 {
+        (self.width as f32 / width).min(self.height as f32 / height)
+    }
+
+    /// The frame with `margin` pixels taken off each side, but never less
+    /// than a pixel across.
+    pub fn inset(&self, margin: u32) -> FrameSize {
+        FrameSize {
+            width: self.width.saturating_sub(margin * 2).max(1),
+            height: self.height.saturating_sub(margin * 2).max(1),
+        }
+    }
+}
+
+/// A paper size to print on, in portrait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    /// ISO A4, 210 by 297 millimetres.
+    A4,
+    /// US Letter, 8.5 by 11 inches.
+    Letter,
+}
+
+impl PageSize {
+    /// The width and height of the page in millimetres.
+    pub fn millimetres(&self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::Letter => (215.9, 279.4),
+        }
+    }
+
+    /// The page in pixels at `dpi`.
+    pub fn frame(&self, dpi: f32) -> FrameSize {
+        let (width, height) = self.millimetres();
+        FrameSize {
+            width: millimetres_to_pixels(width, dpi),
+            height: millimetres_to_pixels(height, dpi),
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a4" => Ok(PageSize::A4),
+            "letter" => Ok(PageSize::Letter),
+            _ => Err(anyhow!("Unknown page size {}", s)),
+        }
+    }
+}
+
+/// `length` millimetres in whole pixels at `dpi`.
+pub fn millimetres_to_pixels(length: f32, dpi: f32) -> u32 {
+    (length / 25.4 * dpi).round() as u32
+}
+
+impl FromStr for FrameSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = s.split_once('x').and_then(|(width, height)| {
+            Some(FrameSize {
+                width: width.trim().parse().ok()?,
+                height: height.trim().parse().ok()?,
+            })
+        });
+
+        match size {
+            Some(size) if size.width > 0 && size.height > 0 => Ok(size),
+            _ => Err(anyhow!("Expected WIDTHxHEIGHT in pixels but got {}", s)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FrameSize {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// A choice of two point coordinates to draw, for plan views or vertical
+/// cross-sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Plane {
+    /// Top-down, as in game.
+    #[default]
+    Xy,
+    /// A cross-section looking along the Y axis.
+    Xz,
+    /// A cross-section looking along the X axis.
+    Yz,
+}
+
+impl Plane {
+    /// Projects a point onto the plane, returning its SVG `(x, y)`.
+    pub fn project(&self, point: &Point) -> (f32, f32) {
+        match self {
+            Plane::Xy => (point.x, point.y),
+            Plane::Xz => (point.x, point.z),
+            Plane::Yz => (point.y, point.z),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Plane {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for Plane {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xy" => Ok(Plane::Xy),
+            "xz" => Ok(Plane::Xz),
+            "yz" => Ok(Plane::Yz),
+            _ => Err(anyhow!("Unknown plane {}", s)),
+        }
+    }
+}
+
+/// Which axes of the plane are mirrored when drawing. By default the first
+/// coordinate increases to the right and the second downwards, which in the
+/// top-down plane puts north at the top and east on the right, as in game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Orientation {
+    /// Increase the first coordinate to the left instead.
+    pub flip_x: bool,
+    /// Increase the second coordinate upwards instead.
+    pub flip_y: bool,
+}
+
+impl Orientation {
+    /// Mirrors plane coordinates into drawn coordinates, by negating each
+    /// flipped axis. Applying it twice gives back the plane coordinates.
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            if self.flip_x { 0.0 - x } else { x },
+            if self.flip_y { 0.0 - y } else { y },
+        )
+    }
+
+    /// Mirrors a `(min_x, min_y, width, height)` view box, keeping its size.
+    fn apply_view_box(&self, (x, y, width, height): (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+        (
+            if self.flip_x { -(x + width) } else { x },
+            if self.flip_y { -(y + height) } else { y },
+            width,
+            height,
+        )
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flip_x, flip_y) = match s {
+            "default" => (false, false),
+            "flip-x" => (true, false),
+            "flip-y" => (false, true),
+            "flip-both" => (true, true),
+            _ => return Err(anyhow!("Unknown orientation {}", s)),
+        };
+
+        Ok(Orientation { flip_x, flip_y })
+    }
+}
 
-            svg.push_str(path.as_str());
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Orientation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// The order in which items are drawn. Later items are drawn over earlier
+/// ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum DrawOrder {
+    /// Draw all lines, then all points, so markers are never buried.
+    #[default]
+    PointsOnTop,
+    /// Draw all points, then all lines.
+    LinesOnTop,
+    /// Draw items in the order they were loaded.
+    FileOrder,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DrawOrder {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+impl FromStr for DrawOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "points-on-top" => Ok(DrawOrder::PointsOnTop),
+            "lines-on-top" => Ok(DrawOrder::LinesOnTop),
+            "file-order" => Ok(DrawOrder::FileOrder),
+            _ => Err(anyhow!("Unknown draw order {}", s)),
+        }
+    }
+}
+
+/// Which items each frame of `map_draw_z_animation` shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZBandMode {
+    /// Only the items within the frame's own band.
+    #[default]
+    Exclusive,
+    /// The items within the frame's band and every band below it, so the
+    /// map builds up floor by floor.
+    Cumulative,
+}
+
+impl FromStr for ZBandMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exclusive" => Ok(ZBandMode::Exclusive),
+            "cumulative" => Ok(ZBandMode::Cumulative),
+            _ => Err(anyhow!("Unknown Z band mode {}", s)),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The drawn `(x, y)` of a point: its `plane` coordinates, mirrored by
+    /// `orientation`.
+    pub fn project(&self, point: &Point) -> (f32, f32) {
+        self.orientation.apply(self.plane.project(point))
+    }
+
+    /// A point label or sub-label as it is drawn, by
+    /// `keep_label_underscores`.
+    fn drawn_label<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        if self.keep_label_underscores {
+            Cow::Borrowed(label)
+        } else {
+            Cow::Owned(label.replace('_', " "))
+        }
+    }
+
+    /// Whether point labels are drawn at `scale`, by `label_min_scale`.
+    fn labels_shown(&self) -> bool {
+        self.label_min_scale
+            .is_none_or(|min_scale| self.scale >= min_scale)
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            font_family: String::from(BUNDLED_FONT_FAMILY),
+            font_size: 10.0,
+            system_fonts: false,
+            scale: 1.0,
+            draw_order: DrawOrder::default(),
+            scale_line_width: false,
+            smooth_lines: false,
+            min_line_width: 0.5,
+            max_line_width: 4.0,
+            line_width: None,
+            point_radius: POINT_RADIUS,
+            contrast_labels: false,
+            point_outline: None,
+            label_halo: None,
+            dpi: None,
+            depth_fade: false,
+            line_opacity: 1.0,
+            plane: Plane::default(),
+            orientation: Orientation::default(),
+            svg_data_attrs: false,
+            svg_ids: false,
+            cluster_radius: None,
+            heatmap_cell_size: None,
+            colormap: Colormap::default(),
+            svg_size: SvgSize::default(),
+            html_cursor_coordinates: false,
+            background: None,
+            background_image: None,
+            label_lengths: None,
+            view_bounds: None,
+            inset: None,
+            stamp: None,
+            on_item: None,
+            fit: Fit::default(),
+            fit_labels: false,
+            padding: 0.0,
+            focus: None,
+            sort_output: false,
+            zone_link_prefix: None,
+            xml_prolog: false,
+            svg_formatting: SvgFormatting::default(),
+            label_min_scale: None,
+            keep_label_underscores: false,
+            max_label_count: None,
+            size_by_label_number: false,
+            size_by_point_type: false,
+            autocrop: None,
+            frame_size: None,
+            frame_margin: 0,
+            letterbox_color: None,
+            metadata: None,
+            point_symbols: false,
+        }
+    }
+}
+
+/// Map-wide state shared by the `SvgDraw` impls.
+struct DrawContext<'a> {
+    options: &'a RenderOptions,
+    /// The length of the longest line item, used by `scale_line_width`.
+    longest_line: f32,
+    /// The lowest and highest Z of any line endpoint, used by `depth_fade`.
+    line_z_range: (f32, f32),
+    /// The `<defs>` id of each shared point circle, by color and radius, and
+    /// the circles in the order they were first drawn. Empty unless
+    /// `point_symbols` is on.
+    point_symbols: HashMap<(Color, u32), String>,
+    symbol_defs: Vec<((Color, u32), String)>,
+    /// The indexes of the points whose labels are drawn, if
+    /// `max_label_count` leaves some out.
+    labelled_points: Option<HashSet<usize>>,
+}
+
+impl<'a> DrawContext<'a> {
+    fn new(map_items: &MapItems, options: &'a RenderOptions) -> Self {
+        let lines = || {
+            map_items.items.iter().filter_map(|item| match item {
+                MapItem::LineItem(line) => Some(line),
+                _ => None,
+            })
+        };
+
+        let longest_line = lines().map(LineItem::length).fold(0.0, f32::max);
+        let line_z_range = lines()
+            .flat_map(|line| [line.from.z, line.to.z])
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), z| {
+                (min.min(z), max.max(z))
+            });
+
+        let mut symbol_defs = Vec::new();
+        let mut point_symbols = HashMap::new();
+        if options.point_symbols {
+            let mut counts: HashMap<(Color, u32), usize> = HashMap::new();
+            let mut kinds = Vec::new();
+            for item in map_items.items.iter() {
+                let MapItem::PointItem(point) = item else {
+                    continue;
+                };
+                if point.is_zone_link(options) {
+                    continue;
+                }
+                let kind = (point.color, point.radius(options).to_bits());
+                let count = counts.entry(kind).or_insert(0);
+                if *count == 0 {
+                    kinds.push(kind);
+                }
+                *count += 1;
+            }
+
+            for kind in kinds.into_iter().filter(|kind| counts[kind] > 1) {
+                let id = format!("point-{}", symbol_defs.len());
+                point_symbols.insert(kind, id.clone());
+                symbol_defs.push((kind, id));
+            }
+        }
+
+        let labelled_points = options.max_label_count.map(|max_count| {
+            let prefix = options
+                .zone_link_prefix
+                .as_deref()
+                .unwrap_or(ZONE_LINK_PREFIX);
+            let mut labelled: Vec<(usize, &PointItem)> = map_items
+                .items
+                .iter()
+                .enumerate()
+                .filter_map(|(index, item)| match item {
+                    MapItem::PointItem(point) if !point.label.is_empty() => Some((index, point)),
+                    _ => None,
+                })
+                .collect();
+            // The sort is stable, so ties keep their file order.
+            labelled.sort_by_key(|(_, point)| !point.label_is_zone_link_with(prefix));
+            labelled
+                .into_iter()
+                .take(max_count)
+                .map(|(index, _)| index)
+                .collect()
+        });
+
+        DrawContext {
+            options,
+            longest_line,
+            line_z_range,
+            point_symbols,
+            symbol_defs,
+            labelled_points,
+        }
+    }
+
+    /// The `<defs>` element holding the shared point circles, if any.
+    fn defs_svg(&self) -> String {
+        if self.symbol_defs.is_empty() {
+            return String::new();
         }
 
-        svg.push_str(&format!("</svg>\n"));
+        let mut svg = String::from("<defs>\n");
+        for ((color, radius), id) in self.symbol_defs.iter() {
+            svg.push_str(&format!(
+                "<circle id=\"{}\" r=\"{}\" fill=\"{}\"{} />\n",
+                id,
+                f32::from_bits(*radius),
+                color.svg(self),
+                point_outline_svg(self)
+            ));
+        }
+        svg.push_str("</defs>\n");
 
         svg
     }
 }
 
-pub fn map_draw(map_items: &MapItems, out_file: &Path) -> Result<(), anyhow::Error> {
-    let svg = map_items.svg();
+/// The opacity of the lowest lines when `depth_fade` is on.
+const MIN_DEPTH_FADE_OPACITY: f32 = 0.2;
 
-    let mut options = usvg::Options::default();
-    options.fontdb.load_system_fonts();
-    let rtree = usvg::Tree::from_data(&svg.as_bytes(), &options.to_ref())?;
+trait SvgDraw {
+    fn svg(&self, context: &DrawContext) -> String;
+}
 
-    let fit_to = usvg::FitTo::Zoom(1.0);
-    let pixmap_size = fit_to
-        .fit_to(rtree.svg_node().size.to_screen_size())
-        .unwrap();
+impl LineItem {
+    /// The stroke attributes of the line, shared by its straight and
+    /// smoothed paths.
+    fn stroke_attributes(&self, context: &DrawContext) -> String {
+        let mut attributes = self.stroke_style(context);
+        if context.options.svg_data_attrs {
+            attributes.push_str(&format!(
+                " data-type=\"line\" data-z=\"{}\"",
+                self.midpoint().z
+            ));
+        }
 
-    let mut pixmap = tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height())
-        .ok_or_else(|| anyhow!("Could not create a pixmap"))?;
+        attributes
+    }
 
-    resvg::render(
-        &rtree,
-        fit_to,
-        tiny_skia::Transform::default(),
-        pixmap.as_mut(),
-    )
-    .ok_or_else(|| anyhow!("Failed to render the SVG"))?;
+    /// The stroke width and opacity attributes, which `scale_line_width` and
+    /// `depth_fade` can set per line.
+    fn stroke_style(&self, context: &DrawContext) -> String {
+        let options = context.options;
 
-    pixmap.save_png(out_file)?;
+        let mut attributes = String::new();
+        if let Some(width) = self.width {
+            attributes.push_str(&format!(" stroke-width=\"{}\"", width));
+        } else if options.scale_line_width && context.longest_line > 0.0 {
+            let t = self.length() / context.longest_line;
+            let width =
+                options.min_line_width + (options.max_line_width - options.min_line_width) * t;
+            attributes.push_str(&format!(" stroke-width=\"{}\"", width));
+        } else if let Some(width) = options.line_width {
+            attributes.push_str(&format!(" stroke-width=\"{}\"", width));
+        }
 
-    Ok(())
+        let mut opacity = options.line_opacity;
+        let (min_z, max_z) = context.line_z_range;
+        let depth_faded = options.depth_fade && max_z > min_z;
+        if depth_faded {
+            let t = ((self.from.z + self.to.z) / 2.0 - min_z) / (max_z - min_z);
+            opacity *= MIN_DEPTH_FADE_OPACITY + (1.0 - MIN_DEPTH_FADE_OPACITY) * t;
+        }
+        if depth_faded || opacity < 1.0 {
+            attributes.push_str(&format!(" stroke-opacity=\"{}\"", opacity));
+        }
+
+        attributes
+    }
+
+    /// The line's rounded length at its midpoint, if `label_lengths` asks
+    /// for it.
+    fn length_label_svg(&self, context: &DrawContext) -> String {
+        let options = context.options;
+        let length = self.length();
+        if !options.label_lengths.is_some_and(|min| length >= min) {
+            return String::new();
+        }
+
+        let (x, y) = options.project(&self.midpoint());
+        let font_size = options.font_size * 0.8;
+        let label = length.round().to_string();
+        let halo = label_halo_svg(
+            (x, y),
+            font_size,
+            Some("middle"),
+            self.color,
+            &label,
+            context,
+        );
+        format!(
+            "{}<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\" text-anchor=\"middle\" class=\"line-length\">{}</text>\n",
+            halo,
+            x,
+            y,
+            xml_escape(&options.font_family),
+            font_size,
+            self.color.svg(context),
+            label
+        )
+    }
 }
 
-pub fn map_view_box(map_items: &MapItems) -> (f32, f32, f32, f32) {
-    let mut xs: Vec<f32> = vec![];
-    let mut ys: Vec<f32> = vec![];
+impl SvgDraw for LineItem {
+    fn svg(&self, context: &DrawContext) -> String {
+        let options = context.options;
+        let (from_x, from_y) = options.project(&self.from);
+        let (to_x, to_y) = options.project(&self.to);
+
+        let mut svg = format!(
+            "<path d=\"M {} {} L {} {}\" stroke=\"{}\"{} class=\"line-item\" />\n",
+            from_x,
+            from_y,
+            to_x,
+            to_y,
+            self.color.svg(context),
+            self.stroke_attributes(context)
+        );
+        svg.push_str(&self.length_label_svg(context));
+
+        svg
+    }
+}
+
+/// Draws a chain of connected lines, as `MapItems::line_chains` finds them,
+/// as one uniform Catmull-Rom spline through their ends. The lines share a
+/// stroke, as `split_by_stroke` makes sure, so the path is styled like the
+/// first line, and has its data attributes. Each span between two ends becomes a cubic Bezier curve whose
+/// control points lie a sixth of the way along the tangents, which run
+/// parallel to the line between the neighbouring ends. The chain's own ends
+/// count as their own neighbours, and a chain that ends where it starts
+/// wraps around, so it closes smoothly.
+fn smoothed_chain_svg(lines: &[&LineItem], context: &DrawContext) -> String {
+    let options = context.options;
+    let mut ends: Vec<(f32, f32)> = vec![options.project(&lines[0].from)];
+    ends.extend(lines.iter().map(|line| options.project(&line.to)));
 
-    map_items.items.iter().for_each(|item| match item {
-        MapItem::LineItem(line) => {
-            xs.push(line.from.x);
-            ys.push(line.from.y);
-            xs.push(line.to.x);
-            ys.push(line.to.y);
+    let closed = ends.len() > 3 && ends.first() == ends.last();
+    let last = ends.len() - 1;
+    let neighbour = |index: isize| -> (f32, f32) {
+        match index {
+            index if index < 0 && closed => ends[last - 1],
+            index if index as usize > last && closed => ends[1],
+            index => ends[index.clamp(0, last as isize) as usize],
         }
-        MapItem::PointItem(point) => {
-            xs.push(point.point.x);
-            ys.push(point.point.y);
+    };
+
+    let mut d = format!("M {} {}", ends[0].0, ends[0].1);
+    for index in 0..last as isize {
+        let (before, from, to, after) = (
+            neighbour(index - 1),
+            neighbour(index),
+            neighbour(index + 1),
+            neighbour(index + 2),
+        );
+        d.push_str(&format!(
+            " C {} {} {} {} {} {}",
+            from.0 + (to.0 - before.0) / 6.0,
+            from.1 + (to.1 - before.1) / 6.0,
+            to.0 - (after.0 - from.0) / 6.0,
+            to.1 - (after.1 - from.1) / 6.0,
+            to.0,
+            to.1
+        ));
+    }
+
+    let mut svg = format!(
+        "<path d=\"{}\" stroke=\"{}\"{} fill=\"none\" class=\"line-item line-item-smoothed\" />\n",
+        d,
+        lines[0].color.svg(context),
+        lines[0].stroke_attributes(context)
+    );
+    for line in lines {
+        svg.push_str(&line.length_label_svg(context));
+    }
+
+    svg
+}
+
+/// Splits a chain from `MapItems::line_chains` into the runs of two or more
+/// lines drawn with the same stroke, which `scale_line_width` and
+/// `depth_fade` can change from one line to the next.
+fn split_by_stroke(
+    chain: Vec<usize>,
+    map_items: &MapItems,
+    context: &DrawContext,
+) -> Vec<Vec<usize>> {
+    let stroke = |index: usize| match &map_items.items[index] {
+        MapItem::LineItem(line) => Some(line.stroke_style(context)),
+        _ => None,
+    };
+
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    for index in chain {
+        match runs.last_mut() {
+            Some(run) if stroke(run[0]) == stroke(index) => run.push(index),
+            _ => runs.push(vec![index]),
         }
-    });
+    }
+    runs.retain(|run| run.len() > 1);
 
-    xs.sort_by(float_ord);
-    ys.sort_by(float_ord);
+    runs
+}
 
-    let min_x = xs.first().unwrap_or(&0.0);
-    let min_y = ys.first().unwrap_or(&0.0);
-    let max_x = xs.last().unwrap_or(&0.0);
-    let max_y = ys.last().unwrap_or(&0.0);
+/// The radius of the circle drawn for a point.
+const POINT_RADIUS: f32 = 3.0;
 
-    return (*min_x, *min_y, max_x - min_x, max_y - min_y);
+/// The radius `RenderOptions::size_by_point_type` draws points of a type
+/// with: 1 is small and 3 large, as the game sizes them, and 2 and every
+/// other type keep the usual radius.
+pub fn marker_radius(point_type: u8) -> f32 {
+    match point_type {
+        1 => 2.0,
+        3 => 4.5,
+        _ => POINT_RADIUS,
+    }
 }
 
-fn float_ord(a: &f32, b: &f32) -> Ordering {
-    if a < b {
-        Ordering::Less
-    } else if b < a {
-        Ordering::Greater
+/// The gap between a point's marker and its label.
+const LABEL_GAP: f32 = 2.0;
+
+/// The font size of a point's sub-label, as a fraction of its label's.
+const SUB_LABEL_SIZE: f32 = 0.75;
+
+/// How far a sub-label's baseline sits below its label's, in lines of the
+/// sub-label's font size.
+const SUB_LABEL_LINE_HEIGHT: f32 = 1.2;
+
+/// The distance from the center of a zone link diamond to its corners at the
+/// default `RenderOptions::point_radius`, a bit larger than the circle drawn
+/// for other points. It scales with the point radius.
+const ZONE_LINK_RADIUS: f32 = 5.0;
+
+/// The width of a label's halo stroke, as a fraction of its font size. Half
+/// of it shows outside the glyphs.
+const LABEL_HALO_WIDTH: f32 = 0.3;
+
+/// The average width of a character, as a fraction of the font size, that
+/// `text_extent` estimates text widths from. It is a little over the average
+/// of the bundled font's letters, so that estimates err on the wide side.
+const TEXT_CHAR_WIDTH: f32 = 0.6;
+
+/// How far text reaches above and below its baseline, as fractions of the
+/// font size, in the bundled font.
+const TEXT_ASCENT: f32 = 0.93;
+const TEXT_DESCENT: f32 = 0.24;
+
+/// Estimates the `(min_x, min_y, max_x, max_y)` box, in drawn coordinates,
+/// of `text` at `font_size` with its baseline starting at `(x, y)`, or
+/// centered on it if `centered`, from its number of characters. The halo,
+/// if `options` draws one, widens the box by the half of its stroke outside
+/// the glyphs.
+fn text_extent(
+    (x, y): (f32, f32),
+    font_size: f32,
+    centered: bool,
+    text: &str,
+    options: &RenderOptions,
+) -> (f32, f32, f32, f32) {
+    let width = text.chars().count() as f32 * font_size * TEXT_CHAR_WIDTH;
+    let halo = if options.label_halo.is_some() {
+        font_size * LABEL_HALO_WIDTH / 2.0
     } else {
-        Ordering::Equal
+        0.0
+    };
+    let min_x = if centered { x - width / 2.0 } else { x };
+
+    (
+        min_x - halo,
+        y - font_size * TEXT_ASCENT - halo,
+        min_x + width + halo,
+        y + font_size * TEXT_DESCENT + halo,
+    )
+}
+
+/// The estimated boxes, as `text_extent` gives them, of the point labels,
+/// sub-labels and texts `item` draws.
+fn item_text_extents(item: &MapItem, options: &RenderOptions) -> Vec<(f32, f32, f32, f32)> {
+    match item {
+        MapItem::PointItem(point) if options.labels_shown() && !point.label.is_empty() => {
+            let mut extents = vec![text_extent(
+                point.label_position(options),
+                options.font_size,
+                false,
+                &point.label,
+                options,
+            )];
+            if let Some(sub_label) = &point.sub_label {
+                let (position, font_size) = point.sub_label_position(options);
+                extents.push(text_extent(position, font_size, false, sub_label, options));
+            }
+            extents
+        }
+        MapItem::TextItem(text) => {
+            let (x, y) = options.project(&text.point);
+            let font_size = options.font_size * text.size;
+            vec![text_extent(
+                (x, y + font_size / 3.0),
+                font_size,
+                true,
+                &text.text,
+                options,
+            )]
+        }
+        _ => vec![],
+    }
+}
+
+/// A copy of a label's `<text>` to draw under it as its `label_halo`, or
+/// nothing without one. `anchor` is the label's `text-anchor`, if it has one.
+fn label_halo_svg(
+    (x, y): (f32, f32),
+    font_size: f32,
+    anchor: Option<&str>,
+    fill: Color,
+    text: &str,
+    context: &DrawContext,
+) -> String {
+    let options = context.options;
+    let Some(halo) = options.label_halo else {
+        return String::new();
+    };
+
+    let color = halo.color(fill).svg(context);
+    let anchor = match anchor {
+        Some(anchor) => format!(" text-anchor=\"{}\"", anchor),
+        None => String::new(),
+    };
+    format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\"{} fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\" class=\"label-halo\">{}</text>\n",
+        x,
+        y,
+        xml_escape(&options.font_family),
+        font_size,
+        anchor,
+        color,
+        color,
+        font_size * LABEL_HALO_WIDTH,
+        xml_escape(text)
+    )
+}
+
+/// The stroke attributes of `point_outline`, if set.
+fn point_outline_svg(context: &DrawContext) -> String {
+    match context.options.point_outline {
+        Some(color) => format!(" stroke=\"{}\" stroke-width=\"1\"", color.svg(context)),
+        None => String::new(),
+    }
+}
+
+impl PointItem {
+    /// Whether the point is drawn as a zone link diamond.
+    fn is_zone_link(&self, options: &RenderOptions) -> bool {
+        options
+            .zone_link_prefix
+            .as_deref()
+            .is_some_and(|prefix| self.label_is_zone_link_with(prefix))
+    }
+
+    /// The radius of the point's circle.
+    fn radius(&self, options: &RenderOptions) -> f32 {
+        let radius = if options.size_by_point_type {
+            marker_radius(self.point_type) * options.point_radius / POINT_RADIUS
+        } else {
+            options.point_radius
+        };
+
+        if options.size_by_label_number {
+            radius * self.label_number().map_or(1.0, f32::sqrt)
+        } else {
+            radius
+        }
+    }
+
+    /// Where the point's label starts, on its baseline, to the right of the
+    /// marker.
+    fn label_position(&self, options: &RenderOptions) -> (f32, f32) {
+        let (x, y) = options.project(&self.point);
+        // Zone link labels overlap the diamond's right corner, as they
+        // always have.
+        let offset = if self.is_zone_link(options) {
+            options.point_radius
+        } else {
+            self.radius(options)
+        };
+
+        (x + offset + LABEL_GAP, y + options.font_size / 3.0)
+    }
+
+    /// Where the point's sub-label starts, below its label, and its font
+    /// size.
+    fn sub_label_position(&self, options: &RenderOptions) -> ((f32, f32), f32) {
+        let (x, y) = self.label_position(options);
+        let font_size = options.font_size * SUB_LABEL_SIZE;
+
+        ((x, y + font_size * SUB_LABEL_LINE_HEIGHT), font_size)
+    }
+}
+
+impl SvgDraw for PointItem {
+    fn svg(&self, context: &DrawContext) -> String {
+        self.svg_labelled(context, true)
+    }
+}
+
+impl PointItem {
+    /// Draws the point, leaving out its label unless `labelled`.
+    fn svg_labelled(&self, context: &DrawContext, labelled: bool) -> String {
+        let options = context.options;
+        let (x, y) = options.project(&self.point);
+
+        let attributes = if options.svg_data_attrs {
+            format!(
+                " data-type=\"point\" data-label=\"{}\" data-z=\"{}\"",
+                xml_escape(&self.label),
+                self.point.z
+            )
+        } else {
+            String::new()
+        };
+
+        let outline = point_outline_svg(context);
+        let zone_link = self.is_zone_link(options);
+        let radius = self.radius(options);
+        let symbol = context.point_symbols.get(&(self.color, radius.to_bits()));
+
+        let mut svg = if zone_link {
+            let corner = ZONE_LINK_RADIUS * options.point_radius / POINT_RADIUS;
+            format!(
+                "<path d=\"M {} {} l {} {} l {} {} l {} {} Z\" fill=\"{}\"{}{} class=\"point-item-zone-link\" />\n",
+                x,
+                y - corner,
+                corner,
+                corner,
+                -corner,
+                corner,
+                -corner,
+                -corner,
+                self.color.svg(context),
+                outline,
+                attributes
+            )
+        } else if let Some(id) = symbol {
+            format!(
+                "<use href=\"#{}\" x=\"{}\" y=\"{}\"{} class=\"point-item-circle\" />\n",
+                id, x, y, attributes
+            )
+        } else {
+            format!(
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"{}{} class=\"point-item-circle\" />\n",
+                x,
+                y,
+                radius,
+                self.color.svg(context),
+                outline,
+                attributes
+            )
+        };
+
+        if labelled && options.labels_shown() && !self.label.is_empty() {
+            let label_color = if options.contrast_labels {
+                self.color.contrasting()
+            } else {
+                self.color
+            };
+
+            let label = options.drawn_label(&self.label);
+            let position = self.label_position(options);
+            svg.push_str(&label_halo_svg(
+                position,
+                options.font_size,
+                None,
+                label_color,
+                &label,
+                context,
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\"{} class=\"point-item-label\">{}</text>\n",
+                position.0,
+                position.1,
+                xml_escape(&options.font_family),
+                options.font_size,
+                label_color.svg(context),
+                attributes,
+                xml_escape(&label)
+            ));
+
+            if let Some(sub_label) = &self.sub_label {
+                let sub_label = options.drawn_label(sub_label);
+                let (position, font_size) = self.sub_label_position(options);
+                svg.push_str(&label_halo_svg(
+                    position,
+                    font_size,
+                    None,
+                    label_color,
+                    &sub_label,
+                    context,
+                ));
+                svg.push_str(&format!(
+                    "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\" class=\"point-item-sub-label\">{}</text>\n",
+                    position.0,
+                    position.1,
+                    xml_escape(&options.font_family),
+                    font_size,
+                    label_color.svg(context),
+                    xml_escape(&sub_label)
+                ));
+            }
+        }
+
+        svg
+    }
+}
+
+impl SvgDraw for TextItem {
+    fn svg(&self, context: &DrawContext) -> String {
+        let options = context.options;
+        let (x, y) = options.project(&self.point);
+        let font_size = options.font_size * self.size;
+
+        let attributes = if options.svg_data_attrs {
+            format!(
+                " data-type=\"text\" data-label=\"{}\" data-z=\"{}\"",
+                xml_escape(&self.text),
+                self.point.z
+            )
+        } else {
+            String::new()
+        };
+
+        let halo = label_halo_svg(
+            (x, y + font_size / 3.0),
+            font_size,
+            Some("middle"),
+            self.color,
+            &self.text,
+            context,
+        );
+        format!(
+            "{}<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" text-anchor=\"middle\" fill=\"{}\"{} class=\"text-item\">{}</text>\n",
+            halo,
+            x,
+            y + font_size / 3.0,
+            xml_escape(&options.font_family),
+            font_size,
+            self.color.svg(context),
+            attributes,
+            xml_escape(&self.text)
+        )
+    }
+}
+
+/// Draws a cluster of two or more points as one marker, with the color of its
+/// first point, growing from the point radius with the square root of its
+/// number of points. `svg_data_attrs` adds the count and the members' labels,
+/// joined by `|`, and `svg_ids` the members' ids in `data-members`, given
+/// `ids` from `item_ids`.
+fn cluster_svg(
+    cluster: &PointCluster,
+    map_items: &MapItems,
+    ids: Option<&[String]>,
+    context: &DrawContext,
+) -> String {
+    let options = context.options;
+    let (x, y) = options.project(&cluster.center);
+    let count = cluster.members.len();
+    let color = match &map_items.items[cluster.members[0]] {
+        MapItem::PointItem(point) => point.color,
+        _ => Color::new(0, 0, 0),
+    };
+
+    let mut attributes = String::new();
+    if options.svg_data_attrs {
+        let labels: Vec<&str> = cluster
+            .members
+            .iter()
+            .filter_map(|member| match &map_items.items[*member] {
+                MapItem::PointItem(point) => Some(point.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        attributes.push_str(&format!(
+            " data-type=\"cluster\" data-count=\"{}\" data-labels=\"{}\" data-z=\"{}\"",
+            count,
+            xml_escape(&labels.join("|")),
+            cluster.center.z
+        ));
+    }
+    if let Some(ids) = ids {
+        let members: Vec<&str> = cluster
+            .members
+            .iter()
+            .map(|member| ids[*member].as_str())
+            .collect();
+        attributes.push_str(&format!(" data-members=\"{}\"", members.join(" ")));
+    }
+
+    format!(
+        "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"{} class=\"point-cluster\" />\n\
+         <text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-family=\"{}\" font-size=\"{}\" fill=\"{}\" class=\"point-cluster-label\">{}</text>\n",
+        x,
+        y,
+        options.point_radius * (count as f32).sqrt(),
+        color.svg(context),
+        attributes,
+        x,
+        y + options.font_size / 3.0,
+        xml_escape(&options.font_family),
+        options.font_size,
+        color.contrasting().svg(context),
+        count
+    )
+}
+
+fn heatmap_svg(cells: &[DensityCell], cell_size: f32, context: &DrawContext) -> String {
+    let max_count = cells.iter().map(|cell| cell.count).max().unwrap_or(0);
+
+    cells
+        .iter()
+        .map(|cell| {
+            format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" class=\"heatmap-cell\" />\n",
+                cell.min_x,
+                cell.min_y,
+                cell_size,
+                cell_size,
+                context
+                    .options
+                    .colormap
+                    .sample(cell.count as f32 / max_count as f32)
+                    .svg(context)
+            )
+        })
+        .collect()
+}
+
+impl SvgDraw for Color {
+    fn svg(&self, _context: &DrawContext) -> String {
+        format!("rgb({},{},{})", self.r, self.g, self.b)
+    }
+}
+
+/// Generates the SVG document for the map items.
+pub fn map_svg(map_items: &MapItems, render_options: &RenderOptions) -> String {
+    let svg = formatted_svg(
+        svg_document(map_items, render_options, render_options.svg_size),
+        render_options.svg_formatting,
+    );
+
+    if render_options.xml_prolog {
+        format!("{}{}", XML_PROLOG, svg)
+    } else {
+        svg
+    }
+}
+
+const XML_PROLOG: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n";
+
+/// What `SvgFormatting::Pretty` indents each level of nesting by.
+const SVG_INDENT: &str = "  ";
+
+/// Lays out an SVG from `svg_document` as `formatting` asks. The document
+/// has each element on its own line, with text elements opened and closed on
+/// the same one, so the nesting can be followed line by line.
+fn formatted_svg(svg: String, formatting: SvgFormatting) -> String {
+    match formatting {
+        SvgFormatting::Compact => svg,
+        SvgFormatting::Pretty => {
+            let mut pretty = String::with_capacity(svg.len() * 2);
+            let mut depth = 0usize;
+            for line in svg.lines() {
+                let closes = line.starts_with("</");
+                let opens = !closes && !line.ends_with("/>") && !line.contains("</");
+                if closes {
+                    depth = depth.saturating_sub(1);
+                }
+
+                pretty.push_str(&SVG_INDENT.repeat(depth));
+                pretty.push_str(line);
+                pretty.push('\n');
+
+                if opens {
+                    depth += 1;
+                }
+            }
+
+            pretty
+        }
+        SvgFormatting::Minify => minified_svg(&svg),
+    }
+}
+
+/// Attributes whose values `SvgFormatting::Minify` can drop, since the SVG
+/// default is the same. Inherited properties, such as `fill-opacity` and
+/// `stroke-opacity`, are left out, as a value of 1 overrides an ancestor's.
+const DEFAULT_ATTRIBUTES: &[(&str, &str)] = &[
+    ("x", "0"),
+    ("y", "0"),
+    ("cx", "0"),
+    ("cy", "0"),
+    ("opacity", "1"),
+];
+
+/// Attributes holding nothing but numbers and path commands, which
+/// `SvgFormatting::Minify` may write more briefly.
+const NUMERIC_ATTRIBUTES: &[&str] = &[
+    "x",
+    "y",
+    "cx",
+    "cy",
+    "r",
+    "d",
+    "transform",
+    "stroke-width",
+    "font-size",
+    "opacity",
+    "fill-opacity",
+    "stroke-opacity",
+];
+
+lazy_static! {
+    static ref SVG_ATTRIBUTE: Regex = Regex::new(r#" ([a-zA-Z-]+)="([^"]*)""#).unwrap();
+    /// A 0 before a decimal point, which can go.
+    static ref LEADING_ZERO: Regex = Regex::new(r"(^|[^0-9.])0\.([0-9])").unwrap();
+    /// Spaces around a path command, which can go.
+    static ref PATH_COMMAND_SPACE: Regex = Regex::new(r" ?([MLCHVZmlchvz]) ?").unwrap();
+}
+
+/// Puts the SVG on one line and shortens each tag as
+/// `SvgFormatting::Minify` describes. Element content, such as label text,
+/// is left alone.
+fn minified_svg(svg: &str) -> String {
+    let keep_classes = svg.contains("<style");
+    let mut minified = String::with_capacity(svg.len());
+
+    for line in svg.lines() {
+        let mut rest = line.trim();
+        while let Some(start) = rest.find('<') {
+            minified.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('>')
+                .map_or(rest.len(), |end| start + end + 1);
+            minified.push_str(&minified_tag(&rest[start..end], keep_classes));
+            rest = &rest[end..];
+        }
+        minified.push_str(rest);
+    }
+    minified.push('\n');
+
+    minified
+}
+
+fn minified_tag(tag: &str, keep_classes: bool) -> String {
+    let tag = SVG_ATTRIBUTE.replace_all(tag, |captures: &Captures| {
+        let (name, value) = (&captures[1], &captures[2]);
+        if (name == "class" && !keep_classes) || DEFAULT_ATTRIBUTES.contains(&(name, value)) {
+            return String::new();
+        }
+        if !NUMERIC_ATTRIBUTES.contains(&name) {
+            return captures[0].to_string();
+        }
+
+        let value = LEADING_ZERO.replace_all(value, "$1.$2");
+        let value = match name {
+            "d" => PATH_COMMAND_SPACE.replace_all(&value, "$1").into_owned(),
+            _ => value.into_owned(),
+        };
+        format!(" {}=\"{}\"", name, value)
+    });
+
+    match tag.strip_suffix(" />") {
+        Some(tag) => format!("{}/>", tag),
+        None => tag.into_owned(),
+    }
+}
+
+/// The SVG to rasterize. With a background image the background color is
+/// filled in beneath the image by `rasterize` instead, as a rect in the SVG
+/// would cover the image.
+fn raster_document(map_items: &MapItems, render_options: &RenderOptions) -> String {
+    let background_rect = render_options.background_image.is_none();
+    svg_document_with(map_items, render_options, SvgSize::Pixels, background_rect)
+}
+
+fn svg_document(map_items: &MapItems, render_options: &RenderOptions, size: SvgSize) -> String {
+    svg_document_with(map_items, render_options, size, true)
+}
+
+fn svg_document_with(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+    size: SvgSize,
+    background_rect: bool,
+) -> String {
+    let context = DrawContext::new(map_items, render_options);
+    let view_box = map_view_box_with(map_items, render_options);
+
+    let (width, height) = match size {
+        SvgSize::Pixels => (view_box.2.to_string(), view_box.3.to_string()),
+        SvgSize::Percent => (String::from("100%"), String::from("100%")),
+    };
+
+    let mut svg = String::new();
+    svg.push_str(&format!(
+            "<svg width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" xmlns=\"http://www.w3.org/2000/svg\">\n",
+            width, height, view_box.0, view_box.1, view_box.2, view_box.3
+        ));
+
+    if let Some(metadata) = &render_options.metadata {
+        svg.push_str(&metadata_svg(metadata, map_items));
+    }
+    svg.push_str(&context.defs_svg());
+
+    if let Some(background) = render_options.background.filter(|_| background_rect) {
+        svg.push_str(&format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" class=\"background\" />\n",
+            view_box.0,
+            view_box.1,
+            view_box.2,
+            view_box.3,
+            background.svg(&context)
+        ));
+    }
+
+    let heatmap_cell_size = render_options
+        .heatmap_cell_size
+        .filter(|cell_size| *cell_size > 0.0);
+    if let Some(cell_size) = heatmap_cell_size {
+        let cells = map_items.point_density_by(cell_size, |point| render_options.project(point));
+        svg.push_str(&heatmap_svg(&cells, cell_size, &context));
+    }
+
+    let clusters = match render_options.cluster_radius {
+        Some(radius) if radius > 0.0 => map_items
+            .point_clusters_by(radius / render_options.scale, |point| {
+                render_options.project(point)
+            }),
+        _ => Vec::new(),
+    };
+
+    // Each cluster is drawn in place of its first point, and its other points
+    // are skipped.
+    let mut clustered: HashMap<usize, Option<&PointCluster>> = HashMap::new();
+    for cluster in clusters.iter().filter(|cluster| cluster.members.len() > 1) {
+        clustered.insert(cluster.members[0], Some(cluster));
+        for member in &cluster.members[1..] {
+            clustered.insert(*member, None);
+        }
+    }
+
+    // Like clusters, each chain is drawn in place of its first line.
+    let chains = if render_options.smooth_lines {
+        map_items
+            .line_chains()
+            .into_iter()
+            .flat_map(|chain| split_by_stroke(chain, map_items, &context))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut chained: HashMap<usize, Option<&[usize]>> = HashMap::new();
+    for chain in chains.iter() {
+        chained.insert(chain[0], Some(chain));
+        for member in &chain[1..] {
+            chained.insert(*member, None);
+        }
+    }
+
+    let ids = render_options.svg_ids.then(|| item_ids(map_items));
+    let mut push_item = |index: usize, item_svg: &str| match &ids {
+        Some(ids) => svg.push_str(&format!("<g id=\"{}\">\n{}</g>\n", ids[index], item_svg)),
+        None => svg.push_str(item_svg),
+    };
+
+    let ordered = draw_ordered(
+        &map_items.items,
+        render_options.draw_order,
+        render_options.sort_output,
+    );
+    for (index, item) in ordered {
+        if let Some(replaced) = render_options
+            .on_item
+            .as_ref()
+            .and_then(|hook| (hook.0)(item))
+        {
+            push_item(index, &replaced);
+            continue;
+        }
+
+        let path = match item {
+            MapItem::LineItem(line) => match chained.get(&index) {
+                Some(Some(chain)) => {
+                    let lines: Vec<&LineItem> = chain
+                        .iter()
+                        .filter_map(|member| match &map_items.items[*member] {
+                            MapItem::LineItem(line) => Some(line),
+                            _ => None,
+                        })
+                        .collect();
+                    smoothed_chain_svg(&lines, &context)
+                }
+                Some(None) => continue,
+                None => line.svg(&context),
+            },
+            MapItem::TextItem(text) => text.svg(&context),
+            MapItem::PointItem(_) if heatmap_cell_size.is_some() => continue,
+            MapItem::PointItem(point) => match clustered.get(&index) {
+                Some(Some(cluster)) => cluster_svg(cluster, map_items, ids.as_deref(), &context),
+                Some(None) => continue,
+                None => point.svg_labelled(
+                    &context,
+                    context
+                        .labelled_points
+                        .as_ref()
+                        .is_none_or(|labelled| labelled.contains(&index)),
+                ),
+            },
+            MapItem::Raw(_) => continue,
+        };
+
+        push_item(index, &path);
+    }
+
+    if let Some(focus) = &render_options.focus {
+        svg.push_str(&focus_mask_svg(focus, view_box, render_options.orientation));
+    }
+    if let Some(inset) = &render_options.inset {
+        svg.push_str(&inset_svg(inset, view_box, render_options));
+    }
+    if let Some(stamp) = &render_options.stamp {
+        svg.push_str(&stamp_svg(stamp, view_box, &context));
+    }
+
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+/// The `svg_ids` id of each item, by index.
+fn item_ids(map_items: &MapItems) -> Vec<String> {
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    map_items
+        .items
+        .iter()
+        .map(|item| {
+            let hash = fnv1a(0, item.to_string().as_bytes());
+            let repeats = seen.entry(hash).or_default();
+            *repeats += 1;
+            match *repeats {
+                1 => format!("item-{:016x}", hash),
+                n => format!("item-{:016x}-{}", hash, n),
+            }
+        })
+        .collect()
+}
+
+/// A `<desc>` element describing the map: its line and point counts and
+/// extent, with the sources and generation time from `metadata`.
+fn metadata_svg(metadata: &SvgMetadata, map_items: &MapItems) -> String {
+    let (mut points, mut lines) = (0, 0);
+    for item in &map_items.items {
+        match item {
+            MapItem::PointItem(_) => points += 1,
+            MapItem::LineItem(_) => lines += 1,
+            MapItem::TextItem(_) | MapItem::Raw(_) => {}
+        }
+    }
+
+    let mut desc = Vec::new();
+    if !metadata.sources.is_empty() {
+        desc.push(format!("Sources: {}.", metadata.sources.join(", ")));
+    }
+    desc.push(format!("Lines: {}. Points: {}.", lines, points));
+    if let Some(bounds) = map_items.bounding_box() {
+        desc.push(format!(
+            "Extent: {}, {} to {}, {}.",
+            bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y
+        ));
+    }
+    if let Some(generated) = &metadata.generated {
+        desc.push(format!("Generated: {}.", generated));
+    }
+
+    format!("<desc>{}</desc>\n", xml_escape(&desc.join(" ")))
+}
+
+/// Covers the view box with the focus overlay, with the focus region cut out
+/// of it by the even-odd fill rule.
+fn focus_mask_svg(
+    focus: &FocusRegion,
+    view_box: (f32, f32, f32, f32),
+    orientation: Orientation,
+) -> String {
+    let (x, y, width, height) = view_box;
+    let cut_out = match focus {
+        FocusRegion::Rect(bounds) => {
+            // Mirroring may swap the corners, which the even-odd fill doesn't
+            // mind.
+            let (min_x, min_y) = orientation.apply((bounds.min_x, bounds.min_y));
+            let (max_x, max_y) = orientation.apply((bounds.max_x, bounds.max_y));
+            format!(
+                "M {} {} H {} V {} H {} Z",
+                min_x, min_y, max_x, max_y, min_x
+            )
+        }
+        FocusRegion::Circle(circle) => {
+            let r = circle.radius;
+            let (center_x, center_y) = orientation.apply((circle.center.x, circle.center.y));
+            format!(
+                "M {} {} a {} {} 0 1 0 {} 0 a {} {} 0 1 0 {} 0 Z",
+                center_x - r,
+                center_y,
+                r,
+                r,
+                2.0 * r,
+                r,
+                r,
+                -2.0 * r
+            )
+        }
+    };
+
+    format!(
+        "<path d=\"M {} {} h {} v {} h {} Z {}\" fill=\"black\" fill-opacity=\"{}\" fill-rule=\"evenodd\" class=\"focus-mask\" />\n",
+        x, y, width, height, -width, cut_out, FOCUS_MASK_OPACITY
+    )
+}
+
+/// Draws the inset in its corner of the view box, fitted to the extent of
+/// its map and the view together so the view's rectangle is never cut off.
+/// Only lines are drawn, as points and labels would be too small to make
+/// out, one view box unit wide whatever the inset's scale.
+fn inset_svg(
+    inset: &Inset,
+    view_box: (f32, f32, f32, f32),
+    render_options: &RenderOptions,
+) -> String {
+    let options = RenderOptions {
+        plane: render_options.plane,
+        orientation: render_options.orientation,
+        ..Default::default()
+    };
+    let context = DrawContext::new(&inset.map_items, &options);
+
+    let (view_x, view_y, view_width, view_height) = view_box;
+    let extent = BoundingBox::from_view_box(map_view_box_with(&inset.map_items, &options))
+        .union(&BoundingBox::from_view_box(view_box));
+    let scale = (view_width * INSET_FRACTION / extent.width())
+        .min(view_height * INSET_FRACTION / extent.height());
+    let (width, height) = (extent.width() * scale, extent.height() * scale);
+
+    let margin = view_width.min(view_height) * INSET_MARGIN;
+    let x = match inset.corner {
+        Corner::TopLeft | Corner::BottomLeft => view_x + margin,
+        Corner::TopRight | Corner::BottomRight => view_x + view_width - margin - width,
+    };
+    let y = match inset.corner {
+        Corner::TopLeft | Corner::TopRight => view_y + margin,
+        Corner::BottomLeft | Corner::BottomRight => view_y + view_height - margin - height,
+    };
+
+    let mut svg = String::from("<g class=\"inset\">\n");
+    svg.push_str(&format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"black\" stroke-width=\"{}\" />\n",
+        x,
+        y,
+        width,
+        height,
+        render_options
+            .background
+            .unwrap_or(Color::new(255, 255, 255))
+            .svg(&context),
+        margin / 4.0
+    ));
+    svg.push_str(&format!(
+        "<g transform=\"translate({} {}) scale({})\" stroke-width=\"{}\">\n",
+        x - extent.min_x * scale,
+        y - extent.min_y * scale,
+        scale,
+        1.0 / scale
+    ));
+    for item in inset.map_items.items.iter() {
+        if let MapItem::LineItem(line) = item {
+            svg.push_str(&line.svg(&context));
+        }
+    }
+    svg.push_str(&format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" class=\"inset-view\" />\n",
+        view_x,
+        view_y,
+        view_width,
+        view_height,
+        INSET_VIEW_COLOR.svg(&context),
+        2.0 / scale
+    ));
+    svg.push_str("</g>\n</g>\n");
+
+    svg
+}
+
+/// Writes the stamp's text in its corner, as far from the edges as an inset.
+fn stamp_svg(stamp: &Stamp, view_box: (f32, f32, f32, f32), context: &DrawContext) -> String {
+    let options = context.options;
+    let (view_x, view_y, view_width, view_height) = view_box;
+    let margin = view_width.min(view_height) * INSET_MARGIN;
+    let (x, anchor) = match stamp.corner {
+        Corner::TopLeft | Corner::BottomLeft => (view_x + margin, "start"),
+        Corner::TopRight | Corner::BottomRight => (view_x + view_width - margin, "end"),
+    };
+    // The text sits on its baseline, so a top stamp drops by its height.
+    let y = match stamp.corner {
+        Corner::TopLeft | Corner::TopRight => view_y + margin + stamp.font_size,
+        Corner::BottomLeft | Corner::BottomRight => view_y + view_height - margin,
+    };
+    let fill = options
+        .background
+        .map_or(Color::new(0, 0, 0), |background| background.contrasting());
+
+    format!(
+        "{}<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" text-anchor=\"{}\" fill=\"{}\" class=\"stamp\">{}</text>\n",
+        label_halo_svg((x, y), stamp.font_size, Some(anchor), fill, &stamp.text, context),
+        x,
+        y,
+        xml_escape(&options.font_family),
+        stamp.font_size,
+        anchor,
+        fill.svg(context),
+        xml_escape(&stamp.text)
+    )
+}
+
+/// The page `map_html` places the SVG into, before and after it.
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Map</title>
+<style>
+html, body { margin: 0; height: 100%; overflow: hidden; background: #fff; }
+svg { display: block; width: 100vw; height: 100vh; cursor: grab; }
+#coordinates { position: fixed; left: 8px; bottom: 8px; padding: 2px 6px; font: 13px monospace; background: rgba(255, 255, 255, 0.8); pointer-events: none; }
+</style>
+</head>
+<body>
+"#;
+const HTML_TAIL: &str = r#"<script>
+const svg = document.querySelector("svg");
+let [x, y, w, h] = svg.getAttribute("viewBox").split(" ").map(Number);
+const update = () => svg.setAttribute("viewBox", `${x} ${y} ${w} ${h}`);
+const unitsPerPixel = () => Math.max(w / svg.clientWidth, h / svg.clientHeight);
+svg.addEventListener("wheel", (event) => {
+  event.preventDefault();
+  const rect = svg.getBoundingClientRect();
+  const k = unitsPerPixel();
+  const px = x + (event.clientX - rect.left - (svg.clientWidth - w / k) / 2) * k;
+  const py = y + (event.clientY - rect.top - (svg.clientHeight - h / k) / 2) * k;
+  const zoom = Math.exp(event.deltaY * 0.001);
+  x = px - (px - x) * zoom;
+  y = py - (py - y) * zoom;
+  w *= zoom;
+  h *= zoom;
+  update();
+}, { passive: false });
+let drag = null;
+svg.addEventListener("pointerdown", (event) => {
+  drag = { x: event.clientX, y: event.clientY };
+  svg.setPointerCapture(event.pointerId);
+});
+svg.addEventListener("pointermove", (event) => {
+  if (!drag) return;
+  const k = unitsPerPixel();
+  x -= (event.clientX - drag.x) * k;
+  y -= (event.clientY - drag.y) * k;
+  drag = { x: event.clientX, y: event.clientY };
+  update();
+});
+svg.addEventListener("pointerup", () => { drag = null; });
+</script>
+</body>
+</html>
+"#;
+
+/// Shows the coordinates under the mouse for `html_cursor_coordinates`,
+/// after `cursor` is set to the drawn axes and how they are mirrored. The
+/// SVG's own screen transform turns the mouse position into view box
+/// coordinates, which follow the view box as it is zoomed and panned, and
+/// undoing the mirroring gives back map coordinates.
+const HTML_CURSOR_SCRIPT: &str = r#"const coordinates = document.getElementById("coordinates");
+document.querySelector("svg").addEventListener("pointermove", (event) => {
+  const svg = event.currentTarget;
+  const point = new DOMPoint(event.clientX, event.clientY).matrixTransform(svg.getScreenCTM().inverse());
+  const a = cursor.flipX ? -point.x : point.x;
+  const b = cursor.flipY ? -point.y : point.y;
+  let text = `${cursor.axes[0]} ${a.toFixed(1)}, ${cursor.axes[1]} ${b.toFixed(1)}`;
+  if (cursor.loc) text += `  /loc ${(-b).toFixed(1)}, ${(-a).toFixed(1)}`;
+  coordinates.textContent = text;
+});
+}
+</script>
+"#;
+
+/// Generates a standalone HTML page showing the map's SVG, with mouse wheel
+/// zoom and drag to pan.
+pub fn map_html(map_items: &MapItems, render_options: &RenderOptions) -> String {
+    let svg = formatted_svg(
+        svg_document(map_items, render_options, render_options.svg_size),
+        render_options.svg_formatting,
+    );
+
+    let mut html = String::with_capacity(HTML_HEAD.len() + svg.len() + HTML_TAIL.len());
+    html.push_str(HTML_HEAD);
+    html.push_str(&svg);
+    if render_options.html_cursor_coordinates {
+        let axes = match render_options.plane {
+            Plane::Xy => ("x", "y"),
+            Plane::Xz => ("x", "z"),
+            Plane::Yz => ("y", "z"),
+        };
+        // The script has a block of its own, so its names can't clash with
+        // HTML_TAIL's.
+        html.push_str(&format!(
+            "<div id=\"coordinates\"></div>\n<script>\n{{\nconst cursor = {{ axes: [\"{}\", \"{}\"], flipX: {}, flipY: {}, loc: {} }};\n",
+            axes.0,
+            axes.1,
+            render_options.orientation.flip_x,
+            render_options.orientation.flip_y,
+            render_options.plane == Plane::Xy
+        ));
+        html.push_str(HTML_CURSOR_SCRIPT);
+    }
+    html.push_str(HTML_TAIL);
+
+    html
+}
+
+/// Orders the items for drawing, along with their indexes. Items of the same
+/// kind keep their relative order, unless `sort` puts them in
+/// `canonical_order` first.
+fn draw_ordered(items: &[MapItem], draw_order: DrawOrder, sort: bool) -> Vec<(usize, &MapItem)> {
+    let mut items: Vec<(usize, &MapItem)> = items.iter().enumerate().collect();
+    if sort {
+        items.sort_by(|(_, a), (_, b)| canonical_order(a, b));
+    }
+
+    let (lines, others): (Vec<_>, Vec<_>) = items
+        .iter()
+        .partition(|(_, item)| matches!(item, MapItem::LineItem(_)));
+
+    match draw_order {
+        DrawOrder::FileOrder => items,
+        DrawOrder::PointsOnTop => lines.into_iter().chain(others).collect(),
+        DrawOrder::LinesOnTop => others.into_iter().chain(lines).collect(),
+    }
+}
+
+/// Compares items for `RenderOptions::sort_output`: lines before points before
+/// texts, then by color, coordinates and label or text.
+fn canonical_order(a: &MapItem, b: &MapItem) -> Ordering {
+    let key = |item: &MapItem| -> (u8, [u8; 3], Vec<f32>) {
+        match item {
+            MapItem::LineItem(line) => (
+                0,
+                [line.color.r, line.color.g, line.color.b],
+                vec![
+                    line.from.x,
+                    line.from.y,
+                    line.from.z,
+                    line.to.x,
+                    line.to.y,
+                    line.to.z,
+                ],
+            ),
+            MapItem::PointItem(point) => (
+                1,
+                [point.color.r, point.color.g, point.color.b],
+                vec![point.point.x, point.point.y, point.point.z],
+            ),
+            MapItem::TextItem(text) => (
+                2,
+                [text.color.r, text.color.g, text.color.b],
+                vec![text.point.x, text.point.y, text.point.z, text.size],
+            ),
+            _ => (3, [0; 3], vec![]),
+        }
+    };
+    let label = |item: &MapItem| match item {
+        MapItem::PointItem(point) => point.label.clone(),
+        MapItem::TextItem(text) => text.text.clone(),
+        _ => String::new(),
+    };
+
+    let (a_kind, a_color, a_coordinates) = key(a);
+    let (b_kind, b_color, b_coordinates) = key(b);
+
+    a_kind
+        .cmp(&b_kind)
+        .then(a_color.cmp(&b_color))
+        .then_with(|| {
+            a_coordinates
+                .iter()
+                .zip(&b_coordinates)
+                .map(|(a, b)| a.total_cmp(b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        })
+        .then_with(|| label(a).cmp(&label(b)))
+}
+
+pub fn map_draw(map_items: &MapItems, out_file: &Path) -> Result<(), anyhow::Error> {
+    map_draw_with(map_items, out_file, &RenderOptions::default())
+}
+
+/// Writes the map to `out_file` in the format its extension names: `.svg`
+/// writes `map_svg`'s output as it is, without rendering, `.png` a PNG as
+/// `map_draw_png` does and, with the `webp` feature, `.webp` a lossless WebP
+/// as `map_draw_webp` does. Any other extension, or none, is an error.
+pub fn map_draw_with(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    let extension = out_file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("svg") => std::fs::write(out_file, map_svg(map_items, render_options))
+            .with_context(|| format!("Failed to write {}", out_file.display())),
+        Some("png") => map_draw_png(map_items, out_file, render_options),
+        #[cfg(feature = "webp")]
+        Some("webp") => map_draw_webp(map_items, out_file, render_options),
+        Some(extension) => Err(anyhow!(
+            "Can't draw a map to {}: unsupported extension .{}",
+            out_file.display(),
+            extension
+        )),
+        None => Err(anyhow!(
+            "Can't draw a map to {}: it has no extension to pick a format by",
+            out_file.display()
+        )),
+    }
+}
+
+/// Renders the map and writes it to `out_file` as a PNG, whatever its
+/// extension.
+pub fn map_draw_png(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    // Render before creating the file, so a failed render leaves no empty
+    // file behind.
+    let rendered = map_render(map_items, render_options)?;
+
+    let mut out = std::io::BufWriter::new(create_file(out_file)?);
+    encode_png(&rendered.pixmap, render_options.dpi, &mut out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Draws the map once per factor in `scales`, each at `render_options.scale`
+/// times the factor, to the paths `scaled_path` names after `out_file`, such
+/// as `map@2x.png`. The SVG is built and parsed once for all of them, unless
+/// points are clustered or labels have a minimum scale, since cluster sizes
+/// and shown labels depend on the scale. Returns the paths written.
+pub fn map_draw_scales(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+    scales: &[f32],
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let scale_dependent =
+        render_options.cluster_radius.is_some() || render_options.label_min_scale.is_some();
+    let shared_tree = if scale_dependent {
+        None
+    } else {
+        Some(parse_svg(
+            &raster_document(map_items, render_options),
+            render_options,
+        )?)
+    };
+
+    let mut paths = Vec::with_capacity(scales.len());
+    for &factor in scales {
+        let scale = render_options.scale * factor;
+        let pixmap = match &shared_tree {
+            Some(tree) => rasterize(tree, scale, render_options)?,
+            None => {
+                let options = RenderOptions {
+                    scale,
+                    ..render_options.clone()
+                };
+                render_svg(&raster_document(map_items, &options), &options)?
+            }
+        };
+
+        let pixmap = match render_options.autocrop {
+            Some(margin) => content_rect(&pixmap, render_options.background, margin)
+                .and_then(|rect| pixmap.clone_rect(rect))
+                .unwrap_or(pixmap),
+            None => pixmap,
+        };
+
+        let path = scaled_path(out_file, factor);
+        let mut out = std::io::BufWriter::new(create_file(&path)?);
+        encode_png(&pixmap, render_options.dpi, &mut out)?;
+        out.flush()?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Draws one PNG per color in the map, holding only that color's items, to
+/// the paths `color_path` names after `out_file`, such as `map-ff0000.png`.
+/// Every file has the whole map's extent, so they line up as layers. Returns
+/// the paths written.
+pub fn map_draw_by_color(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let options = RenderOptions {
+        view_bounds: Some(BoundingBox::from_view_box(plane_view_box(
+            map_items,
+            render_options,
+        ))),
+        // Every layer has to be the same size.
+        autocrop: None,
+        ..render_options.clone()
+    };
+
+    let groups = map_items.partition_by_color();
+    let mut paths = Vec::with_capacity(groups.len());
+    for (color, group) in groups {
+        let path = color_path(out_file, color);
+        map_draw_png(&group, &path, &options)?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// The width and height of a web map tile, in pixels.
+pub const TILE_SIZE: u32 = 256;
+
+/// The deepest zoom level tiles can be drawn at, splitting the map into
+/// 2^10 tiles across.
+pub const MAX_TILE_ZOOM: u32 = 10;
+
+/// A web map tile: `x` tiles across and `y` down from the top left of the
+/// 2^`z` by 2^`z` grid that zoom level `z` splits the map into. Written
+/// `z/x/y`, as tile paths and URLs are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TileId {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// Every tile from zoom 0 up to `max_zoom`, zoom by zoom.
+    pub fn up_to_zoom(max_zoom: u32) -> impl Iterator<Item = TileId> {
+        (0..=max_zoom.min(MAX_TILE_ZOOM)).flat_map(|z| {
+            let tiles = 1 << z;
+            (0..tiles).flat_map(move |x| (0..tiles).map(move |y| TileId { z, x, y }))
+        })
+    }
+}
+
+impl std::fmt::Display for TileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.z, self.x, self.y)
+    }
+}
+
+impl FromStr for TileId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split('/').map(str::parse::<u32>).collect();
+        let tile = match parts[..] {
+            [Ok(z), Ok(x), Ok(y)] => TileId { z, x, y },
+            _ => return Err(anyhow!("Expected a tile as z/x/y, not {}", s)),
+        };
+        if tile.z > MAX_TILE_ZOOM || tile.x >> tile.z != 0 || tile.y >> tile.z != 0 {
+            return Err(anyhow!("There is no tile {}", s));
+        }
+
+        Ok(tile)
+    }
+}
+
+/// Renders one `TILE_SIZE` square tile of the map. Zoom 0 is a single tile
+/// holding the whole view, squared up on its longer side from the top left,
+/// and each zoom level splits every tile of the one before into four. The
+/// tiles past the short side of a view that isn't square are blank.
+pub fn map_render_tile(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+    tile: TileId,
+) -> Result<RenderedMap, anyhow::Error> {
+    let (min_x, min_y, width, height) = map_view_box_with(map_items, render_options);
+    let side = width.max(height) / (1u32 << tile.z) as f32;
+    let view_box = (
+        min_x + tile.x as f32 * side,
+        min_y + tile.y as f32 * side,
+        side,
+        side,
+    );
+    let options = RenderOptions {
+        // Mirroring the tile's view box back gives it in the plane's
+        // coordinates.
+        view_bounds: Some(BoundingBox::from_view_box(
+            render_options.orientation.apply_view_box(view_box),
+        )),
+        frame_size: Some(FrameSize {
+            width: TILE_SIZE,
+            height: TILE_SIZE,
+        }),
+        frame_margin: 0,
+        autocrop: None,
+        ..render_options.clone()
+    };
+
+    map_render(map_items, &options)
+}
+
+/// The tiles from zoom 0 up to `max_zoom` that `map_render_tile` draws part
+/// of the view in, zoom by zoom, leaving out the blank ones past the short
+/// side of a view that isn't square. They are listed as they are iterated.
+pub fn map_tiles(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+    max_zoom: u32,
+) -> impl Iterator<Item = TileId> {
+    let (_, _, width, height) = map_view_box_with(map_items, render_options);
+    let side = width.max(height) as f64;
+    (0..=max_zoom.min(MAX_TILE_ZOOM)).flat_map(move |z| {
+        let tiles = 1u32 << z;
+        let across = |length: f32| {
+            let count = (length as f64 / side * tiles as f64).ceil();
+            if count.is_finite() {
+                (count as u32).clamp(1, tiles)
+            } else {
+                1
+            }
+        };
+        let (columns, rows) = (across(width), across(height));
+        (0..columns).flat_map(move |x| (0..rows).map(move |y| TileId { z, x, y }))
+    })
+}
+
+/// Draws one tile of the map, as `map_render_tile` renders it, to
+/// `out_file` as a PNG.
+pub fn map_draw_tile(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+    tile: TileId,
+) -> Result<(), anyhow::Error> {
+    let rendered = map_render_tile(map_items, render_options, tile)?;
+
+    let mut out = std::io::BufWriter::new(create_file(out_file)?);
+    encode_png(&rendered.pixmap, render_options.dpi, &mut out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// How long each frame of a Z animation shows, in hundredths of a second.
+const Z_FRAME_DELAY: u16 = 50;
+
+/// The `gif` quantizer speed, from 1 (best colors) to 30 (fastest). Maps
+/// use few colors, so the fast end still looks right.
+const GIF_QUANTIZER_SPEED: i32 = 10;
+
+/// Draws an animated GIF to `out_file` with one frame for each of `bands`
+/// equal slices of the map's Z range, from the lowest up, picking each
+/// frame's items with `filter_z`. Every frame has the whole map's extent, so
+/// they line up. A line only shows in frames that hold both of its ends, and
+/// since GIF has no partial transparency, a `background` gives smoother
+/// edges.
+pub fn map_draw_z_animation(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+    bands: usize,
+    mode: ZBandMode,
+) -> Result<(), anyhow::Error> {
+    if bands == 0 {
+        return Err(anyhow!("A Z animation needs at least one band"));
+    }
+
+    let (min_z, max_z) = map_items.z_range().unwrap_or((0.0, 0.0));
+    let band_height = (max_z - min_z) / bands as f32;
+    let options = RenderOptions {
+        view_bounds: Some(BoundingBox::from_view_box(plane_view_box(
+            map_items,
+            render_options,
+        ))),
+        // Every frame has to be the same size.
+        autocrop: None,
+        ..render_options.clone()
+    };
+
+    let mut frames = Vec::with_capacity(bands);
+    for band in 0..bands {
+        let bottom = match mode {
+            ZBandMode::Exclusive => min_z + band_height * band as f32,
+            ZBandMode::Cumulative => min_z,
+        };
+        // End the last band at max_z itself, so rounding can't drop the
+        // highest items.
+        let top = if band + 1 == bands {
+            max_z
+        } else {
+            min_z + band_height * (band + 1) as f32
+        };
+
+        frames.push(map_render(&map_items.filter_z(bottom, top), &options)?.pixmap);
+    }
+
+    let (width, height) = (frames[0].width(), frames[0].height());
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(anyhow!(
+            "A {}x{} pixel map is too large for a GIF",
+            width,
+            height
+        ));
+    };
+
+    let mut out = std::io::BufWriter::new(create_file(out_file)?);
+    let mut encoder = gif::Encoder::new(&mut out, gif_width, gif_height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for pixmap in &frames {
+        let mut data = rgba_data(pixmap);
+        let mut frame =
+            gif::Frame::from_rgba_speed(gif_width, gif_height, &mut data, GIF_QUANTIZER_SPEED);
+        frame.delay = Z_FRAME_DELAY;
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame)?;
+    }
+    drop(encoder);
+    out.flush()?;
+
+    Ok(())
+}
+
+/// `out_file` with `@{factor}x` added before its extension, so `map.png` at
+/// 2 becomes `map@2x.png` and at 1.5 `map@1.5x.png`.
+pub fn scaled_path(out_file: &Path, factor: f32) -> PathBuf {
+    let stem = out_file.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match out_file.extension() {
+        Some(extension) => format!("{}@{}x.{}", stem, factor, extension.to_string_lossy()),
+        None => format!("{}@{}x", stem, factor),
+    };
+
+    out_file.with_file_name(name)
+}
+
+/// Renders the map items and streams the encoded PNG to `out`.
+pub fn map_render_to_writer(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+    out: impl Write,
+) -> Result<(), anyhow::Error> {
+    let rendered = map_render(map_items, render_options)?;
+
+    encode_png(&rendered.pixmap, render_options.dpi, out)
+}
+
+/// Renders the map items and streams them to `out` as a lossless WebP, which
+/// keeps every pixel of thin lines exactly. Unlike PNG, the WebP doesn't
+/// record `dpi`.
+#[cfg(feature = "webp")]
+pub fn map_render_webp_to_writer(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+    out: impl Write,
+) -> Result<(), anyhow::Error> {
+    let rendered = map_render(map_items, render_options)?;
+
+    encode_lossless_webp(&rendered.pixmap, out)
+}
+
+/// Like `map_draw_png`, writing a lossless WebP as
+/// `map_render_webp_to_writer` does.
+#[cfg(feature = "webp")]
+pub fn map_draw_webp(
+    map_items: &MapItems,
+    out_file: &Path,
+    render_options: &RenderOptions,
+) -> Result<(), anyhow::Error> {
+    let rendered = map_render(map_items, render_options)?;
+
+    let mut out = std::io::BufWriter::new(create_file(out_file)?);
+    encode_lossless_webp(&rendered.pixmap, &mut out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "webp")]
+fn encode_lossless_webp(pixmap: &tiny_skia::Pixmap, out: impl Write) -> Result<(), anyhow::Error> {
+    image_webp::WebPEncoder::new(out)
+        .encode(
+            &rgba_data(pixmap),
+            pixmap.width(),
+            pixmap.height(),
+            image_webp::ColorType::Rgba8,
+        )
+        .context("Failed to encode the WebP")
+}
+
+/// A map rendered in memory, along with the geometry needed to relate its
+/// pixels back to map coordinates.
+pub struct RenderedMap {
+    pub pixmap: tiny_skia::Pixmap,
+    /// The rendered extent, in map units.
+    pub bounding_box: BoundingBox,
+    /// Output pixels per map unit.
+    pub scale: f32,
+    /// The plane the map was drawn in, which `bounding_box` is measured in.
+    pub plane: Plane,
+    /// The axes that were mirrored when drawing.
+    pub orientation: Orientation,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RenderedMap {
+    /// The pixel position `point` was drawn at, measured from the top left
+    /// corner of the pixmap.
+    pub fn world_to_pixel(&self, point: &Point) -> (f32, f32) {
+        let (x, y) = self.plane.project(point);
+        let bounds = &self.bounding_box;
+        let x = if self.orientation.flip_x {
+            bounds.max_x - x
+        } else {
+            x - bounds.min_x
+        };
+        let y = if self.orientation.flip_y {
+            bounds.max_y - y
+        } else {
+            y - bounds.min_y
+        };
+
+        (x * self.scale, y * self.scale)
+    }
+
+    /// The map coordinates drawn at a pixel position, the inverse of
+    /// `world_to_pixel`. The coordinates are in `plane`, so for the default
+    /// plane they are X and Y, and the third axis can't be recovered.
+    pub fn pixel_to_world(&self, pixel_x: f32, pixel_y: f32) -> (f32, f32) {
+        let (x, y) = (pixel_x / self.scale, pixel_y / self.scale);
+        let bounds = &self.bounding_box;
+
+        (
+            if self.orientation.flip_x {
+                bounds.max_x - x
+            } else {
+                bounds.min_x + x
+            },
+            if self.orientation.flip_y {
+                bounds.max_y - y
+            } else {
+                bounds.min_y + y
+            },
+        )
+    }
+}
+
+/// Renders the map items to a pixmap in memory.
+pub fn map_render(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<RenderedMap, anyhow::Error> {
+    let framed_options;
+    let render_options = match render_options.frame_size {
+        Some(frame) => {
+            let (_, _, width, height) = plane_view_box(map_items, render_options);
+            framed_options = RenderOptions {
+                scale: frame
+                    .inset(render_options.frame_margin)
+                    .fit_scale(width, height),
+                ..render_options.clone()
+            };
+            &framed_options
+        }
+        None => render_options,
+    };
+
+    let svg = raster_document(map_items, render_options);
+    let pixmap = render_svg(&svg, render_options)?;
+
+    let rendered = RenderedMap {
+        bounding_box: BoundingBox::from_view_box(plane_view_box(map_items, render_options)),
+        scale: render_options.scale,
+        plane: render_options.plane,
+        orientation: render_options.orientation,
+        width: pixmap.width(),
+        height: pixmap.height(),
+        pixmap,
+    };
+
+    if let Some(frame) = render_options.frame_size {
+        let letterbox = render_options.letterbox_color.or(render_options.background);
+        return rendered.framed(frame, letterbox);
+    }
+
+    Ok(match render_options.autocrop {
+        Some(margin) => rendered.cropped_to_content(render_options.background, margin),
+        None => rendered,
+    })
+}
+
+impl RenderedMap {
+    /// Centers the pixmap in one of the frame's size, filled with `letterbox`
+    /// or transparent, growing `bounding_box` to match.
+    fn framed(
+        self,
+        frame: FrameSize,
+        letterbox: Option<Color>,
+    ) -> Result<RenderedMap, anyhow::Error> {
+        let mut pixmap = tiny_skia::Pixmap::new(frame.width, frame.height)
+            .ok_or_else(|| anyhow!("Could not create a pixmap"))?;
+        if let Some(color) = letterbox {
+            pixmap.fill(tiny_skia::Color::from_rgba8(color.r, color.g, color.b, 255));
+        }
+
+        // Rounding can leave the map a pixel larger than the frame, which
+        // then loses its edge.
+        let left = (frame.width as i32 - self.width as i32) / 2;
+        let top = (frame.height as i32 - self.height as i32) / 2;
+        pixmap.draw_pixmap(
+            left,
+            top,
+            self.pixmap.as_ref(),
+            &tiny_skia::PixmapPaint::default(),
+            tiny_skia::Transform::identity(),
+            None,
+        );
+
+        let (x0, y0) = self.pixel_to_world(-left as f32, -top as f32);
+        let (x1, y1) = self.pixel_to_world(
+            (frame.width as i32 - left) as f32,
+            (frame.height as i32 - top) as f32,
+        );
+
+        Ok(RenderedMap {
+            bounding_box: BoundingBox {
+                min_x: x0.min(x1),
+                min_y: y0.min(y1),
+                max_x: x0.max(x1),
+                max_y: y0.max(y1),
+            },
+            width: frame.width,
+            height: frame.height,
+            pixmap,
+            ..self
+        })
+    }
+
+    /// Crops the pixmap as `RenderOptions::autocrop` describes, shrinking
+    /// `bounding_box` to match.
+    fn cropped_to_content(self, background: Option<Color>, margin: u32) -> RenderedMap {
+        let Some(rect) = content_rect(&self.pixmap, background, margin) else {
+            return self;
+        };
+        let Some(pixmap) = self.pixmap.clone_rect(rect) else {
+            return self;
+        };
+
+        let (left, top) = (rect.x() as f32, rect.y() as f32);
+        let (right, bottom) = (left + rect.width() as f32, top + rect.height() as f32);
+        let (x0, y0) = self.pixel_to_world(left, top);
+        let (x1, y1) = self.pixel_to_world(right, bottom);
+
+        RenderedMap {
+            bounding_box: BoundingBox {
+                min_x: x0.min(x1),
+                min_y: y0.min(y1),
+                max_x: x0.max(x1),
+                max_y: y0.max(y1),
+            },
+            width: pixmap.width(),
+            height: pixmap.height(),
+            pixmap,
+            ..self
+        }
+    }
+}
+
+/// The smallest rectangle holding every pixel that differs from an opaque
+/// `background`, or every pixel that isn't fully transparent without one,
+/// grown by `margin` pixels on each side as far as the pixmap allows. `None`
+/// if there are no such pixels.
+fn content_rect(
+    pixmap: &tiny_skia::Pixmap,
+    background: Option<Color>,
+    margin: u32,
+) -> Option<tiny_skia::IntRect> {
+    let is_content = |pixel: &tiny_skia::PremultipliedColorU8| match background {
+        Some(background) => {
+            let color = pixel.demultiply();
+            (color.red(), color.green(), color.blue(), color.alpha())
+                != (background.r, background.g, background.b, 255)
+        }
+        None => pixel.alpha() > 0,
+    };
+
+    let width = pixmap.width();
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (index, pixel) in pixmap.pixels().iter().enumerate() {
+        if is_content(pixel) {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    if left > right {
+        return None;
+    }
+
+    let (left, top) = (left.saturating_sub(margin), top.saturating_sub(margin));
+    let right = (right + margin).min(width);
+    let bottom = (bottom + margin).min(pixmap.height());
+
+    tiny_skia::IntRect::from_xywh(left as i32, top as i32, right - left, bottom - top)
+}
+
+/// Renders the map items to PNG bytes in memory. This doesn't touch the
+/// filesystem, so it is usable from WebAssembly.
+pub fn map_render_png_bytes(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut png = Vec::new();
+    map_render_to_writer(map_items, render_options, &mut png)?;
+
+    Ok(png)
+}
+
+/// Renders the map items to a `data:image/png;base64,...` URI, for use as an
+/// `<img>` source without writing a separate file.
+pub fn map_render_data_uri(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let png = map_render_png_bytes(map_items, render_options)?;
+
+    Ok(format!("data:image/png;base64,{}", base64_encode(&png)))
+}
+
+/// Standard, padded base64.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Renders an SVG document to PNG bytes in memory using only the bundled
+/// font.
+pub fn render_svg_to_png_bytes(svg: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let pixmap = render_svg(svg, &RenderOptions::default())?;
+
+    let mut png = Vec::new();
+    encode_png(&pixmap, None, &mut png)?;
+
+    Ok(png)
+}
+
+/// The scale at which the map prints at `dpi`. With a `print_width` in
+/// inches, the map's width is fitted to it; otherwise one map unit is one
+/// CSS pixel, or 1/96 of an inch.
+pub fn dpi_scale(map_items: &MapItems, dpi: f32, print_width: Option<f32>) -> f32 {
+    match print_width {
+        Some(inches) => dpi * inches / map_view_box(map_items).2,
+        None => dpi / 96.0,
+    }
+}
+
+/// The scale to draw at, from the given `dpi` and `scale` and the `base`
+/// options they override. A given `dpi` wins over a given `scale`, which wins
+/// over the `base` options' dpi and then their scale, so that a `scale` is
+/// kept under a preset or config that sets a dpi.
+pub fn chosen_scale(
+    map_items: &MapItems,
+    dpi: Option<f32>,
+    scale: Option<f32>,
+    base: &RenderOptions,
+    print_width: Option<f32>,
+) -> f32 {
+    match (dpi, scale, base.dpi) {
+        (Some(dpi), _, _) | (None, None, Some(dpi)) => dpi_scale(map_items, dpi, print_width),
+        (None, Some(scale), _) => scale,
+        (None, None, None) => base.scale,
+    }
+}
+
+/// Encodes the pixmap as a PNG, recording `dpi` in a pHYs chunk if given.
+fn encode_png(
+    pixmap: &tiny_skia::Pixmap,
+    dpi: Option<f32>,
+    out: impl Write,
+) -> Result<(), anyhow::Error> {
+    let data = rgba_data(pixmap);
+
+    let mut encoder = png::Encoder::new(out, pixmap.width(), pixmap.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Some(dpi) = dpi {
+        let pixels_per_meter = (dpi / 0.0254).round() as u32;
+        encoder.set_pixel_dims(Some(png::PixelDimensions {
+            xppu: pixels_per_meter,
+            yppu: pixels_per_meter,
+            unit: png::Unit::Meter,
+        }));
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// The pixmap's pixels as straight, not premultiplied, RGBA bytes.
+fn rgba_data(pixmap: &tiny_skia::Pixmap) -> Vec<u8> {
+    let mut data = Vec::with_capacity(pixmap.data().len());
+    for pixel in pixmap.pixels() {
+        let color = pixel.demultiply();
+        data.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+    }
+
+    data
+}
+
+fn render_svg(
+    svg: &str,
+    render_options: &RenderOptions,
+) -> Result<tiny_skia::Pixmap, anyhow::Error> {
+    rasterize(
+        &parse_svg(svg, render_options)?,
+        render_options.scale,
+        render_options,
+    )
+}
+
+fn parse_svg(svg: &str, render_options: &RenderOptions) -> Result<usvg::Tree, anyhow::Error> {
+    let mut options = usvg::Options::default();
+    options.fontdb.load_font_data(BUNDLED_FONT.to_vec());
+    options.fontdb.set_sans_serif_family(BUNDLED_FONT_FAMILY);
+    #[cfg(not(target_arch = "wasm32"))]
+    if render_options.system_fonts {
+        options.fontdb.load_system_fonts();
+    }
+
+    Ok(usvg::Tree::from_data(svg.as_bytes(), &options.to_ref())?)
+}
+
+/// The most pixels `map_render` draws, which take 4 bytes each. Larger
+/// renders fail instead of trying to allocate.
+pub const MAX_PIXELS: u64 = 1 << 28;
+
+/// Draws `rtree` at `scale` over `render_options.background_image`, if any,
+/// which is itself drawn over `render_options.background`.
+fn rasterize(
+    rtree: &usvg::Tree,
+    scale: f32,
+    render_options: &RenderOptions,
+) -> Result<tiny_skia::Pixmap, anyhow::Error> {
+    if !(scale > 0.0 && scale.is_finite()) {
+        return Err(anyhow!("Can't draw at a scale of {}", scale));
+    }
+
+    let size = rtree.svg_node().size;
+    let (width, height) = (
+        (size.width() * scale as f64).ceil(),
+        (size.height() * scale as f64).ceil(),
+    );
+    if width * height > MAX_PIXELS as f64 {
+        return Err(anyhow!(
+            "A {}x{} pixel map is over the limit of {} pixels; draw it at a smaller scale",
+            width,
+            height,
+            MAX_PIXELS
+        ));
+    }
+
+    let fit_to = usvg::FitTo::Zoom(scale);
+    let pixmap_size = fit_to
+        .fit_to(size.to_screen_size())
+        .ok_or_else(|| anyhow!("A map drawn at a scale of {} is empty", scale))?;
+
+    let mut pixmap = tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height())
+        .ok_or_else(|| anyhow!("Could not create a pixmap"))?;
+
+    if let Some(path) = &render_options.background_image {
+        if let Some(background) = render_options.background {
+            pixmap.fill(tiny_skia::Color::from_rgba8(
+                background.r,
+                background.g,
+                background.b,
+                255,
+            ));
+        }
+        draw_stretched_png(&mut pixmap, path)?;
+    }
+
+    resvg::render(
+        rtree,
+        fit_to,
+        tiny_skia::Transform::default(),
+        pixmap.as_mut(),
+    )
+    .ok_or_else(|| anyhow!("Failed to render the SVG"))?;
+
+    Ok(pixmap)
+}
+
+/// Draws the PNG at `path` over the whole of `pixmap`, stretching it to fit.
+fn draw_stretched_png(pixmap: &mut tiny_skia::Pixmap, path: &Path) -> Result<(), anyhow::Error> {
+    let image = tiny_skia::Pixmap::load_png(path)
+        .with_context(|| format!("Failed to read the background image {}", path.display()))?;
+
+    let transform = tiny_skia::Transform::from_scale(
+        pixmap.width() as f32 / image.width() as f32,
+        pixmap.height() as f32 / image.height() as f32,
+    );
+    let paint = tiny_skia::PixmapPaint {
+        quality: tiny_skia::FilterQuality::Bicubic,
+        ..Default::default()
+    };
+    pixmap
+        .draw_pixmap(0, 0, image.as_ref(), &paint, transform, None)
+        .ok_or_else(|| anyhow!("Failed to draw the background image"))?;
+
+    Ok(())
+}
+
+/// Computes the `(min_x, min_y, width, height)` extent of the map items.
+///
+/// An axis with no extent (a single point, colinear points, or an empty map)
+/// is widened to `MIN_VIEW_BOX_EXTENT` centered on the items so that the
+/// render still has a valid, non-empty size. An empty map is centered on
+/// the origin.
+pub fn map_view_box(map_items: &MapItems) -> (f32, f32, f32, f32) {
+    map_view_box_with(map_items, &RenderOptions::default())
+}
+
+/// What a page overlaying a rendered map needs to know about it without
+/// reading the SVG: the view box it was drawn with and how many items it
+/// holds.
+///
+/// With the `serde` feature this serializes with the field names as keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MapMeta {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Every item, including texts and raw lines.
+    pub items: usize,
+    pub points: usize,
+    pub lines: usize,
+}
+
+/// Describes the map as `map_view_box_with` frames it for these options.
+/// The view box is the map's own, before `frame_size` letterboxes it.
+pub fn map_meta(map_items: &MapItems, render_options: &RenderOptions) -> MapMeta {
+    let (min_x, min_y, width, height) = map_view_box_with(map_items, render_options);
+    let stats = map_items.stats();
+    MapMeta {
+        min_x,
+        min_y,
+        width,
+        height,
+        items: map_items.len(),
+        points: stats.points,
+        lines: stats.lines,
+    }
+}
+
+/// Computes the view box as `map_view_box` does, in the coordinates the
+/// render options draw with and from the items `fit` picks, or from
+/// `view_bounds` if it is set.
+pub fn map_view_box_with(
+    map_items: &MapItems,
+    render_options: &RenderOptions,
+) -> (f32, f32, f32, f32) {
+    render_options
+        .orientation
+        .apply_view_box(plane_view_box(map_items, render_options))
+}
+
+/// The view box in the coordinates of `plane`, before `orientation` mirrors
+/// it.
+fn plane_view_box(map_items: &MapItems, render_options: &RenderOptions) -> (f32, f32, f32, f32) {
+    if let Some(bounds) = render_options.view_bounds {
+        let (min_x, width) = min_extent(bounds.min_x, bounds.width());
+        let (min_y, height) = min_extent(bounds.min_y, bounds.height());
+        return (min_x, min_y, width, height);
+    }
+
+    let mut xs: Vec<f32> = vec![];
+    let mut ys: Vec<f32> = vec![];
+    let mut push = |(x, y): (f32, f32)| {
+        xs.push(x);
+        ys.push(y);
+    };
+    let plane = render_options.plane;
+
+    let points_only = render_options.fit == Fit::Points
+        && map_items
+            .items
+            .iter()
+            .any(|item| matches!(item, MapItem::PointItem(_)));
+    let fitted = map_items
+        .items
+        .iter()
+        .filter(|item| !points_only || matches!(item, MapItem::PointItem(_)));
+    for item in fitted {
+        match item {
+            MapItem::LineItem(line) => {
+                push(plane.project(&line.from));
+                push(plane.project(&line.to));
+            }
+            MapItem::PointItem(point) => push(plane.project(&point.point)),
+            MapItem::TextItem(text) => push(plane.project(&text.point)),
+            MapItem::Raw(_) => {}
+        }
+
+        if render_options.fit_labels {
+            // The labels are laid out in drawn coordinates, which
+            // `orientation` mirrors back into plane coordinates.
+            for (min_x, min_y, max_x, max_y) in item_text_extents(item, render_options) {
+                push(render_options.orientation.apply((min_x, min_y)));
+                push(render_options.orientation.apply((max_x, max_y)));
+            }
+        }
+    }
+
+    xs.sort_by(float_ord);
+    ys.sort_by(float_ord);
+
+    let min_x = xs.first().unwrap_or(&0.0);
+    let min_y = ys.first().unwrap_or(&0.0);
+    let max_x = xs.last().unwrap_or(&0.0);
+    let max_y = ys.last().unwrap_or(&0.0);
+
+    let (min_x, width) = min_extent(*min_x, max_x - min_x);
+    let (min_y, height) = min_extent(*min_y, max_y - min_y);
+    let padding = render_options.padding;
+
+    (
+        min_x - padding,
+        min_y - padding,
+        width + 2.0 * padding,
+        height + 2.0 * padding,
+    )
+}
+
+/// The smallest width or height `map_view_box` will return.
+pub const MIN_VIEW_BOX_EXTENT: f32 = 1.0;
+
+fn min_extent(min: f32, extent: f32) -> (f32, f32) {
+    if extent > 0.0 {
+        (min, extent)
+    } else {
+        (min - MIN_VIEW_BOX_EXTENT / 2.0, MIN_VIEW_BOX_EXTENT)
+    }
+}
+
+/// Escapes text for use in SVG attribute values and element content.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn float_ord(a: &f32, b: &f32) -> Ordering {
+    if a < b {
+        Ordering::Less
+    } else if b < a {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map_bounds::{BoundingBox, Circle},
+        map_draw::{
+            base64_encode, chosen_scale, dpi_scale, item_ids, map_draw_scales, map_draw_with,
+            map_draw_z_animation, map_html, map_render, map_render_data_uri, map_render_png_bytes,
+            map_render_tile, map_render_to_writer, map_svg, map_tiles, map_view_box,
+            map_view_box_with, millimetres_to_pixels, minified_tag, render_svg, Corner, DrawOrder,
+            Fit, FocusRegion, FrameSize, Inset, ItemHook, LabelHalo, Orientation, PageSize, Plane,
+            RenderOptions, Stamp, SvgFormatting, SvgMetadata, SvgSize, TileId, ZBandMode,
+            MAX_TILE_ZOOM, TILE_SIZE,
+        },
+        map_items::{Color, LineItem, MapItem, MapItems, Point, PointItem},
+    };
+
+    fn map_items(lines: &[&str]) -> MapItems {
+        let items = lines
+            .iter()
+            .map(|line| match &line[..1] {
+                "P" => MapItem::PointItem(PointItem::try_from(*line).unwrap()),
+                _ => MapItem::LineItem(LineItem::try_from(*line).unwrap()),
+            })
+            .collect();
+
+        MapItems { items }
+    }
+
+    #[test]
+    fn view_box_multiple_items() {
+        let map_items = map_items(&[
+            "L 10.0, 20.0, 0.0, 30.0, -5.0, 0.0, 0, 0, 0",
+            "P -4.0, 8.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+
+        assert_eq!(map_view_box(&map_items), (-4.0, -5.0, 34.0, 25.0));
+    }
+
+    #[test]
+    fn view_box_negative_coordinates() {
+        let map_items = map_items(&[
+            "L -10.0, -20.0, 0.0, -30.0, -5.0, 0.0, 0, 0, 0",
+            "P -40.0, -8.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+
+        assert_eq!(map_view_box(&map_items), (-40.0, -20.0, 30.0, 15.0));
+    }
+
+    #[test]
+    fn view_box_single_point_has_minimum_size() {
+        let map_items = map_items(&["P 5.0, 6.0, 0.0, 0, 0, 0, 2, Bank"]);
+
+        assert_eq!(map_view_box(&map_items), (4.5, 5.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn view_box_colinear_points_have_minimum_width() {
+        let map_items = map_items(&[
+            "P 5.0, 0.0, 0.0, 0, 0, 0, 2, North",
+            "P 5.0, 10.0, 0.0, 0, 0, 0, 2, Middle",
+            "P 5.0, 20.0, 0.0, 0, 0, 0, 2, South",
+        ]);
+
+        assert_eq!(map_view_box(&map_items), (4.5, 0.0, 1.0, 20.0));
+    }
+
+    #[test]
+    fn view_box_empty_map_is_centered_on_origin() {
+        let map_items = map_items(&[]);
+
+        assert_eq!(map_view_box(&map_items), (-0.5, -0.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn render_png_bytes_in_memory() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 10.0, 0.0, 255, 0, 0"]);
+
+        let png = map_render_png_bytes(&map_items, &RenderOptions::default()).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let mut streamed = Vec::new();
+        map_render_to_writer(&map_items, &RenderOptions::default(), &mut streamed).unwrap();
+        assert_eq!(streamed, png);
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn lossless_webp_keeps_every_pixel() {
+        use crate::map_draw::{map_render_webp_to_writer, rgba_data};
+
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 40.0, 17.0, 0.0, 255, 0, 0",
+            "L 0.0, 17.0, 0.0, 40.0, 0.0, 0.0, 0, 128, 255",
+        ]);
+        let options = RenderOptions {
+            scale: 3.0,
+            ..Default::default()
+        };
+
+        let mut webp = Vec::new();
+        map_render_webp_to_writer(&map_items, &options, &mut webp).unwrap();
+        let mut decoder = image_webp::WebPDecoder::new(std::io::Cursor::new(webp)).unwrap();
+        assert!(!decoder.is_lossy());
+        let mut decoded = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut decoded).unwrap();
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!(
+            decoder.dimensions(),
+            (rendered.pixmap.width(), rendered.pixmap.height())
+        );
+        assert_eq!(decoded, rgba_data(&rendered.pixmap));
+    }
+
+    #[test]
+    fn render_reports_geometry() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            scale: 2.0,
+            ..Default::default()
+        };
+
+        let rendered = map_render(&map_items, &options).unwrap();
+
+        assert_eq!(
+            rendered.bounding_box,
+            BoundingBox {
+                min_x: 0.0,
+                min_y: 0.0,
+                max_x: 10.0,
+                max_y: 20.0
+            }
+        );
+        assert_eq!(rendered.scale, 2.0);
+        assert_eq!((rendered.width, rendered.height), (20, 40));
+    }
+
+    #[test]
+    fn points_draw_on_top_of_lines_by_default() {
+        let map_items = map_items(&[
+            "P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank",
+            "L 0.0, 0.0, 0.0, 10.0, 10.0, 0.0, 255, 0, 0",
+        ]);
+
+        let svg = map_svg(&map_items, &RenderOptions::default());
+        assert!(svg.find("<path").unwrap() < svg.find("<circle").unwrap());
+
+        let options = RenderOptions {
+            draw_order: DrawOrder::LinesOnTop,
+            ..Default::default()
+        };
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.find("<circle").unwrap() < svg.find("<path").unwrap());
+    }
+
+    #[test]
+    fn scaled_line_width_follows_length() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 255, 0, 0",
+            "L 0.0, 0.0, 0.0, 0.0, 50.0, 0.0, 255, 0, 0",
+        ]);
+        let options = RenderOptions {
+            scale_line_width: true,
+            min_line_width: 1.0,
+            max_line_width: 3.0,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+
+        assert!(svg.contains("stroke-width=\"3\""));
+        assert!(svg.contains("stroke-width=\"2\""));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("stroke-width"));
+    }
+
+    #[test]
+    fn setting_the_line_width_and_point_radius() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 255, 0, 0, 7",
+            "L 0.0, 0.0, 0.0, 0.0, 50.0, 0.0, 255, 0, 0",
+            "P 5.0, 5.0, 0.0, 0, 0, 0, 3, Bank",
+        ]);
+        let options = RenderOptions {
+            line_width: Some(0.25),
+            point_radius: 1.5,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("stroke-width=\"7\""), "{}", svg);
+        assert!(svg.contains("stroke-width=\"0.25\""), "{}", svg);
+        assert!(svg.contains("r=\"1.5\""), "{}", svg);
+
+        let sized = RenderOptions {
+            size_by_point_type: true,
+            ..options
+        };
+        assert!(map_svg(&map_items, &sized).contains("r=\"2.25\""));
+
+        let svg = map_svg(&map_items, &RenderOptions::default());
+        assert!(svg.contains("r=\"3\""));
+        assert_eq!(svg.matches("stroke-width").count(), 1);
+    }
+
+    #[test]
+    fn point_radius_sizes_zone_links_and_clusters() {
+        let options = RenderOptions {
+            point_radius: 1.5,
+            zone_link_prefix: Some(String::from("to_")),
+            ..Default::default()
+        };
+
+        let zone_link = map_items(&["P 10.0, 10.0, 0.0, 0, 0, 255, 2, to_Steamfont"]);
+        let svg = map_svg(&zone_link, &options);
+        assert!(
+            svg.contains(r#"d="M 10 7.5 l 2.5 2.5 l -2.5 2.5 l -2.5 -2.5 Z""#),
+            "{}",
+            svg
+        );
+        assert!(svg.contains(r#"x="13.5""#), "{}", svg);
+
+        let cluster = map_items(&[
+            "P 1.0, 1.0, 0.0, 255, 0, 0, 2, A",
+            "P 2.0, 2.0, 0.0, 255, 0, 0, 2, B",
+            "P 3.0, 1.0, 0.0, 255, 0, 0, 2, C",
+            "P 2.0, 1.0, 0.0, 255, 0, 0, 2, D",
+        ]);
+        let clustered = RenderOptions {
+            cluster_radius: Some(5.0),
+            ..options
+        };
+        let svg = map_svg(&cluster, &clustered);
+        assert!(
+            svg.contains(r#"r="3" fill="rgb(255,0,0)" class="point-cluster""#),
+            "{}",
+            svg
+        );
+    }
+
+    #[test]
+    fn line_width_field_wins_over_scaling() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 255, 0, 0, 7",
+            "L 0.0, 0.0, 0.0, 0.0, 50.0, 0.0, 255, 0, 0",
+        ]);
+        let options = RenderOptions {
+            scale_line_width: true,
+            min_line_width: 1.0,
+            max_line_width: 3.0,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("stroke-width=\"7\""), "{}", svg);
+        assert!(svg.contains("stroke-width=\"2\""), "{}", svg);
+        assert!(map_svg(&map_items, &RenderOptions::default()).contains("stroke-width=\"7\""));
+    }
+
+    #[test]
+    fn dpi_is_recorded_in_png() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 10.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            dpi: Some(300.0),
+            ..Default::default()
+        };
+
+        let png = map_render_png_bytes(&map_items, &options).unwrap();
+
+        let phys = png.windows(4).position(|w| w == b"pHYs").unwrap();
+        assert_eq!(&png[phys + 4..phys + 8], 11811u32.to_be_bytes());
+        assert_eq!(dpi_scale(&map_items, 300.0, Some(2.0)), 60.0);
+    }
+
+    #[test]
+    fn scale_wins_over_a_base_dpi() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 10.0, 0.0, 255, 0, 0"]);
+        let base = RenderOptions {
+            dpi: Some(300.0),
+            ..Default::default()
+        };
+
+        assert_eq!(chosen_scale(&map_items, None, Some(2.0), &base, None), 2.0);
+        assert_eq!(
+            chosen_scale(&map_items, Some(192.0), Some(3.0), &base, None),
+            2.0
+        );
+        assert_eq!(
+            chosen_scale(&map_items, None, None, &base, None),
+            300.0 / 96.0
+        );
+        assert_eq!(
+            chosen_scale(&map_items, None, None, &RenderOptions::default(), None),
+            1.0
+        );
+    }
+
+    #[test]
+    fn depth_fade_makes_low_lines_fainter() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, -10.0, 10.0, 0.0, -10.0, 255, 0, 0",
+            "L 0.0, 0.0, 10.0, 10.0, 0.0, 10.0, 255, 0, 0",
+        ]);
+        let options = RenderOptions {
+            depth_fade: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+
+        assert!(svg.contains("stroke-opacity=\"0.2\""));
+        assert!(svg.contains("stroke-opacity=\"1\""));
+    }
+
+    #[test]
+    fn line_opacity_combines_with_depth_fade() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, -10.0, 10.0, 0.0, -10.0, 255, 0, 0",
+            "L 0.0, 0.0, 10.0, 10.0, 0.0, 10.0, 255, 0, 0",
+        ]);
+        let options = RenderOptions {
+            line_opacity: 0.5,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert_eq!(svg.matches("stroke-opacity=\"0.5\"").count(), 2);
+
+        let options = RenderOptions {
+            depth_fade: true,
+            ..options
+        };
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("stroke-opacity=\"0.1\""));
+        assert!(svg.contains("stroke-opacity=\"0.5\""));
+    }
+
+    #[test]
+    fn view_box_follows_plane() {
+        let map_items = map_items(&["L 1.0, 2.0, -30.0, 11.0, 22.0, 30.0, 255, 0, 0"]);
+        let options = |plane| RenderOptions {
+            plane,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            map_view_box_with(&map_items, &options(Plane::Xz)),
+            (1.0, -30.0, 10.0, 60.0)
+        );
+        assert_eq!(
+            map_view_box_with(&map_items, &options(Plane::Yz)),
+            (2.0, -30.0, 20.0, 60.0)
+        );
+    }
+
+    #[test]
+    fn label_underscores_are_drawn_as_spaces() {
+        let map_items = map_items(&["P 0.0, 0.0, 0.0, 0, 0, 0, 2, to_East_Commonlands | by_boat"]);
+
+        let svg = map_svg(&map_items, &RenderOptions::default());
+        assert!(svg.contains(r#"class="point-item-label">to East Commonlands</text>"#));
+        assert!(svg.contains(r#"class="point-item-sub-label">by boat</text>"#));
+
+        let svg = map_svg(
+            &map_items,
+            &RenderOptions {
+                keep_label_underscores: true,
+                svg_data_attrs: true,
+                ..Default::default()
+            },
+        );
+        assert!(svg.contains(r#"class="point-item-label">to_East_Commonlands</text>"#));
+        assert!(svg.contains(r#"data-label="to_East_Commonlands""#));
+    }
+
+    #[test]
+    fn view_box_padding() {
+        let options = RenderOptions {
+            padding: 3.0,
+            ..Default::default()
+        };
+
+        let single = map_items(&["P 5.0, 5.0, 0.0, 0, 0, 0, 2, A"]);
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 0, 0, 0, 2, A",
+            "P 10.0, 20.0, 0.0, 0, 0, 0, 2, B",
+        ]);
+        assert_eq!(
+            map_view_box_with(&map_items, &options),
+            (-3.0, -3.0, 16.0, 26.0)
+        );
+        assert_eq!(map_view_box_with(&single, &options), (1.5, 1.5, 7.0, 7.0));
+
+        let cropped = RenderOptions {
+            view_bounds: Some("0,0,10,10".parse().unwrap()),
+            ..options
+        };
+        assert_eq!(
+            map_view_box_with(&map_items, &cropped),
+            (0.0, 0.0, 10.0, 10.0)
+        );
+    }
+
+    #[test]
+    fn view_box_fits_labels() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 0, 0, 0, 2, A",
+            "P 100.0, 0.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+        let options = |flip_y| RenderOptions {
+            fit_labels: true,
+            orientation: Orientation {
+                flip_x: false,
+                flip_y,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(map_view_box(&map_items), (0.0, -0.5, 100.0, 1.0));
+
+        // "Bank" starts past the marker and gap, at 105, and is estimated at
+        // 4 characters of 6.
+        for flip_y in [false, true] {
+            let (min_x, min_y, width, height) = map_view_box_with(&map_items, &options(flip_y));
+            assert_eq!((min_x, width), (0.0, 129.0));
+            assert!((min_y - (10.0 / 3.0 - 9.3)).abs() < 1e-4, "{}", min_y);
+            assert!((height - 11.7).abs() < 1e-4, "{}", height);
+        }
+    }
+
+    #[test]
+    fn data_attrs_are_escaped() {
+        let map_items = map_items(&["P 5.0, 5.0, 12.5, 0, 0, 0, 2, Fish & Chips"]);
+        let options = RenderOptions {
+            svg_data_attrs: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains(r#"data-type="point" data-label="Fish &amp; Chips" data-z="12.5""#));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("data-"));
+    }
+
+    #[test]
+    fn clusters_get_data_attributes_and_member_ids() {
+        let map_items = map_items(&[
+            "P 1.0, 1.0, 0.0, 255, 0, 0, 2, Fish & Chips",
+            "P 2.0, 2.0, 0.0, 255, 0, 0, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            cluster_radius: Some(5.0),
+            svg_data_attrs: true,
+            svg_ids: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(
+            svg.contains(
+                r#"data-type="cluster" data-count="2" data-labels="Fish &amp; Chips|Bank" data-z="0""#
+            ),
+            "{}",
+            svg
+        );
+        let ids = item_ids(&map_items);
+        assert!(
+            svg.contains(&format!(r#"data-members="{} {}""#, ids[0], ids[1])),
+            "{}",
+            svg
+        );
+    }
+
+    #[test]
+    fn item_ids_follow_the_items() {
+        let options = RenderOptions {
+            svg_ids: true,
+            ..Default::default()
+        };
+        let ids = |lines: &[&str]| -> Vec<String> {
+            map_svg(&map_items(lines), &options)
+                .lines()
+                .filter_map(|line| line.strip_prefix("<g id=\""))
+                .map(|line| line.trim_end_matches("\">").to_string())
+                .collect()
+        };
+        let line = "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0";
+        let point = "P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank";
+
+        let both = ids(&[line, point]);
+        assert_eq!(both.len(), 2);
+        assert!(both.iter().all(|id| id.starts_with("item-")));
+        assert_ne!(both[0], both[1]);
+        // Lines are drawn under points either way, each with the same id.
+        assert_eq!(ids(&[point, line]), both);
+        assert_eq!(
+            ids(&[line, line]),
+            vec![both[0].clone(), format!("{}-2", both[0])]
+        );
+        assert!(!map_svg(&map_items(&[line]), &RenderOptions::default()).contains(" id="));
+    }
+
+    #[test]
+    fn html_wraps_the_svg() {
+        let map_items = map_items(&["P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank"]);
+        let options = RenderOptions::default();
+
+        let html = map_html(&map_items, &options);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(&map_svg(&map_items, &options)));
+        assert!(html.contains("<script>"));
+        assert!(!html.contains("id=\"coordinates\""));
+    }
+
+    #[test]
+    fn html_shows_the_cursor_coordinates() {
+        let map_items = map_items(&["P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank"]);
+        let options = RenderOptions {
+            html_cursor_coordinates: true,
+            orientation: "flip-y".parse().unwrap(),
+            ..Default::default()
+        };
+
+        let html = map_html(&map_items, &options);
+        assert!(html.contains("<div id=\"coordinates\"></div>"));
+        assert!(html.contains(
+            r#"const cursor = { axes: ["x", "y"], flipX: false, flipY: true, loc: true };"#
+        ));
+        assert!(html.contains("getScreenCTM().inverse()"));
+
+        let html = map_html(
+            &map_items,
+            &RenderOptions {
+                plane: Plane::Yz,
+                ..options
+            },
+        );
+        assert!(html.contains(r#"axes: ["y", "z"], flipX: false, flipY: true, loc: false"#));
+    }
+
+    #[test]
+    fn clusters_replace_nearby_points() {
+        let map_items = map_items(&[
+            "P 1.0, 1.0, 0.0, 255, 0, 0, 2, A",
+            "P 2.0, 2.0, 0.0, 255, 0, 0, 2, B",
+            "P 3.0, 1.0, 0.0, 255, 0, 0, 2, C",
+            "P 95.0, 95.0, 0.0, 0, 0, 255, 2, Far",
+        ]);
+        let options = RenderOptions {
+            cluster_radius: Some(5.0),
+            scale: 0.5,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert_eq!(svg.matches("class=\"point-cluster\"").count(), 1);
+        assert!(svg.contains(">3</text>"));
+        assert!(svg.contains(">Far</text>"));
+        assert!(!svg.contains(">A</text>"));
+    }
+
+    #[test]
+    fn percent_size_keeps_the_view_box() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 40.0, 20.0, 0.0, 0, 0, 0"]);
+        let options = RenderOptions {
+            svg_size: SvgSize::Percent,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.starts_with(r#"<svg width="100%" height="100%" viewBox="0 0 40 20""#));
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!((rendered.width, rendered.height), (40, 20));
+    }
+
+    #[test]
+    fn heatmap_replaces_points() {
+        let map_items = map_items(&[
+            "P 1.0, 1.0, 0.0, 0, 0, 255, 2, A",
+            "P 2.0, 2.0, 0.0, 0, 0, 255, 2, B",
+            "P 15.0, 1.0, 0.0, 0, 0, 255, 2, C",
+            "L 0.0, 0.0, 0.0, 20.0, 10.0, 0.0, 0, 0, 0",
+        ]);
+        let options = RenderOptions {
+            heatmap_cell_size: Some(10.0),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(!svg.contains("<circle"));
+        assert!(svg.contains(r#"<rect x="0" y="0" width="10" height="10" fill="rgb(252,255,164)""#));
+        assert!(svg.contains(r#"<rect x="10" y="0" width="10" height="10" fill="rgb(212,80,61)""#));
+        assert!(svg.find("<rect").unwrap() < svg.find("<path").unwrap());
+    }
+
+    #[test]
+    fn point_outline_adds_a_stroke() {
+        let map_items = map_items(&["P 5.0, 5.0, 0.0, 255, 0, 0, 2, Bank"]);
+        let options = RenderOptions {
+            point_outline: Some(Color::new(255, 255, 255)),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains(r#"fill="rgb(255,0,0)" stroke="rgb(255,255,255)" stroke-width="1""#));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("stroke="));
+    }
+
+    #[test]
+    fn label_halos_go_under_their_labels() {
+        let map_items = map_items(&["P 5.0, 5.0, 0.0, 255, 255, 0, 2, Bank"]);
+        let options = RenderOptions {
+            label_halo: Some(LabelHalo::Contrasting),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        let halo = svg.find(r#"class="label-halo">Bank</text>"#).unwrap();
+        let label = svg.find(r#"class="point-item-label">Bank</text>"#).unwrap();
+        assert!(halo < label);
+        assert!(svg.contains(r#"fill="rgb(0,0,0)" stroke="rgb(0,0,0)" stroke-width="3""#));
+
+        let options = RenderOptions {
+            label_halo: Some("#ffffff".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(map_svg(&map_items, &options).contains(r#"stroke="rgb(255,255,255)""#));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("label-halo"));
+    }
+
+    #[test]
+    fn background_rect_comes_first() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            background: Some(Color::new(0, 0, 64)),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        let first_element = svg.lines().nth(1).unwrap();
+        assert_eq!(
+            first_element,
+            r#"<rect x="0" y="0" width="10" height="20" fill="rgb(0,0,64)" class="background" />"#
+        );
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("<rect"));
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        let corner = rendered.pixmap.pixel(9, 0).unwrap();
+        assert_eq!((corner.blue(), corner.alpha()), (64, 255));
+    }
+
+    #[test]
+    fn labelling_line_lengths() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 30.0, 40.0, 0.0, 255, 0, 0",
+            "L 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 255, 0, 0",
+        ]);
+        let options = RenderOptions {
+            label_lengths: Some(10.0),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert_eq!(svg.matches("class=\"line-length\"").count(), 1);
+        assert!(svg.contains(r#"<text x="15" y="20""#));
+        assert!(svg.contains(">50</text>"));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("line-length"));
+    }
+
+    #[test]
+    fn pretty_printing_svg() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 255, 0, 0, 2, A",
+            "P 10.0, 10.0, 0.0, 255, 0, 0, 2, ",
+        ]);
+        let options = RenderOptions {
+            point_symbols: true,
+            svg_formatting: SvgFormatting::Pretty,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(
+            "\n  <defs>\n    <circle id=\"point-0\" r=\"3\" fill=\"rgb(255,0,0)\" />\n  </defs>\n"
+        ));
+        assert!(svg.contains("\n  <text "));
+        assert!(svg.ends_with("\n</svg>\n"));
+
+        let compact = map_svg(
+            &map_items,
+            &RenderOptions {
+                point_symbols: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            svg.lines().map(str::trim_start).collect::<Vec<_>>(),
+            compact.lines().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn minifying_svg() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 0.5, -0.25, 0.0, 255, 0, 0",
+            "P 0.0, 10.0, 0.0, 0, 0, 255, 2, Room 0.5",
+        ]);
+        let options = RenderOptions {
+            line_opacity: 0.5,
+            svg_formatting: SvgFormatting::Minify,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert_eq!(svg.lines().count(), 1);
+        assert!(
+            svg.contains(r#"<path d="M0 0L.5 -.25" stroke="rgb(255,0,0)" stroke-opacity=".5"/>"#),
+            "{}",
+            svg
+        );
+        assert!(
+            svg.contains(r#"<circle cy="10" r="3" fill="rgb(0,0,255)"/>"#),
+            "{}",
+            svg
+        );
+        assert!(svg.contains(">Room 0.5</text>"));
+        assert!(!svg.contains("class="));
+
+        // The minified SVG still parses, and draws the same pixels.
+        let compact = RenderOptions {
+            line_opacity: 0.5,
+            ..Default::default()
+        };
+        let minified = render_svg(&svg, &compact).unwrap();
+        let drawn = render_svg(&map_svg(&map_items, &compact), &compact).unwrap();
+        assert_eq!(minified.data(), drawn.data());
+
+        // Inherited properties are kept even at their defaults.
+        assert_eq!(
+            minified_tag(r#"<path stroke-opacity="1" opacity="1" />"#, false),
+            r#"<path stroke-opacity="1"/>"#
+        );
+    }
+
+    #[test]
+    fn drawing_a_minimap_inset() {
+        let full = map_items(&[
+            "L 0.0, 0.0, 0.0, 400.0, 0.0, 0.0, 255, 0, 0",
+            "L 400.0, 0.0, 0.0, 400.0, 400.0, 0.0, 0, 0, 255",
+            "P 10.0, 10.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+        let cropped = full.crop(&"0,0,100,100".parse().unwrap());
+        let options = RenderOptions {
+            view_bounds: Some("0,0,100,100".parse().unwrap()),
+            inset: Some(Inset {
+                map_items: full,
+                corner: Corner::BottomRight,
+            }),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&cropped, &options);
+        let inset = &svg[svg.find(r#"<g class="inset">"#).unwrap()..];
+        // The 400x400 map shrinks to 25x25, 2 units from the bottom right.
+        assert!(inset.contains(r#"<rect x="73" y="73" width="25" height="25""#));
+        assert!(
+            inset.contains(r#"<g transform="translate(73 73) scale(0.0625)" stroke-width="16">"#)
+        );
+        assert_eq!(inset.matches("class=\"line-item\"").count(), 2);
+        assert!(!inset.contains("Bank"));
+        assert!(inset.contains(
+            r#"<rect x="0" y="0" width="100" height="100" fill="none" stroke="rgb(220,40,40)" stroke-width="32" class="inset-view" />"#
+        ));
+        assert!(!map_svg(&cropped, &RenderOptions::default()).contains("inset"));
+        assert!(matches!("top-left".parse(), Ok(Corner::TopLeft)));
+    }
+
+    #[test]
+    fn drawing_sub_labels_under_labels() {
+        let plain = map_items(&["P 5.0, 5.0, 0.0, 255, 0, 0, 2, Bank"]);
+        let map_items = map_items(&["P 5.0, 5.0, 0.0, 255, 0, 0, 2, Bank | closes at night"]);
+
+        let svg = map_svg(&map_items, &RenderOptions::default());
+        assert!(svg.contains(r#"class="point-item-label">Bank</text>"#));
+        assert!(svg.contains(r#"<text x="10" y="17.333332" font-family="DejaVu Sans" font-size="7.5" fill="rgb(255,0,0)" class="point-item-sub-label">closes at night</text>"#));
+        assert!(!map_svg(&plain, &RenderOptions::default()).contains("sub-label"));
+    }
+
+    #[test]
+    fn item_hooks_replace_items() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 10.0, 10.0, 0.0, 255, 0, 0",
+            "L 0.0, 10.0, 0.0, 10.0, 0.0, 0.0, 0, 0, 255",
+            "P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            on_item: Some(ItemHook::new(|item| match item {
+                MapItem::LineItem(line) if line.color.r == 255 => {
+                    Some(String::from("<path class=\"wall\" />\n"))
+                }
+                MapItem::PointItem(_) => Some(String::new()),
+                _ => None,
+            })),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("<path class=\"wall\" />\n"));
+        assert_eq!(svg.matches("class=\"line-item\"").count(), 1);
+        assert!(svg.contains("stroke=\"rgb(0,0,255)\""));
+        assert!(!svg.contains("Bank"));
+    }
+
+    #[test]
+    fn stamping_a_corner() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 50.0, 0.0, 255, 0, 0"]);
+        let stamp = |corner| RenderOptions {
+            stamp: Some(Stamp {
+                text: String::from("qeynos & qeynos2"),
+                corner,
+                font_size: 8.0,
+            }),
+            background: Some(Color::new(0, 0, 0)),
+            ..Default::default()
+        };
+
+        // 2% of the 50 unit short side from each edge.
+        let svg = map_svg(&map_items, &stamp(Corner::BottomLeft));
+        assert!(svg.contains(
+            r#"<text x="1" y="49" font-family="DejaVu Sans" font-size="8" text-anchor="start" fill="rgb(255,255,255)" class="stamp">qeynos &amp; qeynos2</text>"#
+        ));
+        let svg = map_svg(&map_items, &stamp(Corner::TopRight));
+        assert!(svg.contains(r#"<text x="99" y="9" "#));
+        assert!(svg.contains(r#"text-anchor="end""#));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("stamp"));
+    }
+
+    #[test]
+    fn fitting_the_points_only() {
+        let walls = map_items(&["L -100.0, -100.0, 0.0, 100.0, 100.0, 0.0, 0, 0, 0"]);
+        let map_items = map_items(&[
+            "L -100.0, -100.0, 0.0, 100.0, 100.0, 0.0, 0, 0, 0",
+            "P 10.0, 20.0, 0.0, 0, 0, 0, 2, Bank",
+            "P 30.0, 25.0, 0.0, 0, 0, 0, 2, Inn",
+        ]);
+        let options = RenderOptions {
+            fit: Fit::Points,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            map_view_box_with(&map_items, &options),
+            (10.0, 20.0, 20.0, 5.0)
+        );
+        assert_eq!(
+            map_items.bounds_of_points_only(),
+            Some("10,20,30,25".parse().unwrap())
+        );
+
+        assert_eq!(
+            map_view_box_with(&walls, &options),
+            (-100.0, -100.0, 200.0, 200.0)
+        );
+        assert_eq!(walls.bounds_of_points_only(), None);
+    }
+
+    #[test]
+    fn view_bounds_replace_the_extent() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            view_bounds: Some("-5,-5,5,5".parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            map_view_box_with(&map_items, &options),
+            (-5.0, -5.0, 10.0, 10.0)
+        );
+    }
+
+    #[test]
+    fn focus_mask_covers_the_map_last() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            focus: Some(FocusRegion::Circle(Circle::new(5.0, 5.0, 2.0))),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        let last_element = svg.lines().rev().nth(1).unwrap();
+        assert_eq!(
+            last_element,
+            r#"<path d="M 0 0 h 10 v 20 h -10 Z M 3 5 a 2 2 0 1 0 4 0 a 2 2 0 1 0 -4 0 Z" fill="black" fill-opacity="0.6" fill-rule="evenodd" class="focus-mask" />"#
+        );
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        let inside = rendered.pixmap.pixel(5, 5).unwrap();
+        let outside = rendered.pixmap.pixel(9, 18).unwrap();
+        assert_eq!(inside.alpha(), 0);
+        assert!(outside.alpha() > 100);
+    }
+
+    #[test]
+    fn sorted_output_ignores_file_order() {
+        let lines = [
+            "P 5.0, 5.0, 0.0, 0, 0, 255, 2, Inn",
+            "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0",
+            "P 1.0, 2.0, 0.0, 0, 0, 255, 2, Bank",
+            "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 0, 255, 0",
+        ];
+        let options = RenderOptions {
+            sort_output: true,
+            draw_order: DrawOrder::LinesOnTop,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items(&lines), &options);
+        let reversed: Vec<&str> = lines.iter().rev().copied().collect();
+        assert_eq!(svg, map_svg(&map_items(&reversed), &options));
+
+        let bank = svg.find(">Bank<").unwrap();
+        let inn = svg.find(">Inn<").unwrap();
+        let green = svg.find("rgb(0,255,0)").unwrap();
+        let red = svg.find("rgb(255,0,0)").unwrap();
+        assert!(bank < inn && inn < green && green < red);
+    }
+
+    #[test]
+    fn rendering_rejects_bad_scales() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let render = |scale| {
+            map_render(
+                &map_items,
+                &RenderOptions {
+                    scale,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let rendered = render(4.0).unwrap();
+        assert_eq!(
+            (rendered.pixmap.width(), rendered.pixmap.height()),
+            (40, 80)
+        );
+
+        for scale in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(render(scale).is_err(), "{}", scale);
+        }
+        let Err(err) = render(10_000.0) else {
+            panic!("expected a 100000x200000 render to fail");
+        };
+        assert!(err.to_string().contains("100000x200000"), "{}", err);
+    }
+
+    #[test]
+    fn drawing_by_extension() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions::default();
+        let dir = tempfile::tempdir().unwrap();
+
+        let svg_file = dir.path().join("map.svg");
+        map_draw_with(&map_items, &svg_file, &options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&svg_file).unwrap(),
+            map_svg(&map_items, &options)
+        );
+
+        let png_file = dir.path().join("map.PNG");
+        map_draw_with(&map_items, &png_file, &options).unwrap();
+        assert!(std::fs::read(&png_file).unwrap().starts_with(b"\x89PNG"));
+
+        let bmp_file = dir.path().join("map.bmp");
+        let err = map_draw_with(&map_items, &bmp_file, &options).unwrap_err();
+        assert!(err.to_string().contains(".bmp"), "{}", err);
+        assert!(!bmp_file.exists());
+    }
+
+    #[test]
+    fn drawing_at_several_scales() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let dir = tempfile::tempdir().unwrap();
+        let out_file = dir.path().join("map.png");
+
+        let paths = map_draw_scales(
+            &map_items,
+            &out_file,
+            &RenderOptions::default(),
+            &[1.0, 2.5],
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("map@1x.png"),
+                dir.path().join("map@2.5x.png"),
+            ]
+        );
+
+        let sizes: Vec<(u32, u32)> = paths
+            .iter()
+            .map(|path| {
+                let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+                let info = decoder.read_info().unwrap().info().clone();
+                (info.width, info.height)
+            })
+            .collect();
+        assert_eq!(sizes, [(10, 20), (25, 50)]);
+    }
+
+    #[test]
+    fn labels_hidden_below_min_scale_at_several_scales() {
+        let line = "L 0.0, 0.0, 0.0, 60.0, 30.0, 0.0, 255, 0, 0";
+        let labelled = map_items(&[line, "P 5.0, 5.0, 0.0, 0, 0, 255, 2, Bank"]);
+        let unlabelled = map_items(&[line, "P 5.0, 5.0, 0.0, 0, 0, 255, 2, _"]);
+        let options = RenderOptions {
+            label_min_scale: Some(1.0),
+            ..Default::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let draw = |map_items: &MapItems, name: &str| {
+            let out_file = dir.path().join(name);
+            map_draw_scales(map_items, &out_file, &options, &[0.5, 2.0])
+                .unwrap()
+                .iter()
+                .map(|path| std::fs::read(path).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let labelled = draw(&labelled, "labelled.png");
+        let unlabelled = draw(&unlabelled, "unlabelled.png");
+        assert_eq!(labelled[0], unlabelled[0]);
+        assert_ne!(labelled[1], unlabelled[1]);
+    }
+
+    #[test]
+    fn shared_point_circles_become_symbols() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 255, 0, 0, 2, Bank",
+            "P 10.0, 0.0, 0.0, 255, 0, 0, 2, Inn",
+            "P 0.0, 10.0, 0.0, 255, 0, 0, 2, ",
+            "P 10.0, 10.0, 0.0, 0, 0, 255, 2, Guild",
+        ]);
+        let options = RenderOptions {
+            point_symbols: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg
+            .contains("<defs>\n<circle id=\"point-0\" r=\"3\" fill=\"rgb(255,0,0)\" />\n</defs>"));
+        assert!(svg.contains(r##"<use href="#point-0" x="10" y="0" class="point-item-circle" />"##));
+        assert_eq!(svg.matches("<use").count(), 3);
+        assert_eq!(svg.matches("<circle").count(), 2);
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("<use"));
+
+        // Both draw the same pixels.
+        let pixels = |options: &RenderOptions| {
+            let pixmap = map_render(&map_items, options).unwrap().pixmap;
+            pixmap.data().to_vec()
+        };
+        assert_eq!(pixels(&options), pixels(&RenderOptions::default()));
+    }
+
+    #[test]
+    fn drawing_text_items() {
+        let map_items: MapItems = "T 10.0, 20.0, 0.0, 255, 255, 0, 1.5, North & South
+L 0.0, 0.0, 0.0, 20.0, 40.0, 0.0, 255, 0, 0"
+            .parse()
+            .unwrap();
+
+        let svg = map_svg(&map_items, &RenderOptions::default());
+        assert!(svg.contains(
+            r#"<text x="10" y="25" font-family="DejaVu Sans" font-size="15" text-anchor="middle" fill="rgb(255,255,0)" class="text-item">North &amp; South</text>"#
+        ), "{}", svg);
+        assert!(!svg.contains("<circle"));
+    }
+
+    #[test]
+    fn smoothing_line_chains() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 6.0, 0.0, 0.0, 255, 0, 0",
+            "L 6.0, 0.0, 0.0, 12.0, 6.0, 0.0, 255, 0, 0",
+            "L 12.0, 6.0, 0.0, 12.0, 12.0, 0.0, 255, 0, 0",
+            "L 12.0, 12.0, 0.0, 0.0, 12.0, 0.0, 0, 0, 255",
+        ]);
+        let options = RenderOptions {
+            smooth_lines: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains(
+            r#"<path d="M 0 0 C 1 0 4 -1 6 0 C 8 1 11 4 12 6 C 13 8 12 11 12 12" stroke="rgb(255,0,0)" fill="none""#
+        ), "{}", svg);
+        assert_eq!(svg.matches("line-item-smoothed").count(), 1);
+        assert!(svg.contains(r#"<path d="M 12 12 L 0 12" stroke="rgb(0,0,255)""#));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains(" C "));
+
+        // Fading by depth gives each line its own opacity, so none chain.
+        let sloped = self::map_items(&[
+            "L 0.0, 0.0, 0.0, 6.0, 0.0, 0.0, 255, 0, 0",
+            "L 6.0, 0.0, 0.0, 12.0, 6.0, 10.0, 255, 0, 0",
+            "L 12.0, 6.0, 10.0, 12.0, 12.0, 20.0, 255, 0, 0",
+        ]);
+        let faded = RenderOptions {
+            depth_fade: true,
+            ..options.clone()
+        };
+        assert!(!map_svg(&sloped, &faded).contains("line-item-smoothed"));
+    }
+
+    #[test]
+    fn capping_the_label_count() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 0, 0, 0, 2, Bank",
+            "P 10.0, 0.0, 0.0, 0, 0, 0, 2, ",
+            "P 20.0, 0.0, 0.0, 0, 0, 0, 2, Inn",
+            "P 30.0, 0.0, 0.0, 0, 0, 0, 2, to_Steamfont",
+            "P 40.0, 0.0, 0.0, 0, 0, 0, 2, Guild",
+        ]);
+        let labels = |max_label_count| {
+            let options = RenderOptions {
+                max_label_count,
+                ..Default::default()
+            };
+            let svg = map_svg(&map_items, &options);
+            assert_eq!(svg.matches("point-item-circle").count(), 5);
+            ["Bank", "Inn", "to Steamfont", "Guild"]
+                .into_iter()
+                .filter(|label| svg.contains(&format!(">{}</text>", label)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(labels(Some(2)), ["Bank", "to Steamfont"]);
+        assert_eq!(labels(Some(0)), Vec::<&str>::new());
+        assert_eq!(labels(None).len(), 4);
+    }
+
+    #[test]
+    fn zone_links_are_diamonds() {
+        let map_items = map_items(&[
+            "P 10.0, 10.0, 0.0, 0, 0, 255, 2, to_Steamfont",
+            "P 0.0, 0.0, 0.0, 0, 0, 255, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            zone_link_prefix: Some(String::from("to_")),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains(
+            r#"<path d="M 10 5 l 5 5 l -5 5 l -5 -5 Z" fill="rgb(0,0,255)" class="point-item-zone-link" />"#
+        ));
+        assert_eq!(svg.matches("point-item-circle").count(), 1);
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("zone-link"));
+    }
+
+    #[test]
+    fn xml_prolog_only_in_standalone_svg() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            xml_prolog: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(
+            svg.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n<svg ")
+        );
+        assert!(map_svg(&map_items, &RenderOptions::default()).starts_with("<svg "));
+        assert!(!map_html(&map_items, &options).contains("<?xml"));
+        assert!(map_render(&map_items, &options).is_ok());
+    }
+
+    #[test]
+    fn converting_between_world_and_pixels() {
+        let map_items = map_items(&["L -10.0, 20.0, 5.0, 30.0, 60.0, 15.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            scale: 2.0,
+            ..Default::default()
+        };
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!((rendered.width, rendered.height), (80, 80));
+        assert_eq!(
+            rendered.world_to_pixel(&Point::new(0.0, 30.0, 0.0)),
+            (20.0, 20.0)
+        );
+        assert_eq!(rendered.pixel_to_world(20.0, 20.0), (0.0, 30.0));
+        assert_eq!(rendered.pixel_to_world(80.0, 80.0), (30.0, 60.0));
+
+        let options = RenderOptions {
+            plane: Plane::Xz,
+            ..Default::default()
+        };
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!(
+            rendered.world_to_pixel(&Point::new(0.0, 0.0, 10.0)),
+            (10.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn labels_hidden_below_min_scale() {
+        let map_items = map_items(&["P 1.0, 2.0, 0.0, 0, 0, 255, 2, Bank"]);
+        let options = |scale| RenderOptions {
+            scale,
+            label_min_scale: Some(1.0),
+            ..Default::default()
+        };
+
+        assert!(!map_svg(&map_items, &options(0.5)).contains(">Bank<"));
+        assert!(map_svg(&map_items, &options(1.0)).contains(">Bank<"));
+        assert!(map_svg(&map_items, &options(0.5)).contains("point-item-circle"));
+    }
+
+    #[test]
+    fn animating_z_bands() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0",
+            "P 5.0, 5.0, 30.0, 0, 0, 255, 2, Upstairs",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let out_file = dir.path().join("map.gif");
+
+        map_draw_z_animation(
+            &map_items,
+            &out_file,
+            &RenderOptions::default(),
+            3,
+            ZBandMode::Cumulative,
+        )
+        .unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(std::fs::File::open(&out_file).unwrap())
+            .unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (10, 20));
+        let mut frames = 0;
+        while decoder.read_next_frame().unwrap().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+
+        assert!(map_draw_z_animation(
+            &map_items,
+            &out_file,
+            &RenderOptions::default(),
+            0,
+            ZBandMode::Exclusive
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reading_options_from_toml() {
+        let options: RenderOptions = toml::from_str(
+            r##"
+            font_size = 8.0
+            background = "#202020"
+            draw_order = "lines-on-top"
+            plane = "xz"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(options.font_size, 8.0);
+        assert_eq!(options.background, Some(Color::new(32, 32, 32)));
+        assert_eq!(options.draw_order, DrawOrder::LinesOnTop);
+        assert_eq!(options.plane, Plane::Xz);
+        assert_eq!(options.scale, RenderOptions::default().scale);
+
+        assert!(toml::from_str::<RenderOptions>("font_sise = 8.0").is_err());
+        assert!(toml::from_str::<RenderOptions>("plane = \"xy-ish\"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializing_the_map_meta() {
+        use crate::map_draw::map_meta;
+
+        let map_items = map_items(&[
+            "L 10.0, 20.0, 0.0, 30.0, -5.0, 0.0, 0, 0, 0",
+            "L 30.0, -5.0, 0.0, 20.0, 0.0, 0.0, 0, 0, 0",
+            "P -4.0, 8.0, 0.0, 0, 0, 0, 2, Bank",
+        ]);
+
+        let meta = serde_json::to_value(map_meta(&map_items, &RenderOptions::default())).unwrap();
+        assert_eq!(
+            meta,
+            serde_json::json!({
+                "min_x": -4.0,
+                "min_y": -5.0,
+                "width": 34.0,
+                "height": 25.0,
+                "items": 3,
+                "points": 1,
+                "lines": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn describing_the_map() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0",
+            "P 5.0, -5.0, 30.0, 0, 0, 255, 2, Bank",
+        ]);
+        let options = |generated: Option<&str>| RenderOptions {
+            metadata: Some(SvgMetadata {
+                sources: vec![String::from("a&b.txt")],
+                generated: generated.map(String::from),
+            }),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options(None));
+        let desc =
+            "<desc>Sources: a&amp;b.txt. Lines: 1. Points: 1. Extent: 0, -5 to 10, 20.</desc>";
+        assert_eq!(svg.lines().nth(1), Some(desc));
+        assert!(map_svg(&map_items, &options(Some("2026-10-14T10:00:00Z")))
+            .contains("20. Generated: 2026-10-14T10:00:00Z.</desc>"));
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("<desc>"));
+    }
+
+    #[test]
+    fn flipping_y_keeps_points_on_their_lines() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0",
+            "P 10.0, 20.0, 0.0, 0, 0, 255, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            orientation: "flip-y".parse().unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(map_view_box(&map_items), (0.0, 0.0, 10.0, 20.0));
+        assert_eq!(
+            map_view_box_with(&map_items, &options),
+            (0.0, -20.0, 10.0, 20.0)
+        );
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("d=\"M 0 0 L 10 -20\""), "{}", svg);
+        assert!(svg.contains("cx=\"10\" cy=\"-20\""), "{}", svg);
+    }
+
+    #[test]
+    fn flipping_mirrors_points() {
+        let map_items = map_items(&[
+            "L -10.0, 20.0, 0.0, 30.0, 60.0, 0.0, 255, 0, 0",
+            "P 5.0, 30.0, 0.0, 0, 0, 255, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            scale: 2.0,
+            orientation: "flip-x".parse().unwrap(),
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("viewBox=\"-30 20 40 40\""), "{}", svg);
+        assert!(svg.contains("cx=\"-5\" cy=\"30\""), "{}", svg);
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        let point = Point::new(5.0, 30.0, 0.0);
+        assert_eq!(rendered.world_to_pixel(&point), (50.0, 20.0));
+        assert_eq!(rendered.pixel_to_world(50.0, 20.0), (5.0, 30.0));
+
+        let flipped_y = RenderOptions {
+            orientation: Orientation {
+                flip_x: false,
+                flip_y: true,
+            },
+            ..options
+        };
+        let rendered = map_render(&map_items, &flipped_y).unwrap();
+        assert_eq!(rendered.world_to_pixel(&point), (30.0, 60.0));
+    }
+
+    #[test]
+    fn sizing_points_by_label_number() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 255, 0, 0, 2, spawn_rare_4",
+            "P 10.0, 0.0, 0.0, 255, 0, 0, 2, Bank",
+        ]);
+        let options = RenderOptions {
+            size_by_label_number: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("cx=\"0\" cy=\"0\" r=\"6\""), "{}", svg);
+        assert!(svg.contains("<text x=\"8\""), "{}", svg);
+        assert!(svg.contains("cx=\"10\" cy=\"0\" r=\"3\""), "{}", svg);
+        assert!(!map_svg(&map_items, &RenderOptions::default()).contains("r=\"6\""));
+    }
+
+    #[test]
+    fn sizing_points_by_point_type() {
+        let map_items = map_items(&[
+            "P 0.0, 0.0, 0.0, 255, 0, 0, 1, Small",
+            "P 10.0, 0.0, 0.0, 255, 0, 0, 3, Large",
+            "P 20.0, 0.0, 0.0, 255, 0, 0, 9, Other",
+        ]);
+        let options = RenderOptions {
+            size_by_point_type: true,
+            ..Default::default()
+        };
+
+        let svg = map_svg(&map_items, &options);
+        assert!(svg.contains("cx=\"0\" cy=\"0\" r=\"2\""), "{}", svg);
+        assert!(svg.contains("cx=\"10\" cy=\"0\" r=\"4.5\""), "{}", svg);
+        assert!(svg.contains("cx=\"20\" cy=\"0\" r=\"3\""), "{}", svg);
+        assert_eq!(
+            map_svg(&map_items, &RenderOptions::default())
+                .matches("r=\"3\"")
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn rendering_a_data_uri() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 10.0, 20.0, 0.0, 255, 0, 0"]);
+        let uri = map_render_data_uri(&map_items, &RenderOptions::default()).unwrap();
+        // The PNG signature, 89 50 4e 47 0d 0a 1a 0a, in base64.
+        assert!(
+            uri.starts_with("data:image/png;base64,iVBORw0KGgo"),
+            "{}",
+            uri
+        );
+    }
+
+    #[test]
+    fn fitting_into_a_letterboxed_frame() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 50.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            frame_size: Some("200x200".parse().unwrap()),
+            background: Some(Color::new(255, 255, 255)),
+            letterbox_color: Some(Color::new(0, 0, 255)),
+            ..Default::default()
+        };
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!((rendered.width, rendered.height), (200, 200));
+        assert_eq!(rendered.scale, 2.0);
+        // The 200x100 map sits between 50 pixel bars.
+        let pixel = |x, y| {
+            let pixel = rendered.pixmap.pixel(x, y).unwrap();
+            (pixel.red(), pixel.green(), pixel.blue())
+        };
+        assert_eq!(pixel(100, 10), (0, 0, 255));
+        assert_eq!(pixel(100, 190), (0, 0, 255));
+        assert_eq!(pixel(190, 60), (255, 255, 255));
+        assert_eq!(
+            rendered.world_to_pixel(&Point::new(100.0, 50.0, 0.0)),
+            (200.0, 150.0)
+        );
+
+        assert!("200".parse::<FrameSize>().is_err());
+        assert!("0x10".parse::<FrameSize>().is_err());
+    }
+
+    #[test]
+    fn fitting_a_page_within_its_margins() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 100.0, 0.0, 255, 0, 0"]);
+        let frame = PageSize::A4.frame(100.0);
+        assert_eq!((frame.width, frame.height), (827, 1169));
+        assert_eq!(PageSize::Letter.frame(100.0), "850x1100".parse().unwrap());
+
+        let options = RenderOptions {
+            frame_size: Some(frame),
+            frame_margin: millimetres_to_pixels(10.0, 100.0),
+            dpi: Some(100.0),
+            ..Default::default()
+        };
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert_eq!((rendered.width, rendered.height), (827, 1169));
+        // 10mm is 39 pixels, leaving 749 pixels across for the map.
+        assert_eq!(rendered.scale, 7.49);
+        assert_eq!(
+            rendered.world_to_pixel(&Point::new(0.0, 50.0, 0.0)),
+            (39.0, 584.5)
+        );
+
+        assert!("a5".parse::<PageSize>().is_err());
+    }
+
+    #[test]
+    fn rendering_tiles() {
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 50.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions::default();
+        let (min_x, min_y, width, height) = map_view_box_with(&map_items, &options);
+        assert_eq!((width, height), (100.0, 50.0));
+
+        let whole = map_render_tile(&map_items, &options, TileId { z: 0, x: 0, y: 0 }).unwrap();
+        assert_eq!((whole.width, whole.height), (TILE_SIZE, TILE_SIZE));
+        assert_eq!(whole.scale, 2.56);
+
+        let tile = map_render_tile(&map_items, &options, "2/1/0".parse().unwrap()).unwrap();
+        assert_eq!((tile.width, tile.height), (TILE_SIZE, TILE_SIZE));
+        assert_eq!(
+            tile.bounding_box,
+            BoundingBox::from_view_box((min_x + 25.0, min_y, 25.0, 25.0))
+        );
+        assert!(tile.pixmap.pixels().iter().any(|pixel| pixel.alpha() > 0));
+
+        // Below the map's short side.
+        let blank = map_render_tile(&map_items, &options, "2/1/3".parse().unwrap()).unwrap();
+        assert!(blank.pixmap.pixels().iter().all(|pixel| pixel.alpha() == 0));
+
+        assert_eq!(TileId::up_to_zoom(2).count(), 1 + 4 + 16);
+        // The 100x50 view only fills the top half of each zoom's grid.
+        let tiles: Vec<TileId> = map_tiles(&map_items, &options, 2).collect();
+        assert_eq!(tiles.len(), 1 + 2 + 8);
+        assert!(tiles
+            .iter()
+            .all(|tile| tile.y < (1 << tile.z) / 2 || tile.z == 0));
+        assert_eq!(
+            map_tiles(&map_items, &options, 99).last().unwrap().z,
+            MAX_TILE_ZOOM
+        );
+        assert_eq!(TileId { z: 2, x: 1, y: 3 }.to_string(), "2/1/3");
+        assert!("1/2/0".parse::<TileId>().is_err());
+        assert!("1/0".parse::<TileId>().is_err());
+    }
+
+    #[test]
+    fn autocrop_trims_to_drawn_pixels() {
+        let map_items = map_items(&[
+            "L 0.0, 0.0, 0.0, 100.0, 100.0, 0.0, 255, 0, 0",
+            "P 0.0, 0.0, 0.0, 0, 0, 255, 2, ",
+        ]);
+        let options = RenderOptions {
+            view_bounds: Some("-50,-50,150,150".parse().unwrap()),
+            background: Some(Color::new(255, 255, 255)),
+            autocrop: Some(2),
+            ..Default::default()
+        };
+
+        let rendered = map_render(&map_items, &options).unwrap();
+        assert!(
+            rendered.width < 200 && rendered.width >= 104,
+            "{}",
+            rendered.width
+        );
+        assert_eq!(rendered.width, rendered.height);
+        // The world keeps lining up with the cropped pixels.
+        let (x, y) = rendered.world_to_pixel(&Point::new(100.0, 100.0, 0.0));
+        assert!((x - (rendered.width as f32 - 3.0)).abs() <= 1.0, "{}", x);
+        assert!((y - (rendered.height as f32 - 3.0)).abs() <= 1.0, "{}", y);
+
+        let uncropped = map_render(
+            &map_items,
+            &RenderOptions {
+                autocrop: None,
+                ..options
+            },
+        )
+        .unwrap();
+        assert_eq!(uncropped.width, 200);
+    }
+
+    #[test]
+    fn drawing_over_a_background_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("background.png");
+        let mut image = tiny_skia::Pixmap::new(2, 2).unwrap();
+        image.fill(tiny_skia::Color::from_rgba8(0, 128, 0, 255));
+        image.save_png(&path).unwrap();
+
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            view_bounds: Some("0,-50,100,50".parse().unwrap()),
+            background_image: Some(path.clone()),
+            ..Default::default()
+        };
+        let rendered = map_render(&map_items, &options).unwrap();
+
+        let pixel = |x, y| rendered.pixmap.pixel(x, y).unwrap();
+        assert_eq!(
+            (pixel(5, 5).red(), pixel(5, 5).green(), pixel(5, 5).alpha()),
+            (0, 128, 255)
+        );
+        // The line is drawn over it.
+        assert!(pixel(50, 50).red() > pixel(50, 50).green());
+        assert!(!map_svg(&map_items, &options).contains("png"));
+    }
+
+    #[test]
+    fn background_image_is_drawn_over_the_background_color() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("background.png");
+        let mut image = tiny_skia::Pixmap::new(2, 1).unwrap();
+        image.fill(tiny_skia::Color::from_rgba8(0, 128, 0, 255));
+        // The right half is transparent, showing the color beneath.
+        image.pixels_mut()[1] = tiny_skia::PremultipliedColorU8::TRANSPARENT;
+        image.save_png(&path).unwrap();
+
+        let map_items = map_items(&["L 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 255, 0, 0"]);
+        let options = RenderOptions {
+            view_bounds: Some("0,-50,100,50".parse().unwrap()),
+            background: Some(Color::new(0, 0, 255)),
+            background_image: Some(path.clone()),
+            ..Default::default()
+        };
+        let rendered = map_render(&map_items, &options).unwrap();
+
+        // The stretched image is smoothed, so compare rather than match.
+        let pixel = |x, y| rendered.pixmap.pixel(x, y).unwrap();
+        assert!(pixel(5, 5).green() > 100 && pixel(5, 5).blue() < 20);
+        assert!(pixel(95, 5).blue() > 200 && pixel(95, 5).green() < 20);
+        // SVG output has no image, so it keeps the background rect.
+        assert!(map_svg(&map_items, &options).contains("class=\"background\""));
     }
 }
@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::{
+    map_bounds::{BoundingBox, Circle},
+    map_items::{LineItem, MapItem, MapItems, Point},
+};
+
+impl MapItems {
+    /// Keeps the points inside `bounds` and the lines that cross it, which are
+    /// kept whole.
+    pub fn crop(&self, bounds: &BoundingBox) -> MapItems {
+        self.crop_with(
+            |point| bounds.contains(point.x, point.y),
+            |from, to| bounds.clip_segment(from, to),
+            |line, _| line.clone(),
+        )
+    }
+
+    /// Like `crop`, but trims lines that cross the edge of `bounds` so that
+    /// nothing extends outside it. Z is interpolated along the trimmed lines.
+    pub fn clip(&self, bounds: &BoundingBox) -> MapItems {
+        self.crop_with(
+            |point| bounds.contains(point.x, point.y),
+            |from, to| bounds.clip_segment(from, to),
+            trim_line,
+        )
+    }
+
+    /// Keeps the points within `circle` and the lines that pass through it,
+    /// which are kept whole, like `crop`.
+    pub fn crop_circle(&self, circle: &Circle) -> MapItems {
+        self.crop_with(
+            |point| circle.contains(point),
+            |from, to| circle.clip_segment(from, to),
+            |line, _| line.clone(),
+        )
+    }
+
+    /// Like `crop_circle`, but trims each line to the chord of it that lies
+    /// inside `circle`, like `clip`.
+    pub fn clip_circle(&self, circle: &Circle) -> MapItems {
+        self.crop_with(
+            |point| circle.contains(point),
+            |from, to| circle.clip_segment(from, to),
+            trim_line,
+        )
+    }
+
+    fn crop_with(
+        &self,
+        contains: impl Fn(&Point) -> bool,
+        clip_segment: impl Fn(&Point, &Point) -> Option<(f32, f32)>,
+        keep_line: impl Fn(&LineItem, (f32, f32)) -> LineItem,
+    ) -> MapItems {
+        let items = self
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => contains(&point.point).then(|| item.clone()),
+                MapItem::TextItem(text) => contains(&text.point).then(|| item.clone()),
+                MapItem::LineItem(line) => clip_segment(&line.from, &line.to)
+                    .map(|range| MapItem::LineItem(keep_line(line, range))),
+                MapItem::Raw(_) => Some(item.clone()),
+            })
+            .collect();
+
+        MapItems { items }
+    }
+
+    /// Keeps only the point items whose label matches `re`. Other items are
+    /// kept as they are.
+    pub fn include_labels(&self, re: &Regex) -> MapItems {
+        self.filter_points(|label| re.is_match(label))
+    }
+
+    /// Removes the point items whose label matches `re`. Other items are kept
+    /// as they are.
+    pub fn exclude_labels(&self, re: &Regex) -> MapItems {
+        self.filter_points(|label| !re.is_match(label))
+    }
+
+    /// Keeps only the point items whose label is exactly one of `labels`.
+    /// Other items are kept as they are.
+    pub fn include_label_set(&self, labels: &HashSet<String>) -> MapItems {
+        self.filter_points(|label| labels.contains(label))
+    }
+
+    /// Keeps every `n`th point and every `n`th line, counting points and lines
+    /// separately and starting with the first of each. This is lossy and only
+    /// meant for quick previews of very large maps. An `n` of 0 is treated as 1.
+    pub fn sample(&self, n: usize) -> MapItems {
+        let n = n.max(1);
+        let (mut points, mut lines) = (0, 0);
+
+        let items = self
+            .items
+            .iter()
+            .filter(|item| {
+                let seen = match item {
+                    MapItem::PointItem(_) => &mut points,
+                    MapItem::LineItem(_) => &mut lines,
+                    MapItem::TextItem(_) | MapItem::Raw(_) => return true,
+                };
+                *seen += 1;
+                (*seen - 1) % n == 0
+            })
+            .cloned()
+            .collect();
+
+        MapItems { items }
+    }
+
+    /// Keeps the points with a Z from `min_z` to `max_z`, inclusive, and the
+    /// lines with both ends in that range. Lines that cross into or out of the
+    /// range are dropped.
+    pub fn filter_z(&self, min_z: f32, max_z: f32) -> MapItems {
+        let in_range = |point: &Point| (min_z..=max_z).contains(&point.z);
+        let items = self
+            .items
+            .iter()
+            .filter(|item| match item {
+                MapItem::PointItem(point) => in_range(&point.point),
+                MapItem::LineItem(line) => in_range(&line.from) && in_range(&line.to),
+                MapItem::TextItem(text) => in_range(&text.point),
+                MapItem::Raw(_) => true,
+            })
+            .cloned()
+            .collect();
+
+        MapItems { items }
+    }
+
+    fn filter_points(&self, keep: impl Fn(&str) -> bool) -> MapItems {
+        let items = self
+            .items
+            .iter()
+            .filter(|item| match item {
+                MapItem::PointItem(point) => keep(&point.label),
+                _ => true,
+            })
+            .cloned()
+            .collect();
+
+        MapItems { items }
+    }
+}
+
+fn trim_line(line: &LineItem, (start, end): (f32, f32)) -> LineItem {
+    LineItem {
+        from: lerp(&line.from, &line.to, start),
+        to: lerp(&line.from, &line.to, end),
+        ..line.clone()
+    }
+}
+
+fn lerp(from: &Point, to: &Point, t: f32) -> Point {
+    let offset = *to - *from;
+    *from + (offset.x * t, offset.y * t, offset.z * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use regex::Regex;
+
+    use crate::{
+        map_bounds::{BoundingBox, Circle},
+        map_items::{MapItem, MapItems},
+    };
+
+    fn labels(map_items: &MapItems) -> Vec<&str> {
+        map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.label.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn include_then_exclude_labels() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 0, 0, 0, 2, to_Steamfont
+P 1.0, 0.0, 0.0, 0, 0, 0, 2, to_trap_room
+P 2.0, 0.0, 0.0, 0, 0, 0, 2, Bank
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let included = map_items.include_labels(&Regex::new("^to_").unwrap());
+        assert_eq!(labels(&included), ["to_Steamfont", "to_trap_room"]);
+
+        let excluded = included.exclude_labels(&Regex::new("trap").unwrap());
+        assert_eq!(labels(&excluded), ["to_Steamfont"]);
+        assert_eq!(excluded.items.len(), 2);
+    }
+
+    fn line_ends(map_items: &MapItems) -> Vec<(f32, f32, f32, f32, f32, f32)> {
+        map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::LineItem(line) => Some((
+                    line.from.x,
+                    line.from.y,
+                    line.from.z,
+                    line.to.x,
+                    line.to.y,
+                    line.to.z,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn include_label_set_matches_exactly() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 0, 0, 0, 2, Bank
+P 1.0, 0.0, 0.0, 0, 0, 0, 2, Bank_of_Ak'Anon
+P 2.0, 0.0, 0.0, 0, 0, 0, 2, Inn
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+        let set = HashSet::from([String::from("Bank"), String::from("Inn")]);
+
+        let included = map_items.include_label_set(&set);
+        assert_eq!(labels(&included), ["Bank", "Inn"]);
+        assert_eq!(included.items.len(), 3);
+    }
+
+    #[test]
+    fn sample_decimates_points_and_lines_separately() {
+        let map_items: MapItems = "P 0.0, 0.0, 0.0, 0, 0, 0, 2, A
+L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0, 0, 0
+P 1.0, 0.0, 0.0, 0, 0, 0, 2, B
+P 2.0, 0.0, 0.0, 0, 0, 0, 2, C
+L 1.0, 0.0, 0.0, 2.0, 1.0, 0.0, 0, 0, 0
+L 2.0, 0.0, 0.0, 3.0, 1.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let sampled = map_items.sample(2);
+        assert_eq!(labels(&sampled), ["A", "C"]);
+        assert_eq!(line_ends(&sampled).len(), 2);
+        assert_eq!(line_ends(&sampled)[1].0, 2.0);
+
+        assert_eq!(map_items.sample(1).items.len(), 6);
+    }
+
+    #[test]
+    fn clip_trims_lines_to_the_rectangle() {
+        let map_items: MapItems = "P 5.0, 5.0, 0.0, 0, 0, 0, 2, Inside
+P 50.0, 5.0, 0.0, 0, 0, 0, 2, Outside
+L -10.0, 5.0, 0.0, 5.0, 5.0, 30.0, 0, 0, 0
+L -5.0, -5.0, 0.0, 15.0, 15.0, 0.0, 0, 0, 0
+L 2.0, 2.0, 0.0, 4.0, 4.0, 0.0, 0, 0, 0
+L 20.0, 0.0, 0.0, 20.0, 10.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+        let bounds: BoundingBox = "0,0,10,10".parse().unwrap();
+
+        let clipped = map_items.clip(&bounds);
+        assert_eq!(labels(&clipped), ["Inside"]);
+        assert_eq!(
+            line_ends(&clipped),
+            [
+                (0.0, 5.0, 20.0, 5.0, 5.0, 30.0),
+                (0.0, 0.0, 0.0, 10.0, 10.0, 0.0),
+                (2.0, 2.0, 0.0, 4.0, 4.0, 0.0),
+            ]
+        );
+
+        let cropped = map_items.crop(&bounds);
+        assert_eq!(line_ends(&cropped)[0], (-10.0, 5.0, 0.0, 5.0, 5.0, 30.0));
+        assert_eq!(cropped.items.len(), 4);
+    }
+
+    #[test]
+    fn circle_crop_keeps_or_trims_crossing_lines() {
+        let map_items: MapItems = "P 3.0, 4.0, 0.0, 0, 0, 0, 2, Edge
+P 4.0, 4.0, 0.0, 0, 0, 0, 2, Corner
+L -10.0, 0.0, 0.0, 10.0, 0.0, 20.0, 0, 0, 0
+L 10.0, 10.0, 0.0, 10.0, 20.0, 0.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+        let circle = Circle::new(0.0, 0.0, 5.0);
+
+        let cropped = map_items.crop_circle(&circle);
+        assert_eq!(labels(&cropped), ["Edge"]);
+        assert_eq!(line_ends(&cropped), [(-10.0, 0.0, 0.0, 10.0, 0.0, 20.0)]);
+
+        let clipped = map_items.clip_circle(&circle);
+        assert_eq!(line_ends(&clipped), [(-5.0, 0.0, 5.0, 5.0, 0.0, 15.0)]);
+    }
+
+    #[test]
+    fn filter_z_drops_lines_leaving_the_range() {
+        let map_items: MapItems = "P 0.0, 0.0, 5.0, 0, 0, 0, 2, Low
+P 0.0, 0.0, 50.0, 0, 0, 0, 2, High
+L 0.0, 0.0, 0.0, 1.0, 1.0, 10.0, 0, 0, 0
+L 0.0, 0.0, 10.0, 1.0, 1.0, 40.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        let low = map_items.filter_z(0.0, 10.0);
+        assert_eq!(labels(&low), ["Low"]);
+        assert_eq!(line_ends(&low), [(0.0, 0.0, 0.0, 1.0, 1.0, 10.0)]);
+        assert_eq!(map_items.z_range(), Some((0.0, 50.0)));
+    }
+}
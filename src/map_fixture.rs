@@ -0,0 +1,60 @@
+//! Synthetic maps of any size, for benchmarks and tests.
+
+/// Generates the text of a map file with `item_count` items, roughly one
+/// point for every nine lines, spread over a 4000 unit square. The output only
+/// depends on `item_count`.
+pub fn synthetic_map(item_count: usize) -> String {
+    let mut random = Lcg(0x2545_f491_4f6c_dd1d);
+    let mut coordinate = || (random.next() % 400_000) as f32 / 100.0 - 2000.0;
+
+    let mut map = String::with_capacity(item_count * 64);
+    for index in 0..item_count {
+        if index % 10 == 0 {
+            map.push_str(&format!(
+                "P {:.4}, {:.4}, {:.4}, 240, 0, 0, 2, Point_{}\r\n",
+                coordinate(),
+                coordinate(),
+                coordinate() / 10.0,
+                index
+            ));
+        } else {
+            map.push_str(&format!(
+                "L {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, 0, 0, 0\r\n",
+                coordinate(),
+                coordinate(),
+                coordinate() / 10.0,
+                coordinate(),
+                coordinate(),
+                coordinate() / 10.0
+            ));
+        }
+    }
+
+    map
+}
+
+/// A small linear congruential generator, so fixtures need no dependencies.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        self.0 >> 33
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map_fixture::synthetic_map, map_items::MapItems};
+
+    #[test]
+    fn synthetic_map_parses_completely() {
+        let map_items: MapItems = synthetic_map(1000).parse().unwrap();
+
+        assert_eq!(map_items.items.len(), 1000);
+        assert_eq!(synthetic_map(1000), synthetic_map(1000));
+    }
+}
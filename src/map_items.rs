@@ -1,13 +1,30 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use encoding_rs::Encoding;
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::{
+    borrow::Cow,
+    fmt,
     fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, Cursor, Read, Seek, Write},
+    ops::{Add, Index, Sub},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize},
+        Mutex,
+    },
+    thread,
 };
 
-#[derive(Debug)]
+use crate::{
+    map_check::{mixed_scales, MIXED_SCALE_RATIO},
+    map_convert::MapFormat,
+    map_directives::Directives,
+};
+
+#[derive(Debug, Clone, Copy)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -15,6 +32,68 @@ pub struct Point {
 }
 
 impl Point {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Point { x, y, z }
+    }
+
+    /// The distance to `other` in the XY plane, ignoring Z.
+    pub fn distance_2d(&self, other: &Point) -> f32 {
+        (other.x - self.x).hypot(other.y - self.y)
+    }
+
+    /// The distance to `other` in all three axes.
+    pub fn distance_3d(&self, other: &Point) -> f32 {
+        self.distance_2d(other).hypot(other.z - self.z)
+    }
+
+    /// The point halfway between this point and `other`, in all three axes.
+    pub fn midpoint(&self, other: &Point) -> Point {
+        Point {
+            x: (self.x + other.x) / 2.0,
+            y: (self.y + other.y) / 2.0,
+            z: (self.z + other.z) / 2.0,
+        }
+    }
+
+    /// Builds a map file point from the three numbers `/loc` prints in game.
+    ///
+    /// `/loc` reports `Y, X, Z`, and both of those in-game axes point the
+    /// opposite way to the map file's, so `/loc` output `a, b, c` is the map
+    /// file point `(-b, -a, c)`. Z is the same in both.
+    pub fn from_loc(first: f32, second: f32, z: f32) -> Self {
+        Point {
+            // Subtracting from 0 rather than negating keeps -0 out of the files
+            // that are written back.
+            x: 0.0 - second,
+            y: 0.0 - first,
+            z,
+        }
+    }
+
+    /// Reads the point from a `/loc` reply as it appears in game chat or the
+    /// log file, such as `[Mon Oct 12 20:15:01 2026] Your Location is
+    /// 1234.50, -678.90, 12.30`, converting it with `from_loc`. Anything
+    /// before "Your Location is" is ignored.
+    pub fn from_loc_line(line: &str) -> Result<Self, anyhow::Error> {
+        let captures = LOC_LINE
+            .captures(line)
+            .ok_or_else(|| anyhow!("No /loc coordinates in {:?}", line))?;
+        let number = |index: usize| captures[index].parse::<f32>();
+
+        Ok(Point::from_loc(number(1)?, number(2)?, number(3)?))
+    }
+
+    /// The point as `/loc` would report it, the inverse of `from_loc`.
+    pub fn to_loc(&self) -> (f32, f32, f32) {
+        (0.0 - self.y, 0.0 - self.x, self.z)
+    }
+
+    /// The bits of each coordinate, with -0 folded into 0 so that points
+    /// that print the same compare equal.
+    fn bits(&self) -> [u32; 3] {
+        [self.x, self.y, self.z].map(|value| (value + 0.0).to_bits())
+    }
+
     fn parse(x: &str, y: &str, z: &str) -> Result<Self, anyhow::Error> {
         Ok(Point {
             x: x.parse()?,
@@ -24,7 +103,32 @@ impl Point {
     }
 }
 
-#[derive(Debug)]
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+/// Translates the point by an `(x, y, z)` offset.
+impl Add<(f32, f32, f32)> for Point {
+    type Output = Point;
+
+    fn add(self, (x, y, z): (f32, f32, f32)) -> Point {
+        self + Point::new(x, y, z)
+    }
+}
+
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -32,6 +136,55 @@ pub struct Color {
 }
 
 impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// Converts a hue in degrees and a saturation and value from 0 to 1.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let chroma = value * saturation;
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        let channel = |c: f32| ((c + value - chroma) * 255.0).round() as u8;
+        Color::new(channel(r), channel(g), channel(b))
+    }
+
+    /// Perceived brightness from 0 to 255, using the Rec. 601 weights
+    /// `0.299 R + 0.587 G + 0.114 B`.
+    pub fn luminance(&self) -> f32 {
+        0.299 * self.r as f32 + 0.587 * self.g as f32 + 0.114 * self.b as f32
+    }
+
+    /// The Euclidean distance between the two colors in RGB space, from 0 for
+    /// the same color up to about 441.7 between black and white.
+    pub fn distance(&self, other: &Color) -> f32 {
+        let channel = |a: u8, b: u8| (a as f32 - b as f32).powi(2);
+        (channel(self.r, other.r) + channel(self.g, other.g) + channel(self.b, other.b)).sqrt()
+    }
+
+    /// Black or white, whichever is more readable against this color.
+    pub fn contrasting(&self) -> Color {
+        if self.luminance() > 127.5 {
+            Color { r: 0, g: 0, b: 0 }
+        } else {
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            }
+        }
+    }
+
     fn parse(r: &str, g: &str, b: &str) -> Result<Self, anyhow::Error> {
         Ok(Color {
             r: r.parse()?,
@@ -41,168 +194,2209 @@ impl Color {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// Deserializes a string with `T`'s `FromStr`, so that values read with
+/// serde are written the same way as on the command line.
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    /// Parses `#rrggbb`, `r,g,b` or `rgb(r,g,b)`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return Err(anyhow!("Expected #rrggbb but got {}", s));
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            return Ok(Color::new(channel(0)?, channel(2)?, channel(4)?));
+        }
+
+        let channels = s
+            .strip_prefix("rgb(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .unwrap_or(s);
+        let [r, g, b] = channels.split(',').map(str::trim).collect::<Vec<_>>()[..] else {
+            return Err(anyhow!(
+                "Expected #rrggbb, r,g,b or rgb(r,g,b) but got {}",
+                s
+            ));
+        };
+        Color::parse(r, g, b)
+    }
+}
+
+/// Points are equal when their coordinates are bit for bit the same, apart
+/// from the sign of zero; no tolerance is applied.
+impl PartialEq for Point {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits() == other.bits()
+    }
+}
+
+impl Eq for Point {}
+
+impl Hash for Point {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().hash(state);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PointItem {
     pub point: Point,
     pub color: Color,
     pub label: String,
+    /// A second, smaller line of text under the label, written after a `|`
+    /// in the label field, as in `Bank | closes at night`.
+    pub sub_label: Option<String>,
+    /// The number before the label, which the game reads as the kind, and
+    /// size, of the point. `DEFAULT_POINT_TYPE` for points that leave it out.
+    pub point_type: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LineItem {
     pub from: Point,
     pub to: Point,
     pub color: Color,
+    /// The stroke width, in map units, from an optional tenth field after the
+    /// color. Lines without one are drawn with the render options' width.
+    pub width: Option<f32>,
+}
+
+impl PartialEq for LineItem {
+    fn eq(&self, other: &Self) -> bool {
+        (self.from, self.to, self.color) == (other.from, other.to, other.color)
+            && self.width.map(f32::to_bits) == other.width.map(f32::to_bits)
+    }
+}
+
+impl Eq for LineItem {}
+
+impl Hash for LineItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.from, self.to, self.color).hash(state);
+        self.width.map(f32::to_bits).hash(state);
+    }
+}
+
+/// A free-standing text annotation, such as a region name, drawn without a
+/// marker.
+#[derive(Debug, Clone)]
+pub struct TextItem {
+    pub point: Point,
+    pub color: Color,
+    /// The font size, as a multiple of the render options' font size.
+    pub size: f32,
+    pub text: String,
+}
+
+impl PartialEq for TextItem {
+    fn eq(&self, other: &Self) -> bool {
+        (self.point, self.color, &self.text) == (other.point, other.color, &other.text)
+            && self.size.to_bits() == other.size.to_bits()
+    }
+}
+
+impl Eq for TextItem {}
+
+impl Hash for TextItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.point, self.color, &self.text).hash(state);
+        self.size.to_bits().hash(state);
+    }
 }
 
-#[derive(Debug)]
+/// One drawable entry of a map file. More kinds of item may be added, so
+/// matches outside this crate need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum MapItem {
     PointItem(PointItem),
     LineItem(LineItem),
+    TextItem(TextItem),
+    /// A source line that could not be parsed, kept verbatim when
+    /// `LoadOptions::keep_raw_lines` is set. Raw items are never drawn.
+    Raw(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MapItems {
     pub items: Vec<MapItem>,
 }
 
-impl MapItems {
-    pub async fn load_from_files<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Result<Self, anyhow::Error> {
-        let mut items = Vec::new();
+/// Options controlling how map files are loaded.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Replace each item's color with a palette color chosen per input file,
+    /// so that merged maps can be told apart.
+    pub colorize_by_file: bool,
+    /// Keep lines that fail to parse as `MapItem::Raw` instead of dropping
+    /// them, so that a parse and write cycle doesn't lose data.
+    pub keep_raw_lines: bool,
+    /// Fail on the first line that doesn't parse or decode, naming its line
+    /// number, instead of dropping it or keeping it raw. Lines with fields
+    /// after the last one this crate reads fail too, rather than having the
+    /// extra fields dropped. Blank lines and `#` comments are still skipped.
+    pub strict: bool,
+    /// Abort loading with an error once more than this many items have been
+    /// read, across all files. `None`, the default, means no limit. Files are
+    /// parsed as they are read so that the limit bounds memory, though
+    /// standard input is still read whole first.
+    pub max_items: Option<usize>,
+    /// Which line format the files are written in.
+    pub input_format: InputFormat,
+    /// The text encoding the files are written in.
+    pub input_encoding: InputEncoding,
+    /// How many files to read at once, each on its own thread. 0 and 1 read
+    /// them one at a time. The async loaders read every file at once without
+    /// blocking and only parse them on these threads. The loaded items are
+    /// always combined in the order the paths were given, so this only
+    /// changes how long loading takes.
+    pub jobs: usize,
+}
 
-        for path in paths {
-            let map_items = MapItems::load_from_file(path).await?;
-            for item in map_items.items {
-                items.push(item);
-            }
+/// The line formats map files can be read in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum InputFormat {
+    /// The format the game itself ships: fields separated by a comma and a
+    /// space, with a size column before each point's label.
+    #[default]
+    Eq,
+    /// The format written by older mapping addons, which only differs from
+    /// `Eq` in that fields may be separated by a bare comma and point lines
+    /// may leave out the size column.
+    Legacy,
+    /// Read each line as `Eq`, falling back to `Legacy`.
+    Auto,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eq" => Ok(InputFormat::Eq),
+            "legacy" => Ok(InputFormat::Legacy),
+            "auto" => Ok(InputFormat::Auto),
+            _ => Err(anyhow!("Unknown input format {}", s)),
         }
+    }
+}
 
-        Ok(MapItems { items })
+/// The text encoding of map files, UTF-8 by default. Older files are often in
+/// Windows-1252, which decodes accented zone names correctly where UTF-8
+/// would reject the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEncoding(pub &'static Encoding);
+
+impl Default for InputEncoding {
+    fn default() -> Self {
+        InputEncoding(encoding_rs::UTF_8)
     }
+}
 
-    pub async fn load_from_file(path: &Path) -> Result<Self, anyhow::Error> {
-        let file = File::open(path)?;
-
-        let lines = BufReader::new(file).lines();
-        let items = lines
-            .filter_map(|line| {
-                if let Ok(line) = line {
-                    MapItem::parse(&line).ok()
-                } else {
-                    None
-                }
-            })
-            .collect();
+impl FromStr for InputEncoding {
+    type Err = anyhow::Error;
 
-        Ok(MapItems { items })
+    /// Accepts any WHATWG encoding label, such as "utf-8", "latin1" or
+    /// "windows-1252".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Encoding::for_label(s.as_bytes())
+            .map(InputEncoding)
+            .ok_or_else(|| anyhow!("Unknown input encoding {}", s))
     }
 }
 
-impl MapItem {
-    fn parse(line: &str) -> Result<MapItem, anyhow::Error> {
-        let first_char = line
-            .chars()
-            .nth(0)
-            .ok_or_else(|| anyhow!("Missing line identifier"))?;
+impl InputEncoding {
+    /// Decodes one line split off at `\n`, dropping the `\r` of a CRLF
+    /// ending. Returns `None` if the bytes aren't valid in this encoding.
+    pub(crate) fn decode_line(&self, bytes: &[u8]) -> Option<String> {
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        self.0
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(Cow::into_owned)
+    }
+}
 
-        let item = match first_char {
-            'P' => MapItem::PointItem(PointItem::parse(&line)?),
-            'L' => MapItem::LineItem(LineItem::parse(&line)?),
-            _ => return Err(anyhow!("Unrecognized line identifier {}", first_char)),
+impl LoadOptions {
+    /// Calls `load` with every input, such as a path, on up to `jobs` threads,
+    /// and returns the results in the order of `inputs` whichever finishes
+    /// first. Unless `keep_going`, no more inputs are started once one fails,
+    /// and the results end at the first failure.
+    pub fn map_files<I: Sync, T: Send>(
+        &self,
+        inputs: &[I],
+        load: impl Fn(&I) -> Result<T, anyhow::Error> + Sync,
+        keep_going: bool,
+    ) -> Vec<Result<T, anyhow::Error>> {
+        let jobs = self.jobs.clamp(1, inputs.len().max(1));
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results = Mutex::new((0..inputs.len()).map(|_| None).collect::<Vec<_>>());
+        let work = || loop {
+            if failed.load(atomic::Ordering::Relaxed) {
+                break;
+            }
+            let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+            let Some(input) = inputs.get(index) else {
+                break;
+            };
+            let result = load(input);
+            if result.is_err() && !keep_going {
+                failed.store(true, atomic::Ordering::Relaxed);
+            }
+            results.lock().unwrap()[index] = Some(result);
         };
+        if jobs == 1 {
+            work();
+        } else {
+            thread::scope(|scope| {
+                for _ in 0..jobs {
+                    scope.spawn(work);
+                }
+            });
+        }
 
-        Ok(item)
+        // Every input before a failed one was started before it, so the
+        // results up to the first failure are all there.
+        let mut ordered = Vec::with_capacity(inputs.len());
+        for result in results.into_inner().unwrap() {
+            let Some(result) = result else {
+                break;
+            };
+            let stop = result.is_err() && !keep_going;
+            ordered.push(result);
+            if stop {
+                break;
+            }
+        }
+        ordered
+    }
+
+    pub(crate) fn check_item_count(&self, count: usize) -> Result<(), anyhow::Error> {
+        match self.max_items {
+            Some(max_items) if count > max_items => Err(anyhow!(
+                "Map has more than the maximum of {} items",
+                max_items
+            )),
+            _ => Ok(()),
+        }
     }
 }
 
-impl PointItem {
-    /// Parses a PointItem from a map file line.
-    /// P 78.2306, -50.5124, 0.0020, 255, 0, 0, 3, to_The_Steamfont_Mountains
-    fn parse(line: &str) -> Result<Self, anyhow::Error> {
-        let (_, line) = line
-            .split_once(' ')
-            .ok_or_else(|| anyhow!("No line content"))?;
+/// Colors cycled through by `LoadOptions::colorize_by_file`.
+const FILE_PALETTE: [(u8, u8, u8); 8] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (0, 130, 200),
+    (245, 130, 48),
+    (145, 30, 180),
+    (70, 240, 240),
+    (240, 50, 230),
+    (128, 128, 0),
+];
 
-        let segments: Vec<&str> = LINE_CONTENT_SEPARATOR.split(line).collect();
+impl MapItems {
+    #[cfg(feature = "async-io")]
+    pub async fn load_from_files<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+    ) -> Result<Self, anyhow::Error> {
+        MapItems::load_from_files_with(paths, &LoadOptions::default()).await
+    }
 
-        let [x, y, z, r, g, b, _point_type, label] = segments[..] else {
-            return Err(anyhow!("Not enough line content segments"));
-        };
+    /// Loads and concatenates several map files, in order, as `from_files`
+    /// does. The files are all read whole at once without blocking, then
+    /// parsed on a blocking thread, or up to `LoadOptions::jobs` of them. With
+    /// `LoadOptions::max_items` set, they are instead read and parsed as
+    /// `from_files` does on a blocking thread, so that the limit applies
+    /// while reading.
+    #[cfg(feature = "async-io")]
+    pub async fn load_from_files_with<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        let paths: Vec<&Path> = paths.into_iter().collect();
+        check_stdin_once(&paths)?;
+        if options.max_items.is_some() {
+            let paths: Vec<PathBuf> = paths.iter().map(|path| path.to_path_buf()).collect();
+            let options = options.clone();
+            return tokio::task::spawn_blocking(move || {
+                MapItems::from_files(paths.iter().map(PathBuf::as_path), &options)
+            })
+            .await?;
+        }
 
-        Ok(PointItem {
-            point: Point::parse(x, y, z)?,
-            color: Color::parse(r, g, b)?,
-            label: String::from(label),
+        let reads: Vec<_> = paths
+            .iter()
+            .map(|path| tokio::spawn(read_input(path.to_path_buf())))
+            .collect();
+
+        let mut contents = Vec::with_capacity(reads.len());
+        for (path, read) in paths.iter().zip(reads) {
+            let read = read
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            contents.push(read.with_context(|| format!("Failed to open {}", path.display()))?);
+        }
+
+        let paths: Vec<PathBuf> = paths.iter().map(|path| path.to_path_buf()).collect();
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || {
+            let inputs: Vec<(&Path, Vec<u8>)> =
+                paths.iter().map(PathBuf::as_path).zip(contents).collect();
+            let loaded = options.map_files(
+                &inputs,
+                |(path, contents)| {
+                    MapItems::from_source(path, Cursor::new(&contents[..]), &options)
+                },
+                false,
+            );
+            let paths: Vec<&Path> = inputs.iter().map(|(path, _)| *path).collect();
+            let (map_items, _) = MapItems::combine_files(&paths, loaded, &options, false)?;
+
+            Ok(map_items)
         })
+        .await?
     }
-}
 
-impl LineItem {
-    /// Parses a LineItem from a map file line.
-    /// L 1000.0, 0.0, 0.0, 1000.0, -50.0, 0.0, 255, 0, 0
-    fn parse(line: &str) -> Result<Self, anyhow::Error> {
-        let (_, line) = line
-            .split_once(' ')
-            .ok_or_else(|| anyhow!("No line content"))?;
+    #[cfg(feature = "async-io")]
+    pub async fn load_from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        MapItems::load_from_file_with(path, &LoadOptions::default()).await
+    }
 
-        let segments: Vec<&str> = LINE_CONTENT_SEPARATOR.split(line).collect();
+    /// Loads a single map file as `from_file` does, reading it whole without
+    /// blocking and then parsing it on a blocking thread, or with
+    /// `LoadOptions::max_items` set, reading and parsing it as `from_file`
+    /// does on a blocking thread.
+    #[cfg(feature = "async-io")]
+    pub async fn load_from_file_with(
+        path: &Path,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        if options.max_items.is_some() {
+            let path = path.to_path_buf();
+            let options = options.clone();
+            return tokio::task::spawn_blocking(move || MapItems::from_file(&path, &options))
+                .await?;
+        }
 
-        let [fx, fy, fz, tx, ty, tz, r, g, b] = segments[..] else {
-            return Err(anyhow!("Not enough line content segments"));
-        };
+        let contents = read_input(path.to_path_buf())
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
 
-        Ok(LineItem {
-            from: Point::parse(fx, fy, fz)?,
-            to: Point::parse(tx, ty, tz)?,
-            color: Color::parse(r, g, b)?,
+        let path = path.to_path_buf();
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || {
+            MapItems::from_source(&path, Cursor::new(&contents[..]), &options)
         })
+        .await?
     }
-}
 
-lazy_static! {
-    static ref LINE_CONTENT_SEPARATOR: Regex = Regex::new(",\\s+").unwrap();
-}
+    /// Loads and concatenates several map files, in order. `STDIN_PATH` reads
+    /// standard input in its place, and may only be given once.
+    pub fn from_files<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        let (map_items, _) = MapItems::load_files(paths, options, false)?;
 
-#[cfg(test)]
-mod tests {
-    use crate::{map_items::MapItem};
-    use std::assert_matches::assert_matches;
+        Ok(map_items)
+    }
 
-    #[test]
-    fn parsing_point() {
-        let map_item = MapItem::parse(
-            "P 78.2306, -50.5124, 0.0020, 255, 254, 253, 3, to_The_Steamfont_Mountains",
+    /// Like `from_files`, but a file that fails to load is left out and
+    /// returned with its error instead of failing the whole load. Exceeding
+    /// `LoadOptions::max_items` still fails.
+    pub fn from_files_skipping_failures<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+        options: &LoadOptions,
+    ) -> Result<(Self, Vec<(PathBuf, anyhow::Error)>), anyhow::Error> {
+        MapItems::load_files(paths, options, true)
+    }
+
+    fn load_files<'a>(
+        paths: impl IntoIterator<Item = &'a Path>,
+        options: &LoadOptions,
+        skip_failures: bool,
+    ) -> Result<(Self, Vec<(PathBuf, anyhow::Error)>), anyhow::Error> {
+        let paths: Vec<&Path> = paths.into_iter().collect();
+        check_stdin_once(&paths)?;
+        let loaded = options.map_files(
+            &paths,
+            |path| MapItems::from_file(path, options),
+            skip_failures,
         );
 
-        assert_matches!(map_item, Ok(MapItem::PointItem(_)));
+        MapItems::combine_files(&paths, loaded, options, skip_failures)
+    }
 
-        if let Ok(MapItem::PointItem(point)) = map_item {
-            assert_eq!(point.point.x, 78.2306);
-            assert_eq!(point.point.y, -50.5124);
-            assert_eq!(point.point.z, 0.0020);
-            assert_eq!(point.color.r, 255);
-            assert_eq!(point.color.g, 254);
-            assert_eq!(point.color.b, 253);
-            assert_eq!(point.label, "to_The_Steamfont_Mountains")
+    /// Concatenates the items `loaded` from each of `paths`, colorizing them
+    /// and checking their count as `options` asks.
+    fn combine_files(
+        paths: &[&Path],
+        loaded: Vec<Result<MapItems, anyhow::Error>>,
+        options: &LoadOptions,
+        skip_failures: bool,
+    ) -> Result<(Self, Vec<(PathBuf, anyhow::Error)>), anyhow::Error> {
+        let mut items = Vec::new();
+        let mut extents = Vec::new();
+        let mut failures = Vec::new();
+
+        for (index, (path, loaded)) in paths.iter().zip(loaded).enumerate() {
+            let map_items = match loaded {
+                Ok(map_items) => map_items,
+                Err(err) if skip_failures => {
+                    extents.push(None);
+                    failures.push((path.to_path_buf(), err));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let bounds = map_items.bounding_box();
+            if let Some(bounds) = &bounds {
+                log::debug!(
+                    "{} spans {}, {} to {}, {}",
+                    path.display(),
+                    bounds.min_x,
+                    bounds.min_y,
+                    bounds.max_x,
+                    bounds.max_y
+                );
+            }
+            extents.push(bounds.map(|bounds| bounds.width().max(bounds.height())));
+
+            for mut item in map_items.items {
+                if options.colorize_by_file {
+                    let (r, g, b) = FILE_PALETTE[index % FILE_PALETTE.len()];
+                    item.set_color(Color { r, g, b });
+                }
+                items.push(item);
+            }
+            options.check_item_count(items.len())?;
         }
+
+        if let Some((largest, smallest)) = mixed_scales(&extents) {
+            log::warn!(
+                "{} is at least {} times the size of {}; the files may use different units",
+                paths[largest].display(),
+                MIXED_SCALE_RATIO,
+                paths[smallest].display()
+            );
+        }
+
+        Ok((MapItems { items }, failures))
     }
 
-    #[test]
-    fn parsing_line() {
-        let map_item = MapItem::parse("L 1000.0, 1.1, 2.2, 1000.0, -50.0, 3.3, 255, 254, 253");
+    /// Loads a single map file. A path ending in `.zip` is read as an archive
+    /// of map files when the `zip` feature is enabled, and one ending in
+    /// `.csv` with `from_csv_with`. `STDIN_PATH` reads the map from standard
+    /// input.
+    pub fn from_file(path: &Path, options: &LoadOptions) -> Result<Self, anyhow::Error> {
+        if is_stdin_path(path) {
+            let contents = read_stdin().context("Failed to read standard input")?;
+            return MapItems::from_source(path, Cursor::new(contents), options);
+        }
 
-        assert_matches!(map_item, Ok(MapItem::LineItem(_)));
+        if let Some(format @ (MapFormat::GeoJson | MapFormat::Svg)) = MapFormat::from_path(path) {
+            return Err(anyhow!("Reading {} files is not supported", format));
+        }
 
-        if let Ok(MapItem::LineItem(line)) = map_item {
-            assert_eq!(line.from.x, 1000.0);
-            assert_eq!(line.from.y, 1.1);
-            assert_eq!(line.from.z, 2.2);
-            assert_eq!(line.to.x, 1000.0);
-            assert_eq!(line.to.y, -50.0);
-            assert_eq!(line.to.z, 3.3);
-            assert_eq!(line.color.r, 255);
-            assert_eq!(line.color.g, 254);
-            assert_eq!(line.color.b, 253);
+        MapItems::from_source(path, BufReader::new(open_file(path)?), options)
+    }
+
+    /// Loads the contents of the file at `path`, read from `reader`, in the
+    /// format its name calls for, as `from_file` describes.
+    fn from_source(
+        path: &Path,
+        reader: impl BufRead + Seek,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        match MapFormat::from_path(path) {
+            Some(MapFormat::Csv) => MapItems::from_csv_with(reader, options)
+                .with_context(|| format!("Failed to read {}", path.display())),
+            Some(format @ (MapFormat::GeoJson | MapFormat::Svg)) => {
+                Err(anyhow!("Reading {} files is not supported", format))
+            }
+            _ if is_zip_path(path) => MapItems::from_zip(reader, options),
+            _ => decompressing_reader(reader)
+                .and_then(|reader| MapItems::from_reader(reader, options))
+                .with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Loads every `.map` and `.txt` entry in a zip archive. Entries are read
+    /// in sorted name order so that the result is deterministic. Without the
+    /// `zip` feature this always fails.
+    pub fn from_zip(
+        reader: impl std::io::Read + std::io::Seek,
+        options: &LoadOptions,
+    ) -> Result<Self, anyhow::Error> {
+        let mut items = Vec::new();
+
+        read_zip_entries(reader, |name, entry| {
+            let map_items = MapItems::from_reader(entry, options)
+                .with_context(|| format!("Failed to read {} from the archive", name))?;
+            items.extend(map_items.items);
+            options.check_item_count(items.len())
+        })?;
+
+        Ok(MapItems { items })
+    }
+
+    /// Loads a map file like `from_file`, but keeps a zip archive's entries
+    /// apart, named `archive.zip/entry.txt`. A plain map file is returned as
+    /// its only entry, named by its path.
+    pub fn from_file_by_entry(
+        path: &Path,
+        options: &LoadOptions,
+    ) -> Result<Vec<(String, MapItems)>, anyhow::Error> {
+        if !is_zip_path(path) {
+            return Ok(vec![(
+                path.display().to_string(),
+                MapItems::from_file(path, options)?,
+            )]);
+        }
+
+        let mut entries = Vec::new();
+        read_zip_entries(open_file(path)?, |name, entry| {
+            let map_items = MapItems::from_reader(entry, options)
+                .with_context(|| format!("Failed to read {} from the archive", name))?;
+            entries.push((format!("{}/{}", path.display(), name), map_items));
+            Ok(())
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Parses map items from any buffered reader, without touching the
+    /// filesystem. `#` lines are comments, or directives as described in
+    /// `map_directives`.
+    pub fn from_reader(reader: impl BufRead, options: &LoadOptions) -> Result<Self, anyhow::Error> {
+        let mut items = Vec::new();
+        let mut directives = Directives::default();
+
+        for (index, bytes) in reader.split(b'\n').enumerate() {
+            let line_number = index + 1;
+            let Some(line) = options.input_encoding.decode_line(&bytes?) else {
+                if options.strict {
+                    return Err(anyhow!(
+                        "Line {} isn't valid text in the input encoding",
+                        line_number
+                    ));
+                }
+                continue;
+            };
+            if options.strict && line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(comment) = line.strip_prefix('#') {
+                directives.read(comment);
+                if options.keep_raw_lines {
+                    items.push(MapItem::Raw(line));
+                }
+                continue;
+            }
+
+            let parsed = directives.parse(&line, |line| {
+                MapItem::parse(line, options.input_format, options.strict)
+            });
+            let item = match parsed {
+                Ok(item) => item,
+                Err(err) if options.strict => {
+                    return Err(err.context(format!("Failed to parse line {}", line_number)))
+                }
+                Err(_) if options.keep_raw_lines => MapItem::Raw(line),
+                Err(_) => continue,
+            };
+
+            items.push(item);
+            options.check_item_count(items.len())?;
+        }
+
+        Ok(MapItems { items })
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Replaces every point, both endpoints of every line and the position of
+    /// every text with the result of `map`, such as to move or reproject the
+    /// whole map.
+    pub fn map_points(&mut self, mut map: impl FnMut(Point) -> Point) {
+        for item in self.items.iter_mut() {
+            match item {
+                MapItem::PointItem(point) => point.point = map(point.point),
+                MapItem::LineItem(line) => {
+                    line.from = map(line.from);
+                    line.to = map(line.to);
+                }
+                MapItem::TextItem(text) => text.point = map(text.point),
+                MapItem::Raw(_) => {}
+            }
+        }
+    }
+
+    /// Replaces the color of every point, line and text with the result of
+    /// `map`.
+    pub fn map_colors(&mut self, mut map: impl FnMut(Color) -> Color) {
+        for item in self.items.iter_mut() {
+            if let Some(color) = item.color() {
+                item.set_color(map(color));
+            }
+        }
+    }
+
+    /// Sets the Z of every point and line endpoint to 0, for purely 2D work.
+    pub fn flatten_z(&mut self) {
+        self.map_points(|point| Point { z: 0.0, ..point });
+    }
+
+    /// Converts every point and line endpoint between `/loc` order and map
+    /// file coordinates, for maps written by hand from `/loc` output. See
+    /// `Point::from_loc`; the transform is its own inverse, so applying it
+    /// again converts back.
+    pub fn swap_loc_convention(&mut self) {
+        self.map_points(|point| Point::from_loc(point.x, point.y, point.z));
+    }
+
+    /// Writes the items in the `InputFormat::Eq` format, one per line, so that
+    /// `from_reader` reads them back unchanged.
+    pub fn write_to(&self, mut out: impl Write) -> std::io::Result<()> {
+        for item in self.items.iter() {
+            writeln!(out, "{}", item)?;
         }
+        out.flush()
+    }
+
+    /// The items as `write_to` writes them.
+    pub fn to_map_string(&self) -> String {
+        self.items
+            .iter()
+            .map(|item| format!("{}\n", item))
+            .collect()
+    }
+
+    /// Keeps only the items for which `keep` returns true, in order, like
+    /// `Vec::retain`.
+    pub fn retain(&mut self, keep: impl FnMut(&MapItem) -> bool) {
+        self.items.retain(keep);
+    }
+
+    /// Keeps only the first `len` items, in file order, like `Vec::truncate`.
+    pub fn truncate(&mut self, len: usize) {
+        self.items.truncate(len);
+    }
+
+    /// Moves all the items of `other` onto the end of this map.
+    pub fn append(&mut self, mut other: MapItems) {
+        self.items.append(&mut other.items);
+    }
+}
+
+impl Extend<MapItem> for MapItems {
+    fn extend<T: IntoIterator<Item = MapItem>>(&mut self, iter: T) {
+        self.items.extend(iter);
+    }
+}
+
+/// The input path that reads standard input instead of a file.
+pub const STDIN_PATH: &str = "-";
+
+pub(crate) fn is_stdin_path(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// Fails if `STDIN_PATH` is among `paths` more than once, as standard input
+/// can only be read through once.
+pub fn check_stdin_once(paths: &[&Path]) -> Result<(), anyhow::Error> {
+    if paths.iter().filter(|path| is_stdin_path(path)).count() > 1 {
+        return Err(anyhow!(
+            "Standard input ({}) can only be read once",
+            STDIN_PATH
+        ));
+    }
+    Ok(())
+}
+
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Reads a whole input without blocking, from standard input for
+/// `STDIN_PATH`.
+#[cfg(feature = "async-io")]
+async fn read_input(path: PathBuf) -> std::io::Result<Vec<u8>> {
+    if is_stdin_path(&path) {
+        tokio::task::spawn_blocking(read_stdin).await?
+    } else {
+        tokio::fs::read(path).await
+    }
+}
+
+/// Opens `path` for reading, naming it in the error.
+pub(crate) fn open_file(path: &Path) -> Result<File, anyhow::Error> {
+    File::open(path).with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Creates or truncates `path` for writing, naming it in the error.
+pub(crate) fn create_file(path: &Path) -> Result<File, anyhow::Error> {
+    File::create(path).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// The bytes every gzip stream starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Opens a map file, or standard input for `STDIN_PATH`, for reading its
+/// lines, decompressing it as `decompressing_reader` does.
+pub(crate) fn open_map_file(path: &Path) -> Result<Box<dyn BufRead>, anyhow::Error> {
+    if is_stdin_path(path) {
+        return decompressing_reader(std::io::stdin().lock())
+            .context("Failed to read standard input");
+    }
+
+    decompressing_reader(BufReader::new(open_file(path)?))
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Decompresses `reader` if it starts with `GZIP_MAGIC`, whatever the name
+/// of the file it reads.
+fn decompressing_reader<'a>(
+    mut reader: impl BufRead + 'a,
+) -> Result<Box<dyn BufRead + 'a>, anyhow::Error> {
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        gzip_reader(reader)
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_reader<'a>(reader: impl BufRead + 'a) -> Result<Box<dyn BufRead + 'a>, anyhow::Error> {
+    Ok(Box::new(BufReader::new(
+        flate2::bufread::MultiGzDecoder::new(reader),
+    )))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_reader<'a>(_reader: impl BufRead + 'a) -> Result<Box<dyn BufRead + 'a>, anyhow::Error> {
+    Err(anyhow!(
+        "Gzip-compressed map files require the gzip feature"
+    ))
+}
+
+pub(crate) fn is_zip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Calls `read` with every `.map` and `.txt` entry in a zip archive, in sorted
+/// name order so that the result is deterministic.
+#[cfg(feature = "zip")]
+pub(crate) fn read_zip_entries(
+    reader: impl std::io::Read + std::io::Seek,
+    mut read: impl FnMut(&str, &mut dyn BufRead) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            let name = name.to_ascii_lowercase();
+            name.ends_with(".map") || name.ends_with(".txt")
+        })
+        .map(String::from)
+        .collect();
+    names.sort();
+
+    for name in names {
+        let entry = archive.by_name(&name)?;
+        read(&name, &mut BufReader::new(entry))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "zip"))]
+pub(crate) fn read_zip_entries(
+    _reader: impl std::io::Read + std::io::Seek,
+    _read: impl FnMut(&str, &mut dyn BufRead) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!("Zip archives require the zip feature"))
+}
+
+impl Index<usize> for MapItems {
+    type Output = MapItem;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.items[index]
+    }
+}
+
+impl FromIterator<MapItem> for MapItems {
+    fn from_iter<T: IntoIterator<Item = MapItem>>(iter: T) -> Self {
+        MapItems {
+            items: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for MapItems {
+    type Item = MapItem;
+    type IntoIter = std::vec::IntoIter<MapItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl FromStr for MapItems {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        MapItems::from_reader(text.as_bytes(), &LoadOptions::default())
+    }
+}
+
+impl MapItem {
+    /// The color of a point, line or text, or `None` for raw lines.
+    pub fn color(&self) -> Option<Color> {
+        match self {
+            MapItem::PointItem(point) => Some(point.color),
+            MapItem::LineItem(line) => Some(line.color),
+            MapItem::TextItem(text) => Some(text.color),
+            MapItem::Raw(_) => None,
+        }
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        match self {
+            MapItem::PointItem(point) => point.color = color,
+            MapItem::LineItem(line) => line.color = color,
+            MapItem::TextItem(text) => text.color = color,
+            MapItem::Raw(_) => {}
+        }
+    }
+
+    /// Parses a line in `format`, failing on fields after the last one read
+    /// if `strict`, instead of dropping them.
+    fn parse(line: &str, format: InputFormat, strict: bool) -> Result<MapItem, anyhow::Error> {
+        let item = match format {
+            InputFormat::Eq => MapItem::parse_item(line, strict),
+            InputFormat::Legacy => MapItem::parse_legacy_item(line, strict),
+            InputFormat::Auto => MapItem::parse_item(line, strict)
+                .or_else(|_| MapItem::parse_legacy_item(line, strict)),
+        };
+
+        item.with_context(|| format!("Invalid map line {:?}", line))
+    }
+
+    pub(crate) fn parse_item(line: &str, strict: bool) -> Result<MapItem, anyhow::Error> {
+        let first_char = line
+            .chars()
+            .next()
+            .ok_or_else(|| anyhow!("Missing line identifier"))?;
+
+        let item = match first_char {
+            'P' => MapItem::PointItem(PointItem::parse(line, strict)?),
+            'L' => MapItem::LineItem(LineItem::parse(line, strict)?),
+            'T' => MapItem::TextItem(TextItem::parse(line, strict)?),
+            _ => return Err(anyhow!("Unrecognized line identifier {}", first_char)),
+        };
+
+        Ok(item)
+    }
+
+    /// Parses a line in `InputFormat::Legacy`.
+    /// P 78.2306,-50.5124,0.0020,255,0,0,to_The_Steamfont_Mountains
+    fn parse_legacy_item(line: &str, strict: bool) -> Result<MapItem, anyhow::Error> {
+        let (identifier, line) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("No line content"))?;
+
+        let segments = split_content(line.trim(), &LEGACY_CONTENT_SEPARATOR)?;
+
+        let item = match (identifier, &segments[..]) {
+            ("P", [x, y, z, r, g, b, label]) => {
+                let (label, sub_label) = split_sub_label(label);
+                MapItem::PointItem(PointItem {
+                    point: Point::parse(x, y, z)?,
+                    color: Color::parse(r, g, b)?,
+                    label: label.to_string(),
+                    sub_label,
+                    point_type: DEFAULT_POINT_TYPE,
+                })
+            }
+            ("P", [x, y, z, r, g, b, point_type, label]) => {
+                let (label, sub_label) = split_sub_label(label);
+                MapItem::PointItem(PointItem {
+                    point: Point::parse(x, y, z)?,
+                    color: Color::parse(r, g, b)?,
+                    label: label.to_string(),
+                    sub_label,
+                    point_type: parse_point_type(point_type)?,
+                })
+            }
+            ("L", [fx, fy, fz, tx, ty, tz, r, g, b, extra @ ..]) => {
+                check_extra_fields(extra.len(), strict)?;
+                MapItem::LineItem(LineItem {
+                    from: Point::parse(fx, fy, fz)?,
+                    to: Point::parse(tx, ty, tz)?,
+                    color: Color::parse(r, g, b)?,
+                    width: None,
+                })
+            }
+            ("P" | "L", _) => return Err(anyhow!("Wrong number of line content segments")),
+            _ => return Err(anyhow!("Unrecognized line identifier {}", identifier)),
+        };
+
+        Ok(item)
+    }
+}
+
+/// The label prefix the game's maps use for points that mark a connection
+/// to another zone, as in `to_The_Steamfont_Mountains`.
+pub const ZONE_LINK_PREFIX: &str = "to_";
+
+impl PointItem {
+    pub fn new(point: Point, color: Color, label: impl Into<String>) -> Self {
+        PointItem {
+            point,
+            color,
+            label: label.into(),
+            sub_label: None,
+            point_type: DEFAULT_POINT_TYPE,
+        }
+    }
+
+    /// Whether the label marks a zone connection, by starting with
+    /// `ZONE_LINK_PREFIX`. This is only a naming convention.
+    pub fn label_is_zone_link(&self) -> bool {
+        self.label_is_zone_link_with(ZONE_LINK_PREFIX)
+    }
+
+    /// Like `label_is_zone_link`, for maps that mark zone connections with a
+    /// different prefix.
+    pub fn label_is_zone_link_with(&self, prefix: &str) -> bool {
+        self.label.starts_with(prefix)
+    }
+
+    /// The number at the end of the label, such as 5 in `spawn_rare_5` or
+    /// 2.5 in `Spawn 2.5`: the text after the last underscore or whitespace,
+    /// or the whole label if it has neither, if that is a positive number.
+    pub fn label_number(&self) -> Option<f32> {
+        let token = self
+            .label
+            .rsplit(|c: char| c == '_' || c.is_whitespace())
+            .next()?;
+
+        token
+            .parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite() && *number > 0.0)
+    }
+
+    /// Parses a PointItem from a map file line, splitting a sub-label off the
+    /// label at its first `|`. Fields after the label are ignored, unless
+    /// `strict`.
+    /// P 78.2306, -50.5124, 0.0020, 255, 0, 0, 3, to_The_Steamfont_Mountains
+    fn parse(line: &str, strict: bool) -> Result<Self, anyhow::Error> {
+        let (_, line) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("No line content"))?;
+
+        let segments = split_content(line, &LINE_CONTENT_SEPARATOR)?;
+
+        let [x, y, z, r, g, b, point_type, label, extra @ ..] = &segments[..] else {
+            return Err(anyhow!("Not enough line content segments"));
+        };
+        check_extra_fields(extra.len(), strict)?;
+
+        let (label, sub_label) = split_sub_label(label);
+
+        Ok(PointItem {
+            point: Point::parse(x, y, z)?,
+            color: Color::parse(r, g, b)?,
+            label: label.to_string(),
+            sub_label,
+            point_type: parse_point_type(point_type)?,
+        })
+    }
+}
+
+impl LineItem {
+    pub fn new(from: Point, to: Point, color: Color) -> Self {
+        LineItem {
+            from,
+            to,
+            color,
+            width: None,
+        }
+    }
+
+    /// The length of the line in the XY plane.
+    pub fn length(&self) -> f32 {
+        (self.to.x - self.from.x).hypot(self.to.y - self.from.y)
+    }
+
+    /// How far apart the two ends are in Z.
+    pub fn z_delta(&self) -> f32 {
+        (self.to.z - self.from.z).abs()
+    }
+
+    /// The point halfway along the line.
+    pub fn midpoint(&self) -> Point {
+        self.from.midpoint(&self.to)
+    }
+
+    /// Parses a LineItem from a map file line, with an optional width after
+    /// the color. Fields after the width are ignored, unless `strict`.
+    /// L 1000.0, 0.0, 0.0, 1000.0, -50.0, 0.0, 255, 0, 0
+    /// L 1000.0, 0.0, 0.0, 1000.0, -50.0, 0.0, 255, 0, 0, 2
+    fn parse(line: &str, strict: bool) -> Result<Self, anyhow::Error> {
+        let (_, line) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("No line content"))?;
+
+        let segments: Vec<&str> = LINE_CONTENT_SEPARATOR.split(line).collect();
+
+        let [fx, fy, fz, tx, ty, tz, r, g, b, ref width @ ..] = segments[..] else {
+            return Err(anyhow!("Not enough line content segments"));
+        };
+        check_extra_fields(width.len().saturating_sub(1), strict)?;
+        let width = match width.first() {
+            None => None,
+            Some(width) => Some(
+                width
+                    .parse()
+                    .with_context(|| format!("Invalid line width {}", width))?,
+            ),
+        };
+
+        Ok(LineItem {
+            from: Point::parse(fx, fy, fz)?,
+            to: Point::parse(tx, ty, tz)?,
+            color: Color::parse(r, g, b)?,
+            width,
+        })
+    }
+}
+
+impl TextItem {
+    pub fn new(point: Point, color: Color, size: f32, text: impl Into<String>) -> Self {
+        TextItem {
+            point,
+            color,
+            size,
+            text: text.into(),
+        }
+    }
+
+    /// Parses a TextItem from a map file line, laid out like a point with
+    /// the size in place of the point type. Fields after the text are
+    /// ignored, unless `strict`.
+    /// T 100.0, -50.0, 0.0, 255, 255, 255, 2, North_Gate
+    fn parse(line: &str, strict: bool) -> Result<Self, anyhow::Error> {
+        let (_, line) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("No line content"))?;
+
+        let segments = split_content(line, &LINE_CONTENT_SEPARATOR)?;
+
+        let [x, y, z, r, g, b, size, text, extra @ ..] = &segments[..] else {
+            return Err(anyhow!("Not enough line content segments"));
+        };
+        check_extra_fields(extra.len(), strict)?;
+        let size: f32 = size
+            .parse()
+            .with_context(|| format!("Invalid text size {}", size))?;
+        if !(size.is_finite() && size > 0.0) {
+            return Err(anyhow!("Invalid text size {}", size));
+        }
+
+        Ok(TextItem {
+            point: Point::parse(x, y, z)?,
+            color: Color::parse(r, g, b)?,
+            size,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Fails if `strict` and there are `extra` fields after the last one read.
+fn check_extra_fields(extra: usize, strict: bool) -> Result<(), anyhow::Error> {
+    if strict && extra > 0 {
+        return Err(anyhow!("Unexpected fields after the last one read"));
+    }
+    Ok(())
+}
+
+/// Splits the content of a map line into its fields. One field may be
+/// double-quoted, in which case it can contain separators and spaces, and `""`
+/// stands for a quote; any fields after it are split as usual. A quote
+/// anywhere other than the start of a field is kept as part of the field.
+fn split_content<'a>(
+    content: &'a str,
+    separator: &Regex,
+) -> Result<Vec<Cow<'a, str>>, anyhow::Error> {
+    let quoted_field = separator
+        .find_iter(content)
+        .find(|m| content[m.end()..].starts_with('"'));
+
+    let Some(quoted_field) = quoted_field else {
+        return Ok(separator.split(content).map(Cow::Borrowed).collect());
+    };
+
+    let mut fields: Vec<Cow<str>> = separator
+        .split(&content[..quoted_field.start()])
+        .map(Cow::Borrowed)
+        .collect();
+
+    let mut label = String::new();
+    let mut chars = content[quoted_field.end() + 1..].chars();
+    loop {
+        match chars.next() {
+            Some('"') if chars.as_str().starts_with('"') => {
+                chars.next();
+                label.push('"');
+            }
+            Some('"') => break,
+            Some(c) => label.push(c),
+            None => return Err(anyhow!("Unterminated quoted label")),
+        }
+    }
+
+    fields.push(Cow::Owned(label));
+
+    let rest = chars.as_str();
+    if rest.trim().is_empty() {
+        return Ok(fields);
+    }
+    match separator.find(rest) {
+        Some(m) if m.start() == 0 => {
+            fields.extend(separator.split(&rest[m.end()..]).map(Cow::Borrowed));
+            Ok(fields)
+        }
+        _ => Err(anyhow!("Unexpected content after the quoted label")),
+    }
+}
+
+/// Splits a point's label field at its first `|` into the label and a
+/// sub-label, trimming the space around the `|`. An empty sub-label is none.
+fn split_sub_label(field: &str) -> (&str, Option<String>) {
+    match field.split_once('|') {
+        Some((label, sub_label)) => (
+            label.trim_end(),
+            Some(sub_label.trim_start())
+                .filter(|sub_label| !sub_label.is_empty())
+                .map(str::to_string),
+        ),
+        None => (field, None),
+    }
+}
+
+/// The point type of points that aren't given one, such as legacy lines
+/// that leave it out.
+pub const DEFAULT_POINT_TYPE: u8 = 2;
+
+fn parse_point_type(point_type: &str) -> Result<u8, anyhow::Error> {
+    point_type
+        .parse()
+        .with_context(|| format!("Invalid point type {}", point_type))
+}
+
+/// Formats the item as a map file line. Labels containing a comma, or
+/// starting with a quote, are quoted so that they read back whole.
+impl fmt::Display for MapItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapItem::PointItem(PointItem {
+                point,
+                color,
+                label,
+                sub_label,
+                point_type,
+            }) => {
+                write!(
+                    f,
+                    "P {}, {}, {}, {}, {}, {}, {}, ",
+                    point.x, point.y, point.z, color.r, color.g, color.b, point_type
+                )?;
+                match sub_label {
+                    Some(sub_label) => write_label(f, &format!("{} | {}", label, sub_label)),
+                    None => write_label(f, label),
+                }
+            }
+            MapItem::LineItem(LineItem {
+                from,
+                to,
+                color,
+                width,
+            }) => {
+                write!(
+                    f,
+                    "L {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                    from.x, from.y, from.z, to.x, to.y, to.z, color.r, color.g, color.b
+                )?;
+                match width {
+                    Some(width) => write!(f, ", {}", width),
+                    None => Ok(()),
+                }
+            }
+            MapItem::TextItem(TextItem {
+                point,
+                color,
+                size,
+                text,
+            }) => {
+                write!(
+                    f,
+                    "T {}, {}, {}, {}, {}, {}, {}, ",
+                    point.x, point.y, point.z, color.r, color.g, color.b, size
+                )?;
+                write_label(f, text)
+            }
+            MapItem::Raw(line) => f.write_str(line),
+        }
+    }
+}
+
+/// Writes the last field of a point or text line, quoted if it would
+/// otherwise not read back the same.
+fn write_label(f: &mut fmt::Formatter<'_>, label: &str) -> fmt::Result {
+    if label.contains(',') || label.starts_with('"') {
+        write!(f, "\"{}\"", label.replace('"', "\"\""))
+    } else {
+        f.write_str(label)
+    }
+}
+
+impl TryFrom<&str> for PointItem {
+    type Error = anyhow::Error;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        check_identifier(line, "P")?;
+        PointItem::parse(line, false)
+    }
+}
+
+impl TryFrom<&str> for LineItem {
+    type Error = anyhow::Error;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        check_identifier(line, "L")?;
+        LineItem::parse(line, false)
+    }
+}
+
+/// Checks that `line` starts with `identifier`, as the item parsers skip it
+/// unread.
+fn check_identifier(line: &str, identifier: &str) -> Result<(), anyhow::Error> {
+    let found = line.split(' ').next().unwrap_or_default();
+    if found != identifier {
+        return Err(anyhow!(
+            "Expected a {} line but got {:?}",
+            identifier,
+            found
+        ));
+    }
+
+    Ok(())
+}
+
+lazy_static! {
+    static ref LINE_CONTENT_SEPARATOR: Regex = Regex::new(",\\s+").unwrap();
+    static ref LEGACY_CONTENT_SEPARATOR: Regex = Regex::new(",\\s*").unwrap();
+    static ref LOC_LINE: Regex = Regex::new(
+        r"(?i)your location is\s+([-+]?[0-9]*\.?[0-9]+),\s*([-+]?[0-9]*\.?[0-9]+),\s*([-+]?[0-9]*\.?[0-9]+)"
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map_items::{
+        Color, InputEncoding, InputFormat, LineItem, LoadOptions, MapItem, MapItems, Point,
+        PointItem, TextItem,
+    };
+
+    #[test]
+    fn parsing_point() {
+        let map_item = MapItem::parse(
+            "P 78.2306, -50.5124, 0.0020, 255, 254, 253, 3, to_The_Steamfont_Mountains",
+            InputFormat::Eq,
+            false,
+        );
+
+        assert!(matches!(map_item, Ok(MapItem::PointItem(_))));
+
+        if let Ok(MapItem::PointItem(point)) = map_item {
+            assert_eq!(point.point.x, 78.2306);
+            assert_eq!(point.point.y, -50.5124);
+            assert_eq!(point.point.z, 0.0020);
+            assert_eq!(point.color.r, 255);
+            assert_eq!(point.color.g, 254);
+            assert_eq!(point.color.b, 253);
+            assert_eq!(point.label, "to_The_Steamfont_Mountains");
+            assert_eq!(point.point_type, 3);
+        }
+
+        assert!(MapItem::parse(
+            "P 1.0, 2.0, 3.0, 0, 0, 0, big, Bank",
+            InputFormat::Eq,
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parsing_a_sub_label() {
+        let point =
+            PointItem::try_from("P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank | closes at night").unwrap();
+        assert_eq!(point.label, "Bank");
+        assert_eq!(point.sub_label.as_deref(), Some("closes at night"));
+        assert_eq!(
+            MapItem::PointItem(point).to_string(),
+            "P 1, 2, 3, 0, 0, 0, 2, Bank | closes at night"
+        );
+
+        let point =
+            PointItem::try_from("P 1.0, 2.0, 3.0, 0, 0, 0, 2, \"Bank, east | closed\"").unwrap();
+        assert_eq!(point.label, "Bank, east");
+        assert_eq!(
+            MapItem::PointItem(point).to_string(),
+            "P 1, 2, 3, 0, 0, 0, 2, \"Bank, east | closed\""
+        );
+
+        let point = PointItem::try_from("P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank").unwrap();
+        assert_eq!(point.sub_label, None);
+    }
+
+    #[test]
+    fn parsing_text() {
+        let map_item = MapItem::parse(
+            "T 100.0, -50.0, 1.5, 255, 254, 253, 1.5, North_Gate",
+            InputFormat::Eq,
+            false,
+        )
+        .unwrap();
+
+        let MapItem::TextItem(text) = &map_item else {
+            panic!("expected a text item, got {:?}", map_item);
+        };
+        assert_eq!(
+            *text,
+            TextItem::new(
+                Point::new(100.0, -50.0, 1.5),
+                Color::new(255, 254, 253),
+                1.5,
+                "North_Gate"
+            )
+        );
+        assert_eq!(
+            map_item.to_string(),
+            "T 100, -50, 1.5, 255, 254, 253, 1.5, North_Gate"
+        );
+
+        for line in [
+            "T 1.0, 2.0, 3.0, 0, 0, 0, 0, Note",
+            "T 1.0, 2.0, 3.0, 0, 0, 0, big, Note",
+            "T 1.0, 2.0, 3.0, 0, 0, 0, Note",
+        ] {
+            assert!(
+                MapItem::parse(line, InputFormat::Eq, false).is_err(),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn parsing_line() {
+        let map_item = MapItem::parse(
+            "L 1000.0, 1.1, 2.2, 1000.0, -50.0, 3.3, 255, 254, 253",
+            InputFormat::Eq,
+            false,
+        );
+
+        assert!(matches!(map_item, Ok(MapItem::LineItem(_))));
+
+        if let Ok(MapItem::LineItem(line)) = map_item {
+            assert_eq!(line.from.x, 1000.0);
+            assert_eq!(line.from.y, 1.1);
+            assert_eq!(line.from.z, 2.2);
+            assert_eq!(line.to.x, 1000.0);
+            assert_eq!(line.to.y, -50.0);
+            assert_eq!(line.to.z, 3.3);
+            assert_eq!(line.color.r, 255);
+            assert_eq!(line.color.g, 254);
+            assert_eq!(line.color.b, 253);
+            assert_eq!(line.width, None);
+        }
+    }
+
+    #[test]
+    fn parsing_line_width() {
+        let line = LineItem::try_from("L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0, 2.5").unwrap();
+        assert_eq!(line.width, Some(2.5));
+        assert_eq!(
+            MapItem::LineItem(line).to_string(),
+            "L 1, 2, 3, 4, 5, 6, 255, 0, 0, 2.5"
+        );
+
+        assert!(LineItem::try_from("L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0, wide").is_err());
+    }
+
+    #[test]
+    fn ignoring_extra_trailing_fields() {
+        let point = PointItem::try_from("P 1.0, 2.0, 3.0, 10, 20, 30, 2, Bank, flagged").unwrap();
+        assert_eq!(
+            point,
+            PointItem::new(Point::new(1.0, 2.0, 3.0), Color::new(10, 20, 30), "Bank")
+        );
+
+        let point = PointItem::try_from(r#"P 1.0, 2.0, 3.0, 10, 20, 30, 2, "Bank, east", flagged"#)
+            .unwrap();
+        assert_eq!(point.label, "Bank, east");
+
+        let line = LineItem::try_from("L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0, 2, 3").unwrap();
+        assert_eq!(line.width, Some(2.0));
+
+        let text = MapItem::parse(
+            "T 1.0, 2.0, 3.0, 0, 0, 0, 2, Note, flagged",
+            InputFormat::Eq,
+            false,
+        )
+        .unwrap();
+        assert!(matches!(&text, MapItem::TextItem(text) if text.text == "Note"));
+
+        let legacy =
+            MapItem::parse("L 1,2,3,4,5,6,255,0,0,extra", InputFormat::Legacy, false).unwrap();
+        assert!(matches!(legacy, MapItem::LineItem(_)));
+    }
+
+    #[test]
+    fn point_item_try_from() {
+        let point = PointItem::try_from("P 1.5, -2.5, 3.0, 10, 20, 30, 2, Bank").unwrap();
+
+        assert_eq!(point.point.x, 1.5);
+        assert_eq!(point.point.y, -2.5);
+        assert_eq!(point.color.g, 20);
+        assert_eq!(point.label, "Bank");
+
+        assert!(PointItem::try_from("L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0").is_err());
+        assert!(PointItem::try_from("T 1.5, -2.5, 3.0, 10, 20, 30, 2, Bank").is_err());
+    }
+
+    #[test]
+    fn line_item_try_from() {
+        let line = LineItem::try_from("L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0").unwrap();
+
+        assert_eq!(line.from.x, 1.0);
+        assert_eq!(line.to.z, 6.0);
+        assert_eq!(line.color.r, 255);
+
+        assert!(LineItem::try_from("P 1.5, -2.5, 3.0, 10, 20, 30, 2, Bank").is_err());
+        assert!(LineItem::try_from("P 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0, 2").is_err());
+    }
+
+    #[test]
+    fn parse_error_quotes_line() {
+        let err = MapItem::parse("P 1.0, 2.0, bogus", InputFormat::Eq, false).unwrap_err();
+
+        assert!(format!("{}", err).contains("P 1.0, 2.0, bogus"));
+    }
+
+    #[test]
+    fn parsing_map_items_from_str() {
+        let map_items: MapItems = "P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank\nnot a map line\nL 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(map_items.items.len(), 2);
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn loading_zip_archive_in_name_order() {
+        use std::io::{Cursor, Write};
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let file_options = zip::write::SimpleFileOptions::default();
+        writer.start_file("b.txt", file_options).unwrap();
+        writer
+            .write_all(b"P 2.0, 0.0, 0.0, 0, 0, 0, 2, Second\n")
+            .unwrap();
+        writer.start_file("readme.md", file_options).unwrap();
+        writer
+            .write_all(b"P 9.0, 0.0, 0.0, 0, 0, 0, 2, Ignored\n")
+            .unwrap();
+        writer.start_file("a.map", file_options).unwrap();
+        writer
+            .write_all(b"P 1.0, 0.0, 0.0, 0, 0, 0, 2, First\n")
+            .unwrap();
+        let archive = writer.finish().unwrap();
+
+        let map_items = MapItems::from_zip(archive, &Default::default()).unwrap();
+
+        let labels: Vec<&str> = map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, ["First", "Second"]);
+    }
+
+    #[test]
+    fn loading_stops_past_max_items() {
+        let text = "P 1.0, 2.0, 3.0, 0, 0, 0, 1, A\nP 4.0, 5.0, 6.0, 0, 0, 0, 1, B\n";
+        let options = |max_items| LoadOptions {
+            max_items: Some(max_items),
+            ..Default::default()
+        };
+
+        assert!(MapItems::from_reader(text.as_bytes(), &options(2)).is_ok());
+
+        let err = MapItems::from_reader(text.as_bytes(), &options(1))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Map has more than the maximum of 1 items");
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn loading_files_concurrently_stops_past_max_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("max-items.txt");
+        std::fs::write(
+            &path,
+            "P 1.0, 2.0, 3.0, 0, 0, 0, 1, A
+"
+            .repeat(3),
+        )
+        .unwrap();
+        let options = |max_items| LoadOptions {
+            max_items: Some(max_items),
+            ..Default::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let loaded = runtime
+            .block_on(MapItems::load_from_files_with(
+                [path.as_path()],
+                &options(3),
+            ))
+            .unwrap();
+        let err = runtime
+            .block_on(MapItems::load_from_files_with(
+                [path.as_path(), path.as_path()],
+                &options(5),
+            ))
+            .unwrap_err();
+        let single = runtime.block_on(MapItems::load_from_file_with(&path, &options(2)));
+
+        assert_eq!(loaded.items.len(), 3);
+        assert_eq!(err.to_string(), "Map has more than the maximum of 5 items");
+        assert!(single.is_err());
+    }
+
+    #[test]
+    fn loading_strictly_rejects_extra_fields() {
+        let options = |strict, input_format| LoadOptions {
+            strict,
+            input_format,
+            ..Default::default()
+        };
+
+        for (line, input_format) in [
+            ("P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank, extra", InputFormat::Eq),
+            (
+                "L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0, 0, 0, 2, extra",
+                InputFormat::Eq,
+            ),
+            ("T 1.0, 2.0, 3.0, 0, 0, 0, 2, Note, extra", InputFormat::Eq),
+            ("L 1,2,3,4,5,6,255,0,0,extra", InputFormat::Legacy),
+        ] {
+            let loose = MapItems::from_reader(line.as_bytes(), &options(false, input_format));
+            assert_eq!(loose.unwrap().items.len(), 1, "{}", line);
+
+            let err = MapItems::from_reader(line.as_bytes(), &options(true, input_format))
+                .err()
+                .unwrap();
+            assert!(
+                format!("{:#}", err).contains("Unexpected fields after the last one read"),
+                "{:#}",
+                err
+            );
+        }
+
+        let line = "L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0, 0, 0, 2";
+        let strict = MapItems::from_reader(line.as_bytes(), &options(true, InputFormat::Eq));
+        assert_eq!(strict.unwrap().items.len(), 1);
+    }
+
+    #[test]
+    fn loading_strictly() {
+        let strict = LoadOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        let text = "# header\n\nP 1.0, 2.0, 3.0, 0, 0, 0, 1, A\n   \nL 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0, 0, 0\n";
+        let map_items = MapItems::from_reader(text.as_bytes(), &strict).unwrap();
+        assert_eq!(map_items.items.len(), 2);
+
+        let text = "P 1.0, 2.0, 3.0, 0, 0, 0, 1, A\n\nP 1.0, 2.0, oops\n";
+        let err = MapItems::from_reader(text.as_bytes(), &strict)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Failed to parse line 3");
+        assert!(
+            format!("{:#}", err).contains("\"P 1.0, 2.0, oops\""),
+            "{:#}",
+            err
+        );
+
+        let map_items = MapItems::from_reader(text.as_bytes(), &LoadOptions::default()).unwrap();
+        assert_eq!(map_items.items.len(), 1);
+    }
+
+    #[test]
+    fn line_geometry() {
+        let line = LineItem::try_from("L 1.0, 2.0, 3.0, 4.0, 6.0, -3.0, 0, 0, 0").unwrap();
+
+        assert_eq!(line.length(), 5.0);
+
+        let midpoint = line.midpoint();
+        assert_eq!((midpoint.x, midpoint.y, midpoint.z), (2.5, 4.0, 0.0));
+    }
+
+    #[test]
+    fn loading_legacy_format() {
+        let text = "P 1.0,2.0,3.0,255,0,0,Bank\nL 0.0, 0.0,0.0,1.0,1.0,1.0,0,0,255\n";
+        let options = |input_format| LoadOptions {
+            input_format,
+            ..Default::default()
+        };
+
+        let map_items =
+            MapItems::from_reader(text.as_bytes(), &options(InputFormat::Legacy)).unwrap();
+        assert_eq!(map_items.items.len(), 2);
+        assert!(matches!(&map_items.items[0], MapItem::PointItem(point) if point.label == "Bank"));
+        assert!(matches!(&map_items.items[1], MapItem::LineItem(line) if line.to.z == 1.0));
+
+        let map_items = MapItems::from_reader(text.as_bytes(), &options(InputFormat::Eq)).unwrap();
+        assert_eq!(map_items.items.len(), 0);
+
+        let text = "P 1.0, 2.0, 3.0, 0, 0, 0, 2, Inn\nP 1.0,2.0,3.0,0,0,0,Bank\n";
+        let map_items =
+            MapItems::from_reader(text.as_bytes(), &options(InputFormat::Auto)).unwrap();
+        assert_eq!(map_items.items.len(), 2);
+    }
+
+    #[test]
+    fn loading_latin1_labels() {
+        let text = b"P 1.0, 2.0, 3.0, 0, 0, 0, 2, Caf\xe9\r\nP 4.0, 5.0, 6.0, 0, 0, 0, 2, Bank\n";
+
+        let map_items = MapItems::from_reader(&text[..], &LoadOptions::default()).unwrap();
+        assert_eq!(map_items.items.len(), 1);
+
+        let options = LoadOptions {
+            input_encoding: "latin1".parse().unwrap(),
+            ..Default::default()
+        };
+        let map_items = MapItems::from_reader(&text[..], &options).unwrap();
+        assert_eq!(map_items.items.len(), 2);
+        assert!(matches!(&map_items.items[0], MapItem::PointItem(point) if point.label == "Café"));
+        assert!("klingon".parse::<InputEncoding>().is_err());
+    }
+
+    #[test]
+    fn writing_map_items() {
+        let text = "P 1.5, -2, 3, 255, 0, 0, 2, Bank\n\
+            P 0, 0, 0, 0, 0, 0, 2, \"Inn, \"\"The Rest\"\"\"\n\
+            L 0, 0, 0, 10, 20.25, -1, 0, 0, 255\n";
+        let map_items: MapItems = text.parse().unwrap();
+
+        let mut written = Vec::new();
+        map_items.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn reading_standard_input_only_once() {
+        use crate::map_items::STDIN_PATH;
+        use std::path::Path;
+
+        let stdin = Path::new(STDIN_PATH);
+        let err = MapItems::from_files(
+            [stdin, Path::new("other.txt"), stdin],
+            &LoadOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Standard input (-) can only be read once");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn loading_gzipped_files() {
+        use std::io::Write;
+
+        let text = "P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank\nL 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        // Compressed files are recognized by their content, not their name.
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["map.map.gz", "map.txt"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, &gzipped).unwrap();
+            let map_items = MapItems::from_file(&path, &LoadOptions::default());
+
+            assert_eq!(
+                map_items.unwrap().items,
+                text.parse::<MapItems>().unwrap().items
+            );
+        }
+    }
+
+    #[cfg(feature = "async-io")]
+    #[test]
+    fn loading_files_concurrently_keeps_their_order() {
+        use std::path::PathBuf;
+
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["First", "Second", "Third"]
+            .iter()
+            .map(|label| {
+                let path = dir.path().join(format!("{}.txt", label));
+                std::fs::write(&path, format!("P 1.0, 2.0, 3.0, 0, 0, 0, 2, {}\n", label)).unwrap();
+                path
+            })
+            .collect();
+        let missing = dir.path().join("missing.txt");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let map_items = runtime
+            .block_on(MapItems::load_from_files(
+                paths.iter().map(PathBuf::as_path),
+            ))
+            .unwrap();
+        let err = runtime
+            .block_on(MapItems::load_from_files([
+                paths[0].as_path(),
+                missing.as_path(),
+            ]))
+            .unwrap_err();
+
+        let labels: Vec<&str> = map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::PointItem(point) => Some(point.label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels, ["First", "Second", "Third"]);
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to open {}", missing.display())
+        );
+    }
+
+    #[test]
+    fn fixtures_round_trip_through_the_map_format() {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("maps/City/Akanon");
+        for name in ["akanon.txt", "akanon_1.txt", "akanon_2.txt"] {
+            let map_items =
+                MapItems::from_file(&fixtures.join(name), &LoadOptions::default()).unwrap();
+            assert!(!map_items.is_empty(), "{}", name);
+
+            let written = map_items.to_map_string();
+            let mut buffer = Vec::new();
+            map_items.write_to(&mut buffer).unwrap();
+            assert_eq!(buffer, written.as_bytes());
+
+            let read_back: MapItems = written.parse().unwrap();
+            assert_eq!(read_back.items, map_items.items, "{}", name);
+        }
+    }
+
+    #[test]
+    fn missing_file_error_names_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.txt");
+        let err = MapItems::from_file(&path, &LoadOptions::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to open {}", path.display())
+        );
+    }
+
+    #[test]
+    fn reading_loc_lines() {
+        let point = Point::from_loc_line("Your Location is 1234.50, -678.90, 12.30").unwrap();
+        assert_eq!(point, Point::new(678.9, -1234.5, 12.3));
+
+        let point =
+            Point::from_loc_line("[Mon Oct 12 20:15:01 2026] Your Location is -52.13, 8.00, -3.75")
+                .unwrap();
+        assert_eq!(point, Point::new(-8.0, 52.13, -3.75));
+
+        assert!(Point::from_loc_line("You say, 'at 10, 20, 30'").is_err());
+        assert!(Point::from_loc_line("Your Location is 10, 20").is_err());
+    }
+
+    #[test]
+    fn converting_loc_coordinates() {
+        // "Your Location is 100.00, -250.50, 3.25" in game.
+        let point = Point::from_loc(100.0, -250.5, 3.25);
+        assert_eq!((point.x, point.y, point.z), (250.5, -100.0, 3.25));
+        assert_eq!(point.to_loc(), (100.0, -250.5, 3.25));
+
+        let mut map_items: MapItems = "L 100.0, -250.5, 0.0, 10.0, 20.0, 5.0, 0, 0, 0\n\
+                                       P 0.0, 30.0, 0.0, 0, 0, 0, 2, Bank"
+            .parse()
+            .unwrap();
+        map_items.swap_loc_convention();
+
+        let mut written = Vec::new();
+        map_items.write_to(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "L 250.5, -100, 0, -20, -10, 5, 0, 0, 0\nP -30, 0, 0, 0, 0, 0, 2, Bank\n"
+        );
+
+        map_items.swap_loc_convention();
+        let MapItem::LineItem(line) = &map_items[0] else {
+            panic!("Expected a line");
+        };
+        assert_eq!((line.from.x, line.from.y), (100.0, -250.5));
+    }
+
+    #[test]
+    fn numbers_at_the_end_of_labels() {
+        let number = |label| {
+            PointItem::new(Point::new(0.0, 0.0, 0.0), Color::new(0, 0, 0), label).label_number()
+        };
+
+        assert_eq!(number("spawn_rare_5"), Some(5.0));
+        assert_eq!(number("Spawn 2.5"), Some(2.5));
+        assert_eq!(number("12"), Some(12.0));
+        assert_eq!(number("Bank"), None);
+        assert_eq!(number("spawn5"), None);
+        assert_eq!(number("spawn_0"), None);
+        assert_eq!(number("spawn_inf"), None);
+    }
+
+    #[test]
+    fn recognizing_zone_links() {
+        let point = |label| PointItem::new(Point::new(0.0, 0.0, 0.0), Color::new(0, 0, 0), label);
+
+        assert!(point("to_The_Steamfont_Mountains").label_is_zone_link());
+        assert!(!point("Bank").label_is_zone_link());
+        assert!(point("Zone: Steamfont").label_is_zone_link_with("Zone:"));
+    }
+
+    #[test]
+    fn skipping_files_that_fail_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.txt");
+        let missing = dir.path().join("missing.txt");
+        std::fs::write(&good, "P 1.0, 2.0, 3.0, 0, 0, 0, 2, Bank\n").unwrap();
+
+        let paths = [missing.as_path(), good.as_path()];
+        assert!(MapItems::from_files(paths, &LoadOptions::default()).is_err());
+
+        let (map_items, failures) =
+            MapItems::from_files_skipping_failures(paths, &LoadOptions::default()).unwrap();
+        assert_eq!(map_items.len(), 1);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, missing);
+    }
+
+    #[test]
+    fn loading_files_stops_at_the_first_failure() {
+        use std::sync::atomic::{self, AtomicUsize};
+
+        let calls = |jobs, keep_going| {
+            let options = LoadOptions {
+                jobs,
+                ..Default::default()
+            };
+            let calls = AtomicUsize::new(0);
+            let results = options.map_files(
+                &[0, 1, 2, 3, 4],
+                |input| {
+                    calls.fetch_add(1, atomic::Ordering::Relaxed);
+                    match input {
+                        0 => Err(anyhow::anyhow!("bad file")),
+                        input => Ok(*input),
+                    }
+                },
+                keep_going,
+            );
+            (calls.into_inner(), results.len())
+        };
+
+        assert_eq!(calls(1, false), (1, 1));
+        assert_eq!(calls(1, true), (5, 5));
+        assert_eq!(calls(2, false).1, 1);
+        assert_eq!(calls(2, true), (5, 5));
+    }
+
+    #[test]
+    fn loading_files_in_parallel_keeps_their_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<std::path::PathBuf> = (0..5)
+            .map(|index| {
+                let path = dir.path().join(format!("{}.txt", index));
+                let line = format!("P {}.0, 0.0, 0.0, 0, 0, 0, 2, File{}\n", index, index);
+                std::fs::write(&path, line).unwrap();
+                path
+            })
+            .collect();
+
+        let labels = |jobs| {
+            let options = LoadOptions {
+                jobs,
+                ..Default::default()
+            };
+            let map_items =
+                MapItems::from_files(paths.iter().map(|path| path.as_path()), &options).unwrap();
+            map_items
+                .items
+                .into_iter()
+                .filter_map(|item| match item {
+                    MapItem::PointItem(point) => Some(point.label),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let sequential = labels(1);
+        let parallel = labels(3);
+        assert_eq!(sequential, ["File0", "File1", "File2", "File3", "File4"]);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn point_arithmetic() {
+        let a = Point::new(1.0, 2.0, 3.0);
+        let b = Point::new(0.5, -4.0, 10.0);
+
+        assert_eq!(a + b, Point::new(1.5, -2.0, 13.0));
+        assert_eq!(a - b, Point::new(0.5, 6.0, -7.0));
+        assert_eq!(a + b - b, a);
+        assert_eq!(a + (10.0, 0.0, -3.0), Point::new(11.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn mapping_points_and_colors() {
+        let mut map_items: MapItems = "P 1.0, 2.0, 3.0, 10, 20, 30, 2, Bank
+L 0.0, 0.0, 0.0, 4.0, 5.0, 6.0, 1, 2, 3"
+            .parse()
+            .unwrap();
+
+        map_items.map_colors(|color| Color::new(color.r * 2, color.g * 2, color.b * 2));
+        map_items.map_points(|point| point + (1.0, 0.0, 0.0));
+
+        let MapItem::PointItem(point) = &map_items.items[0] else {
+            panic!("expected a point");
+        };
+        assert_eq!(point.color, Color::new(20, 40, 60));
+        assert_eq!((point.point.x, point.point.y), (2.0, 2.0));
+
+        let MapItem::LineItem(line) = &map_items.items[1] else {
+            panic!("expected a line");
+        };
+        assert_eq!(line.color, Color::new(2, 4, 6));
+        assert_eq!((line.from.x, line.to.x, line.to.z), (1.0, 5.0, 6.0));
+    }
+
+    #[test]
+    fn constructing_items() {
+        let point = PointItem::new(Point::new(1.0, 2.0, 3.0), Color::new(4, 5, 6), "Bank");
+        assert_eq!(
+            (point.point.z, point.color.b, point.label.as_str()),
+            (3.0, 6, "Bank")
+        );
+
+        let line = LineItem::new(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(3.0, 4.0, 0.0),
+            Color::new(0, 0, 0),
+        );
+        assert_eq!(line.length(), 5.0);
+    }
+
+    #[test]
+    fn parsing_quoted_labels() {
+        let label = |line| PointItem::try_from(line).unwrap().label;
+
+        assert_eq!(
+            label(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, "to, the, wastes""#),
+            "to, the, wastes"
+        );
+        assert_eq!(
+            label(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, " the ""Bank"" ""#),
+            r#" the "Bank" "#
+        );
+        assert_eq!(label(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, """#), "");
+        assert_eq!(
+            label(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, Bob's_"Inn""#),
+            r#"Bob's_"Inn""#
+        );
+
+        assert!(PointItem::try_from(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, "open"#).is_err());
+        assert!(PointItem::try_from(r#"P 1.0, 2.0, 3.0, 255, 0, 0, 3, "a" b"#).is_err());
+        assert!(PointItem::try_from(r#"P 1.0, 2.0, 3.0, 255, "0, 0", 3, a"#).is_err());
+
+        let map_items: MapItems = r#"P 1,2,3,255,0,0,3,"to, the, wastes""#.parse().unwrap();
+        assert_eq!(map_items.items.len(), 0);
+
+        let legacy = MapItems::from_reader(
+            r#"P 1,2,3,255,0,0,3,"to, the, wastes""#.as_bytes(),
+            &LoadOptions {
+                input_format: InputFormat::Legacy,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(
+            matches!(&legacy.items[0], MapItem::PointItem(point) if point.label == "to, the, wastes")
+        );
+    }
+
+    #[test]
+    fn combining_map_items() {
+        let mut map_items: MapItems = "P 1.0, 0.0, 0.0, 0, 0, 0, 2, First".parse().unwrap();
+        let second: MapItems = "P 2.0, 0.0, 0.0, 0, 0, 0, 2, Second".parse().unwrap();
+        let third: MapItems = "L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0".parse().unwrap();
+
+        map_items.append(second);
+        map_items.extend(third);
+
+        assert_eq!(map_items.items.len(), 3);
+        assert!(matches!(map_items.items[2], MapItem::LineItem(_)));
+
+        let lines: MapItems = map_items
+            .into_iter()
+            .filter(|item| matches!(item, MapItem::LineItem(_)))
+            .collect();
+        assert_eq!(lines.items.len(), 1);
+    }
+
+    #[test]
+    fn collection_access() {
+        let map_items: MapItems = "P 1.0, 0.0, 0.0, 0, 0, 0, 2, First
+L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0"
+            .parse()
+            .unwrap();
+
+        assert_eq!(map_items.len(), 2);
+        assert!(!map_items.is_empty());
+        assert!(matches!(&map_items[1], MapItem::LineItem(_)));
+        assert!(MapItems::from_iter([]).is_empty());
+    }
+
+    #[test]
+    fn retaining_items() {
+        let mut map_items: MapItems = "P 1.0, 0.0, 0.0, 0, 0, 0, 2, First
+L 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 255, 0, 0
+P 2.0, 0.0, 0.0, 0, 0, 0, 2, Second"
+            .parse()
+            .unwrap();
+
+        map_items.retain(|item| matches!(item, MapItem::PointItem(point) if point.point.x > 1.0));
+        assert_eq!(map_items.len(), 1);
+        assert!(matches!(&map_items[0], MapItem::PointItem(point) if point.label == "Second"));
+    }
+
+    #[test]
+    fn flattening_z() {
+        let mut map_items: MapItems = "P 1.0, 2.0, 3.0, 0, 0, 0, 2, A
+L 1.0, 2.0, -3.0, 4.0, 5.0, 6.0, 0, 0, 0"
+            .parse()
+            .unwrap();
+
+        map_items.flatten_z();
+
+        assert!(matches!(&map_items.items[0], MapItem::PointItem(p) if p.point.z == 0.0));
+        assert!(
+            matches!(&map_items.items[1], MapItem::LineItem(l) if l.from.z == 0.0 && l.to.z == 0.0)
+        );
+    }
+
+    #[test]
+    fn parsing_colors() {
+        let rgb = |s: &str| s.parse::<Color>().map(|c| (c.r, c.g, c.b)).ok();
+
+        assert_eq!(rgb("#ff8000"), Some((255, 128, 0)));
+        assert_eq!(rgb("10, 20,30"), Some((10, 20, 30)));
+        assert_eq!(rgb("rgb(10, 20, 30)"), Some((10, 20, 30)));
+        assert_eq!(rgb("#ff80"), None);
+        assert_eq!(rgb("1,2"), None);
+        assert_eq!(rgb("rgb(1,2)"), None);
+        assert_eq!(rgb("300,0,0"), None);
+    }
+
+    #[test]
+    fn hsv_colors() {
+        let rgb = |color: Color| (color.r, color.g, color.b);
+
+        assert_eq!(rgb(Color::from_hsv(0.0, 1.0, 1.0)), (255, 0, 0));
+        assert_eq!(rgb(Color::from_hsv(120.0, 1.0, 1.0)), (0, 255, 0));
+        assert_eq!(rgb(Color::from_hsv(600.0, 1.0, 0.5)), (0, 0, 128));
+        assert_eq!(rgb(Color::from_hsv(30.0, 0.0, 1.0)), (255, 255, 255));
+    }
+
+    #[test]
+    fn contrasting_colors() {
+        use crate::map_items::Color;
+
+        let yellow = Color {
+            r: 255,
+            g: 255,
+            b: 0,
+        };
+        let navy = Color { r: 0, g: 0, b: 128 };
+
+        assert_eq!(yellow.luminance(), 0.299 * 255.0 + 0.587 * 255.0);
+        assert_eq!(yellow.contrasting().r, 0);
+        assert_eq!(navy.contrasting().r, 255);
     }
 }
@@ -0,0 +1,386 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::map_items::{LineItem, MapItem, MapItems, Point, PointItem};
+
+/// How far from exactly colinear, relative to the lengths of the two
+/// segments, a pair may be and still be merged. Only there to absorb float
+/// rounding, not to simplify.
+const COLINEAR_EPSILON: f32 = 1e-6;
+
+/// How far apart two coordinates may be, in each of X, Y and Z, and still
+/// count as the same for `MapItems::dedup`. Only there to absorb the
+/// rounding of files written by different tools.
+pub const DEDUP_EPSILON: f32 = 1e-3;
+
+/// Which way `MapItems::snap_to_grid` moves a coordinate onto the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundMode {
+    /// To the closest grid line.
+    #[default]
+    Nearest,
+    /// To the grid line below, so everything lands on the corner of the
+    /// cell it's in.
+    Floor,
+    /// To the grid line above.
+    Ceil,
+}
+
+impl RoundMode {
+    /// `value` moved onto a multiple of `cell_size`.
+    pub fn snap(self, value: f32, cell_size: f32) -> f32 {
+        let cells = value / cell_size;
+        let cells = match self {
+            RoundMode::Nearest => cells.round(),
+            RoundMode::Floor => cells.floor(),
+            RoundMode::Ceil => cells.ceil(),
+        };
+
+        // Adding zero turns a -0 into 0, so it doesn't print as "-0".
+        cells * cell_size + 0.0
+    }
+}
+
+impl FromStr for RoundMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(RoundMode::Nearest),
+            "floor" => Ok(RoundMode::Floor),
+            "ceil" => Ok(RoundMode::Ceil),
+            _ => Err(anyhow!("Unknown rounding mode {}", s)),
+        }
+    }
+}
+
+impl MapItems {
+    /// Moves every X and Y onto a grid of `cell_size` units, rounding the way
+    /// `mode` says, to line the map up with a tile grid. Z is left alone.
+    /// Lines whose ends land on the same spot are kept.
+    pub fn snap_to_grid(&mut self, cell_size: f32, mode: RoundMode) {
+        self.map_points(|point| {
+            Point::new(
+                mode.snap(point.x, cell_size),
+                mode.snap(point.y, cell_size),
+                point.z,
+            )
+        });
+    }
+
+    /// Merges runs of consecutive lines that continue each other exactly: the
+    /// next line starts where the previous one ends, has the same color and
+    /// width, and heads in the same direction. The drawn map is unchanged,
+    /// with fewer items.
+    pub fn merge_colinear(&mut self) {
+        let mut merged: Vec<MapItem> = Vec::with_capacity(self.items.len());
+
+        for item in self.items.drain(..) {
+            if let (Some(MapItem::LineItem(previous)), MapItem::LineItem(line)) =
+                (merged.last_mut(), &item)
+            {
+                if continues(previous, line) {
+                    previous.to = line.to;
+                    continue;
+                }
+            }
+            merged.push(item);
+        }
+
+        self.items = merged;
+    }
+
+    /// Removes the points and lines that repeat an earlier one, as when
+    /// several files for the same zone are loaded together. Points repeat
+    /// with the same color, label, sub-label and type, and lines with the
+    /// same color and width, at coordinates within `DEDUP_EPSILON`. A line
+    /// from A to B repeats one from B to A. The first of each is kept where
+    /// it was, and texts and raw lines are left alone. Returns how many items
+    /// were removed.
+    pub fn dedup(&mut self) -> usize {
+        let cube = |point: &Point| {
+            [point.x, point.y, point.z].map(|value| (value / DEDUP_EPSILON).floor() as i64)
+        };
+        // The indexes of the kept points and lines, by the cube of each of
+        // their coordinates.
+        let mut kept: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut duplicates = vec![false; self.items.len()];
+
+        for (index, item) in self.items.iter().enumerate() {
+            let points = match item {
+                MapItem::PointItem(point) => vec![point.point],
+                MapItem::LineItem(line) => vec![line.from, line.to],
+                MapItem::TextItem(_) | MapItem::Raw(_) => continue,
+            };
+
+            // Any item this repeats has a coordinate near the first one.
+            let [x, y, z] = cube(&points[0]);
+            let mut near = (-1..=1).flat_map(|dx| {
+                (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz]))
+            });
+            duplicates[index] = near.any(|near| {
+                kept.get(&near).is_some_and(|indexes| {
+                    indexes
+                        .iter()
+                        .any(|&other| repeats(item, &self.items[other]))
+                })
+            });
+
+            if !duplicates[index] {
+                for point in points.iter() {
+                    let indexes = kept.entry(cube(point)).or_default();
+                    if indexes.last() != Some(&index) {
+                        indexes.push(index);
+                    }
+                }
+            }
+        }
+
+        let removed = duplicates.iter().filter(|&&duplicate| duplicate).count();
+        let mut duplicates = duplicates.into_iter();
+        self.items.retain(|_| !duplicates.next().unwrap_or(false));
+
+        removed
+    }
+
+    /// The indexes of each run of two or more consecutive lines that connect:
+    /// each starts exactly where the previous one ends, in the same color and
+    /// width, as a road or river drawn one segment at a time. Unlike
+    /// `merge_colinear`, the direction may change.
+    pub fn line_chains(&self) -> Vec<Vec<usize>> {
+        let mut chains: Vec<Vec<usize>> = Vec::new();
+        let mut chain: Vec<usize> = Vec::new();
+        let mut previous: Option<&LineItem> = None;
+
+        for (index, item) in self.items.iter().enumerate() {
+            let line = match item {
+                MapItem::LineItem(line) => Some(line),
+                _ => None,
+            };
+            let connected = matches!((previous, line), (Some(previous), Some(line)) if connects(previous, line));
+            if !connected && chain.len() > 1 {
+                chains.push(std::mem::take(&mut chain));
+            } else if !connected {
+                chain.clear();
+            }
+            if line.is_some() {
+                chain.push(index);
+            }
+            previous = line;
+        }
+        if chain.len() > 1 {
+            chains.push(chain);
+        }
+
+        chains
+    }
+}
+
+/// Whether `item` is the same point or line as `other`, as `MapItems::dedup`
+/// compares them.
+fn repeats(item: &MapItem, other: &MapItem) -> bool {
+    match (item, other) {
+        (MapItem::PointItem(a), MapItem::PointItem(b)) => repeats_point(a, b),
+        (MapItem::LineItem(a), MapItem::LineItem(b)) => {
+            a.color == b.color
+                && a.width.map(f32::to_bits) == b.width.map(f32::to_bits)
+                && ((near_point(&a.from, &b.from) && near_point(&a.to, &b.to))
+                    || (near_point(&a.from, &b.to) && near_point(&a.to, &b.from)))
+        }
+        _ => false,
+    }
+}
+
+fn repeats_point(a: &PointItem, b: &PointItem) -> bool {
+    near_point(&a.point, &b.point)
+        && a.color == b.color
+        && a.label == b.label
+        && a.sub_label == b.sub_label
+        && a.point_type == b.point_type
+}
+
+fn near_point(a: &Point, b: &Point) -> bool {
+    (a.x - b.x).abs() <= DEDUP_EPSILON
+        && (a.y - b.y).abs() <= DEDUP_EPSILON
+        && (a.z - b.z).abs() <= DEDUP_EPSILON
+}
+
+/// Whether `next` starts at the end of `line`, in the same color and width.
+fn connects(line: &LineItem, next: &LineItem) -> bool {
+    same_point(&line.to, &next.from) && line.color == next.color && line.width == next.width
+}
+
+/// Whether `next` connects to `line` and carries on in the same direction.
+fn continues(line: &LineItem, next: &LineItem) -> bool {
+    if !connects(line, next) {
+        return false;
+    }
+
+    let a = direction(&line.from, &line.to);
+    let b = direction(&next.from, &next.to);
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let scale = norm(a) * norm(b);
+
+    scale > 0.0 && dot > 0.0 && norm(cross) <= COLINEAR_EPSILON * scale
+}
+
+fn direction(from: &Point, to: &Point) -> [f32; 3] {
+    [to.x - from.x, to.y - from.y, to.z - from.z]
+}
+
+fn norm(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+fn same_point(a: &Point, b: &Point) -> bool {
+    a.x == b.x && a.y == b.y && a.z == b.z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoundMode;
+    use crate::map_items::{LoadOptions, MapItem, MapItems};
+
+    fn line_ends(map_items: &MapItems) -> Vec<(f32, f32, f32, f32)> {
+        map_items
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                MapItem::LineItem(line) => Some((line.from.x, line.from.y, line.to.x, line.to.y)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merging_colinear_chains() {
+        let mut map_items: MapItems = "L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 255, 0, 0\n\
+            L 1.0, 1.0, 0.0, 3.0, 3.0, 0.0, 255, 0, 0\n\
+            L 3.0, 3.0, 0.0, 4.0, 4.0, 0.0, 255, 0, 0\n\
+            L 4.0, 4.0, 0.0, 5.0, 4.0, 0.0, 255, 0, 0\n\
+            L 5.0, 4.0, 0.0, 6.0, 4.0, 0.0, 0, 0, 255\n\
+            L 6.0, 4.0, 0.0, 5.5, 4.0, 0.0, 0, 0, 255\n"
+            .parse()
+            .unwrap();
+
+        map_items.merge_colinear();
+        assert_eq!(
+            line_ends(&map_items),
+            vec![
+                (0.0, 0.0, 4.0, 4.0),
+                (4.0, 4.0, 5.0, 4.0),
+                (5.0, 4.0, 6.0, 4.0),
+                (6.0, 4.0, 5.5, 4.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn finding_line_chains() {
+        let map_items: MapItems = "L 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 255, 0, 0
+L 1.0, 1.0, 0.0, 3.0, 0.0, 0.0, 255, 0, 0
+L 3.0, 0.0, 0.0, 4.0, 4.0, 0.0, 255, 0, 0
+L 4.0, 4.0, 0.0, 5.0, 4.0, 0.0, 0, 0, 255
+P 5.0, 4.0, 0.0, 0, 0, 0, 2, Bank
+L 5.0, 4.0, 0.0, 6.0, 4.0, 0.0, 0, 0, 255
+L 6.0, 4.0, 0.0, 7.0, 5.0, 0.0, 0, 0, 255
+L 9.0, 9.0, 0.0, 9.0, 8.0, 0.0, 0, 0, 255"
+            .parse()
+            .unwrap();
+
+        assert_eq!(map_items.line_chains(), vec![vec![0, 1, 2], vec![5, 6]]);
+    }
+
+    #[test]
+    fn keeping_separated_segments() {
+        let mut map_items: MapItems = "L 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 255, 0, 0\n\
+            P 5.0, 5.0, 0.0, 0, 0, 0, 2, Bank\n\
+            L 1.0, 0.0, 0.0, 2.0, 0.0, 0.0, 255, 0, 0\n\
+            L 2.5, 0.0, 0.0, 3.0, 0.0, 0.0, 255, 0, 0\n"
+            .parse()
+            .unwrap();
+
+        map_items.merge_colinear();
+        assert_eq!(map_items.len(), 4);
+    }
+
+    #[test]
+    fn removing_repeated_items() {
+        let mut map_items: MapItems = "L 0.0, 0.0, 0.0, 10.0, 5.0, 1.0, 255, 0, 0\n\
+            P 3.0, 4.0, 0.0, 0, 0, 0, 2, Bank\n\
+            L 10.0, 5.0, 1.0, 0.0, 0.0, 0.0, 255, 0, 0\n\
+            L 0.0005, 0.0, 0.0, 10.0, 5.0, 1.0, 255, 0, 0\n\
+            L 0.0, 0.0, 0.0, 10.0, 5.0, 1.0, 0, 0, 255\n\
+            L 0.0, 0.0, 0.0, 10.0, 5.0, 1.0, 255, 0, 0, 2\n\
+            L 0.1, 0.0, 0.0, 10.0, 5.0, 1.0, 255, 0, 0\n\
+            P 3.0, 4.0004, 0.0, 0, 0, 0, 2, Bank\n\
+            P 3.0, 4.0, 0.0, 0, 0, 0, 2, Bank_2\n\
+            P 3.0, 4.0, 0.0, 0, 0, 0, 3, Bank\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(map_items.dedup(), 3);
+        assert_eq!(
+            line_ends(&map_items),
+            vec![
+                (0.0, 0.0, 10.0, 5.0),
+                (0.0, 0.0, 10.0, 5.0),
+                (0.0, 0.0, 10.0, 5.0),
+                (0.1, 0.0, 10.0, 5.0),
+            ]
+        );
+        assert_eq!(map_items.len(), 7);
+        assert_eq!(map_items.dedup(), 0);
+    }
+
+    #[test]
+    fn removing_a_file_loaded_twice() {
+        let path =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("maps/City/Akanon/akanon.txt");
+        let options = LoadOptions::default();
+        let mut once = MapItems::from_file(&path, &options).unwrap();
+        let mut twice = MapItems::from_files([path.as_path(), path.as_path()], &options).unwrap();
+
+        let loaded = once.len();
+        let repeated_within_the_file = once.dedup();
+        assert_eq!(twice.dedup(), loaded + repeated_within_the_file);
+        assert_eq!(twice.items, once.items);
+    }
+
+    #[test]
+    fn snapping_to_a_grid() {
+        let snapped = |mode| {
+            let mut map_items: MapItems = "P 12.0, -3.0, 7.5, 0, 0, 0, 1, A\n\
+                L 26.0, 30.0, 1.0, 40.0, 10.0, 1.0, 255, 0, 0\n"
+                .parse()
+                .unwrap();
+            map_items.snap_to_grid(10.0, mode);
+
+            let point = match &map_items.items[0] {
+                MapItem::PointItem(point) => point.point,
+                _ => unreachable!(),
+            };
+            ((point.x, point.y, point.z), line_ends(&map_items)[0])
+        };
+
+        assert_eq!(
+            snapped(RoundMode::Nearest),
+            ((10.0, 0.0, 7.5), (30.0, 30.0, 40.0, 10.0))
+        );
+        assert_eq!(
+            snapped(RoundMode::Floor),
+            ((10.0, -10.0, 7.5), (20.0, 30.0, 40.0, 10.0))
+        );
+        assert_eq!(
+            snapped(RoundMode::Ceil),
+            ((20.0, 0.0, 7.5), (30.0, 30.0, 40.0, 10.0))
+        );
+        assert!(RoundMode::Nearest.snap(-3.0, 10.0).is_sign_positive());
+    }
+}